@@ -1,21 +1,42 @@
 -- Clear old data
 DELETE FROM notes;
+DELETE FROM code_versions;
 DELETE FROM codes;
 DELETE FROM attributes;
 DELETE FROM logs;
 DELETE FROM sqlite_sequence WHERE name IN ('notes', 'codes', 'attributes', 'logs');
 
 -- Sample codes
-INSERT INTO codes (name, capabilities, script) VALUES
+INSERT INTO codes (name, capabilities, script, attribute_schema) VALUES
   ('simple_done', '["SysLog", { "GetAttribute": "Own" } , { "SetAttribute": "Own" } ]',
-  CAST(readfile('simple_todo.lua') AS TEXT)),
+  CAST(readfile('simple_todo.lua') AS TEXT),
+  '[{ "name": "done", "value_type": "Date" }]'),
   ('create_child', '["SysLog", { "CreateChild": "Own" }]',
-  CAST(readfile('create_child.lua') AS TEXT));
+  CAST(readfile('create_child.lua') AS TEXT), '[]');
+UPDATE codes SET tests = '[
+  { "name": "marks as done", "action": "done",
+    "input": { "Date": "2025-01-01" }, "result": "Note marked as done",
+    "expected_attributes": { "done": { "Date": "2025-01-01" } } },
+  { "name": "no form once done", "action": "forms",
+    "attributes": { "done": { "Date": "2025-01-01" } }, "result": {} }
+]' WHERE name = 'simple_done';
+UPDATE codes SET tests = '[
+  { "name": "creates a child", "action": "create",
+    "input": { "UInt": 2 }, "result": "Child created" }
+]' WHERE name = 'create_child';
+INSERT INTO code_versions
+  (code_name, version, author, message, capabilities, script,
+   attribute_schema, completion_attribute)
+SELECT name, 1, 'admin', 'Created', capabilities, script, attribute_schema,
+       completion_attribute
+FROM codes;
 
--- Root notes
-INSERT INTO notes (title, description, code_name) VALUES
-  ('Main Project', 'Top-level project note', 'simple_done'),
-  ('Inbox', 'Temporary tasks and notes', 'create_child');
+-- Root note and its children
+INSERT INTO notes (id, parent_id, title, description, position) VALUES
+  (0, NULL, 'Home', '', 0);
+INSERT INTO notes (parent_id, title, description, code_name) VALUES
+  (0, 'Main Project', 'Top-level project note', 'simple_done'),
+  (0, 'Inbox', 'Temporary tasks and notes', 'create_child');
 
 -- Sub-notes
 INSERT INTO notes (parent_id, title, description, code_name) VALUES
@@ -24,11 +45,11 @@ INSERT INTO notes (parent_id, title, description, code_name) VALUES
   (2, 'Buy groceries', 'Milk, eggs, etc.', NULL);
 
 -- Attributes
-INSERT INTO attributes (note_id, key, value) VALUES
-  (1, 'owner', 'Alice'),
-  (3, 'status', 'pending'),
-  (4, 'deadline', '2025-07-01'),
-  (5, 'tag', 'personal');
+INSERT INTO attributes (note_id, key, value, value_type) VALUES
+  (1, 'owner', 'Alice', 'String'),
+  (3, 'status', 'pending', 'String'),
+  (4, 'due', '2025-07-01', 'Date'),
+  (5, 'tag', 'personal', 'String');
 
 -- Logs (some with dummy binary blobs)
 INSERT INTO logs (note_id, kind, message, blob_data) VALUES
@@ -38,6 +38,6 @@ done = coroutine.create(function (value)
   id = coroutine.yield("GetId")
   coroutine.yield({ SysLog = "Marking note as done" })
   local date = value["Date"]
-  coroutine.yield({ SetAttribute = { id = id, key = "done", value = date } })
+  coroutine.yield({ SetAttribute = { id = id, key = "done", value = { Date = date } } })
   return { Result = "Note marked as done" }
 end)
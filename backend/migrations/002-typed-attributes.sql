@@ -0,0 +1,8 @@
+-- Every attribute value carries the type it was written with
+ALTER TABLE attributes ADD COLUMN value_type TEXT NOT NULL DEFAULT 'String';
+
+-- The built-in done flow always stored dates
+UPDATE attributes SET value_type = 'Date'
+WHERE key = 'done' AND date(value) = value;
+
+INSERT OR REPLACE INTO meta (key, value) VALUES ('schema_version', '2');
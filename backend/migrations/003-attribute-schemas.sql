@@ -0,0 +1,4 @@
+-- Attributes a code relies on: JSON list of names, types, required, defaults
+ALTER TABLE codes ADD COLUMN attribute_schema TEXT NOT NULL DEFAULT '[]';
+
+INSERT OR REPLACE INTO meta (key, value) VALUES ('schema_version', '3');
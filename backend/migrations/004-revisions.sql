@@ -0,0 +1,16 @@
+-- Before/after values of every change to a note's fields or attributes.
+-- For attributes (field = 'attribute') values are typed JSON, otherwise
+-- plain text. NULL means the value was absent.
+CREATE TABLE revisions (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  note_id INTEGER NOT NULL,
+  created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  field TEXT NOT NULL,        -- "title", "description", "code_name", "attribute"
+  key TEXT,                   -- attribute key, if field = 'attribute'
+  before TEXT,
+  after TEXT,
+
+  FOREIGN KEY (note_id) REFERENCES notes(id) ON DELETE CASCADE
+);
+
+INSERT OR REPLACE INTO meta (key, value) VALUES ('schema_version', '4');
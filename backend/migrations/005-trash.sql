@@ -0,0 +1,6 @@
+-- Deleted notes stay in the trash until purged. A whole subtree is trashed
+-- together: every note in it records the root it was trashed with.
+ALTER TABLE notes ADD COLUMN deleted_at TEXT;
+ALTER TABLE notes ADD COLUMN trashed_with INTEGER;
+
+INSERT OR REPLACE INTO meta (key, value) VALUES ('schema_version', '5');
@@ -0,0 +1,5 @@
+-- Manual order of siblings, lowest first. Existing notes keep creation order.
+ALTER TABLE notes ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+UPDATE notes SET position = id;
+
+INSERT OR REPLACE INTO meta (key, value) VALUES ('schema_version', '6');
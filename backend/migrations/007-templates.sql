@@ -0,0 +1,5 @@
+-- Notes acting as templates list their parameters as a JSON array of
+-- names, NULL for ordinary notes
+ALTER TABLE notes ADD COLUMN template_params TEXT;
+
+INSERT OR REPLACE INTO meta (key, value) VALUES ('schema_version', '7');
@@ -0,0 +1,6 @@
+-- A single permanent root note (id 0) replaces the list of top-level notes
+INSERT INTO notes (id, parent_id, title, description, position)
+VALUES (0, NULL, 'Home', '', 0);
+UPDATE notes SET parent_id = 0 WHERE parent_id IS NULL AND id != 0;
+
+INSERT OR REPLACE INTO meta (key, value) VALUES ('schema_version', '8');
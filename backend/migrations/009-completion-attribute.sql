@@ -0,0 +1,18 @@
+-- Attribute whose presence marks a note running the code as done
+ALTER TABLE codes
+  ADD COLUMN completion_attribute TEXT NOT NULL DEFAULT 'done';
+
+-- Completion state of every note: its completion attribute (`done` for
+-- notes without code) is set and is not a false Bool
+CREATE VIEW note_status AS
+SELECT n.id AS note_id,
+       EXISTS (
+         SELECT 1 FROM attributes a
+         WHERE a.note_id = n.id
+           AND a.key = COALESCE(c.completion_attribute, 'done')
+           AND NOT (a.value_type = 'Bool' AND a.value = 'false')
+       ) AS done
+FROM notes n
+LEFT JOIN codes c ON c.name = n.code_name;
+
+INSERT OR REPLACE INTO meta (key, value) VALUES ('schema_version', '9');
@@ -0,0 +1,9 @@
+-- Form run when a note using the code is moved on a board grouping by
+-- board_action_key. Without one, or on boards grouping by another key,
+-- moving a card sets the grouping attribute directly.
+ALTER TABLE codes
+  ADD COLUMN board_action TEXT;
+ALTER TABLE codes
+  ADD COLUMN board_action_key TEXT;
+
+INSERT OR REPLACE INTO meta (key, value) VALUES ('schema_version', '10');
@@ -0,0 +1,46 @@
+-- Every state a code has been in. `codes` keeps the current one, its
+-- `version` pointing here. Versions are never modified: rolling back
+-- records a new version with older content.
+CREATE TABLE code_versions (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  code_name TEXT NOT NULL,
+  version INTEGER NOT NULL,   -- 1, 2, ... for each code
+  created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  author TEXT NOT NULL,
+  message TEXT NOT NULL,
+  capabilities TEXT NOT NULL,
+  script TEXT NOT NULL,
+  attribute_schema TEXT NOT NULL,
+  completion_attribute TEXT NOT NULL,
+  board_action TEXT,
+  board_action_key TEXT,
+
+  UNIQUE (code_name, version),
+  FOREIGN KEY (code_name) REFERENCES codes(name)
+    ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE TRIGGER code_versions_immutable
+BEFORE UPDATE OF version, created_at, author, message, capabilities, script,
+  attribute_schema, completion_attribute, board_action, board_action_key
+  ON code_versions
+BEGIN
+  SELECT RAISE(ABORT, 'code versions cannot be modified');
+END;
+
+ALTER TABLE codes
+  ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+
+INSERT INTO code_versions
+  (code_name, version, author, message, capabilities, script,
+   attribute_schema, completion_attribute, board_action, board_action_key)
+SELECT name, 1, 'admin', 'Version before history was kept', capabilities,
+       script, attribute_schema, completion_attribute, board_action,
+       board_action_key
+FROM codes;
+
+-- Version of the code that ran, for logs written by executions
+ALTER TABLE logs
+  ADD COLUMN code_version INTEGER;
+
+INSERT OR REPLACE INTO meta (key, value) VALUES ('schema_version', '11');
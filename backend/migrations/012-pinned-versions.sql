@@ -0,0 +1,21 @@
+-- Version of its code a note runs. NULL follows the current version.
+ALTER TABLE notes
+  ADD COLUMN code_version INTEGER;
+
+-- Pinned notes use the completion attribute of their version
+DROP VIEW note_status;
+CREATE VIEW note_status AS
+SELECT n.id AS note_id,
+       EXISTS (
+         SELECT 1 FROM attributes a
+         WHERE a.note_id = n.id
+           AND a.key = COALESCE(v.completion_attribute,
+                                c.completion_attribute, 'done')
+           AND NOT (a.value_type = 'Bool' AND a.value = 'false')
+       ) AS done
+FROM notes n
+LEFT JOIN codes c ON c.name = n.code_name
+LEFT JOIN code_versions v
+  ON v.code_name = n.code_name AND v.version = n.code_version;
+
+INSERT OR REPLACE INTO meta (key, value) VALUES ('schema_version', '12');
@@ -0,0 +1,6 @@
+-- Test cases of the code as a JSON list, each one run on a scratch
+-- database with a single note using the code.
+ALTER TABLE codes
+  ADD COLUMN tests TEXT NOT NULL DEFAULT '[]';
+
+INSERT OR REPLACE INTO meta (key, value) VALUES ('schema_version', '13');
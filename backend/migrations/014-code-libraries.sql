@@ -0,0 +1,8 @@
+-- Library codes are imported by other codes, which get the table the
+-- library returns. Notes cannot use them. Codes already imported by
+-- other codes are flagged as libraries once this has run, see
+-- mark_imported_libraries.
+ALTER TABLE codes
+  ADD COLUMN library BOOLEAN NOT NULL DEFAULT 0;
+
+INSERT OR REPLACE INTO meta (key, value) VALUES ('schema_version', '14');
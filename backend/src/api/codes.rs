@@ -1,6 +1,19 @@
-use crate::db_manage::codes::{create_code, edit_code};
+use crate::db_manage::code_tests::{
+    get_code_tests, run_code_tests, set_code_tests,
+};
+use crate::db_manage::code_versions::{rollback_code, upgrade_note};
+use crate::db_manage::codes::{
+    create_code, delete_code, edit_code, get_code_by_name, parse_board_action,
+    rename_code, Code, DEFAULT_ATTRIBUTE_SCHEMA, DEFAULT_COMPLETION_ATTRIBUTE,
+};
+use crate::db_manage::dependencies::check_capabilities;
+use crate::db_manage::errors::DbError;
+use crate::db_manage::simulation::trace_code;
+use crate::db_manage::validation::CodeProblem;
 use crate::frontend::codes::rocket_uri_macro_view_code;
+use crate::frontend::view::{MyFlash, MyFlashType, View, ViewState};
 use chrono::NaiveDate;
+use maud::html;
 use rocket::form::Form;
 use rocket::response::{Flash, Redirect};
 use rocket_db_pools::Connection;
@@ -10,12 +23,60 @@ use crate::Db;
 use rocket::uri;
 use rocket::{post, FromForm};
 use serde::{Deserialize, Serialize, Serializer};
+use serde_json::Value as JsonValue;
 
 #[derive(FromForm)]
 pub struct NewCodeForm {
     pub name: String,
     pub capabilities: String,
     pub script: String,
+    // Optional for clients predating schemas: defaults for a new code,
+    // the current values when editing
+    pub attribute_schema: Option<String>,
+    pub completion_attribute: Option<String>,
+    pub board_action: Option<String>,
+    pub board_action_key: Option<String>,
+    pub library: bool,
+    pub author: String,
+}
+
+/// The submitted code form again with what went wrong, so that no edit
+/// is lost and problems can be shown next to their field.
+fn code_form_error(
+    e: DbError,
+    form: impl FnOnce(Vec<CodeProblem>) -> ViewState,
+) -> View {
+    let (message, problems) = match e {
+        DbError::InvalidCode { problems } => (
+            "The code was not saved, see the problems below.".into(),
+            problems,
+        ),
+        e => (format!("Failed to save code: {e}"), vec![]),
+    };
+    View {
+        state: form(problems),
+        flash: vec![MyFlash {
+            flash_type: MyFlashType::Error,
+            message: html! { (message) },
+        }],
+    }
+}
+
+/// A success flash, turned into a warning when the saved capabilities do
+/// not match the commands the script yields.
+async fn saved_code_flash(
+    db: &mut Connection<Db>,
+    capabilities: &str,
+    script: &str,
+    redirect: Redirect,
+    message: String,
+) -> Flash<Redirect> {
+    match check_capabilities(db, capabilities, script).await {
+        Ok(report) if !report.is_empty() => {
+            Flash::warning(redirect, format!("{message} {report}"))
+        }
+        _ => Flash::success(redirect, message),
+    }
 }
 
 #[post("/codes/new", data = "<form>")]
@@ -23,23 +84,64 @@ pub async fn create_code_submit(
     _auth: Authenticated,
     mut db: Connection<Db>,
     form: Form<NewCodeForm>,
-) -> Result<Flash<Redirect>, Flash<Redirect>> {
+) -> Result<Flash<Redirect>, View> {
     let NewCodeForm {
         name,
         capabilities,
         script,
+        attribute_schema,
+        completion_attribute,
+        board_action,
+        board_action_key,
+        library,
+        author,
     } = form.into_inner();
+    let draft = Code {
+        name,
+        capabilities,
+        script,
+        attribute_schema: attribute_schema
+            .unwrap_or_else(|| DEFAULT_ATTRIBUTE_SCHEMA.to_string()),
+        completion_attribute: completion_attribute
+            .unwrap_or_else(|| DEFAULT_COMPLETION_ATTRIBUTE.to_string()),
+        board_action,
+        board_action_key,
+        version: 0,
+        library,
+    };
 
     let note: Option<String> = None;
-    match create_code(&mut db, name, capabilities, script).await {
-        Ok(name) => Ok(Flash::success(
-            Redirect::to(uri!(view_code(name = name.clone(), note = note))), // TODO insert a redirect?
+    match create_code(
+        &mut db,
+        draft.name.clone(),
+        draft.capabilities.clone(),
+        draft.script.clone(),
+        draft.attribute_schema.clone(),
+        draft.completion_attribute.clone(),
+        parse_board_action(
+            draft.board_action_key.clone(),
+            draft.board_action.clone(),
+        ),
+        draft.library,
+        &author,
+    )
+    .await
+    {
+        Ok(name) => Ok(saved_code_flash(
+            &mut db,
+            &draft.capabilities,
+            &draft.script,
+            Redirect::to(uri!(view_code(
+                name = name.clone(),
+                note = note,
+                version = _
+            ))), // TODO insert a redirect?
             format!("Code {name} created."),
-        )),
-        Err(e) => Err(Flash::error(
-            Redirect::to("/codes/new"), // TODO use uri! macro
-            format!("Failed to create code: {e}"),
-        )),
+        )
+        .await),
+        Err(e) => Err(code_form_error(e, |problems| {
+            ViewState::CodeNew(Some(draft), problems)
+        })),
     }
 }
 
@@ -48,6 +150,16 @@ pub struct EditCodeForm {
     pub name: String, // Code to be updated
     pub capabilities: String,
     pub script: String,
+    // Optional for clients predating schemas: defaults for a new code,
+    // the current values when editing
+    pub attribute_schema: Option<String>,
+    pub completion_attribute: Option<String>,
+    pub board_action: Option<String>,
+    pub board_action_key: Option<String>,
+    pub library: bool,
+    pub author: String,
+    // What changed, recorded with the new version
+    pub message: String,
 }
 
 #[post("/codes/edit?<next>", data = "<form>")]
@@ -56,27 +168,307 @@ pub async fn edit_code_submit(
     mut db: Connection<Db>,
     form: Form<EditCodeForm>,
     next: Option<String>,
-) -> Result<Flash<Redirect>, Flash<Redirect>> {
+) -> Result<Flash<Redirect>, View> {
     let EditCodeForm {
         name,
         capabilities,
         script,
+        attribute_schema,
+        completion_attribute,
+        board_action,
+        board_action_key,
+        library,
+        author,
+        message,
     } = form.into_inner();
-    match edit_code(&mut db, &name, &capabilities, &script).await {
-        Ok(_) => match next {
-            None => Ok(Flash::success(
-                Redirect::to("/"),
-                format!("Code {name} updated."),
-            )),
-            Some(s) => Ok(Flash::success(Redirect::to(s), "Code updated.")),
-        },
+    let current = get_code_by_name(&mut db, &name).await.ok().flatten();
+    let attribute_schema = attribute_schema
+        .or_else(|| current.as_ref().map(|c| c.attribute_schema.clone()))
+        .unwrap_or_else(|| DEFAULT_ATTRIBUTE_SCHEMA.to_string());
+    let completion_attribute = completion_attribute
+        .or_else(|| current.map(|c| c.completion_attribute))
+        .unwrap_or_else(|| DEFAULT_COMPLETION_ATTRIBUTE.to_string());
+    let result = edit_code(
+        &mut db,
+        &name,
+        &capabilities,
+        &script,
+        &attribute_schema,
+        &completion_attribute,
+        parse_board_action(board_action_key.clone(), board_action.clone()),
+        library,
+        &author,
+        &message,
+    )
+    .await;
+    match result {
+        Ok(version) => {
+            let (redirect, message) = match next {
+                None => (
+                    Redirect::to("/"),
+                    format!("Code {name} updated to version {version}."),
+                ),
+                Some(s) => (
+                    Redirect::to(s),
+                    format!("Code updated to version {version}."),
+                ),
+            };
+            Ok(saved_code_flash(
+                &mut db,
+                &capabilities,
+                &script,
+                redirect,
+                message,
+            )
+            .await)
+        }
+        Err(e) => Err(code_form_error(e, |problems| {
+            let draft = Code {
+                name,
+                capabilities,
+                script,
+                attribute_schema,
+                completion_attribute,
+                board_action,
+                board_action_key,
+                version: 0,
+                library,
+            };
+            ViewState::CodeEdit(draft, next, problems)
+        })),
+    }
+}
+
+#[derive(FromForm)]
+pub struct RollbackCodeForm {
+    pub version: i64,
+    pub author: String,
+}
+
+#[post("/codes/<name>/rollback", data = "<form>")]
+pub async fn rollback_code_submit(
+    _auth: Authenticated,
+    mut db: Connection<Db>,
+    name: String,
+    form: Form<RollbackCodeForm>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    let note: Option<String> = None;
+    let target = uri!(view_code(name = name.clone(), note = note, version = _));
+    match rollback_code(&mut db, &name, form.version, &form.author).await {
+        Ok(version) => Ok(Flash::success(
+            Redirect::to(target),
+            format!(
+                "Code {name} rolled back to version {}, now version {version}.",
+                form.version
+            ),
+        )),
         Err(e) => Err(Flash::error(
-            Redirect::to(uri!("/")), // Optionally redirect elsewhere
-            format!("Failed to update code: {e}"),
+            Redirect::to(target),
+            format!("Failed to roll back code: {e}"),
         )),
     }
 }
 
+#[derive(FromForm)]
+pub struct UpgradeForm {
+    pub notes: Vec<i64>,
+}
+
+/// Upgrades each selected note on its own, so one failing migration does
+/// not hold back the others.
+#[post("/codes/<name>/upgrade", data = "<form>")]
+pub async fn upgrade_notes_submit(
+    _auth: Authenticated,
+    mut db: Connection<Db>,
+    name: String,
+    form: Form<UpgradeForm>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    let target =
+        uri!(crate::frontend::codes::upgrade_code(name = name.clone()));
+    let mut failures = vec![];
+    for id in &form.notes {
+        if let Err(e) = upgrade_note(&mut db, *id).await {
+            failures.push(format!("note {id}: {e}"));
+        }
+    }
+    let upgraded = form.notes.len() - failures.len();
+    if failures.is_empty() {
+        Ok(Flash::success(
+            Redirect::to(target),
+            format!("{upgraded} note(s) upgraded."),
+        ))
+    } else {
+        Err(Flash::error(
+            Redirect::to(target),
+            format!(
+                "{upgraded} note(s) upgraded, failed for {}",
+                failures.join("; ")
+            ),
+        ))
+    }
+}
+
+#[derive(FromForm)]
+pub struct RenameCodeForm {
+    pub new_name: String,
+    pub author: String,
+}
+
+#[post("/codes/<name>/rename", data = "<form>")]
+pub async fn rename_code_submit(
+    _auth: Authenticated,
+    mut db: Connection<Db>,
+    name: String,
+    form: Form<RenameCodeForm>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    let new_name = form.new_name.trim().to_string();
+    match rename_code(&mut db, &name, &new_name, &form.author).await {
+        Ok(importers) => Ok(Flash::success(
+            Redirect::to(uri!(view_code(
+                name = new_name.clone(),
+                note = None::<String>,
+                version = _
+            ))),
+            match importers.is_empty() {
+                true => format!("Code {name} renamed to {new_name}."),
+                false => format!(
+                    "Code {name} renamed to {new_name}, imports rewritten in {}.",
+                    importers.join(", ")
+                ),
+            },
+        )),
+        Err(e) => Err(Flash::error(
+            Redirect::to(uri!(view_code(
+                name = name.clone(),
+                note = None::<String>,
+                version = _
+            ))),
+            format!("Failed to rename code: {e}"),
+        )),
+    }
+}
+
+#[post("/codes/<name>/delete")]
+pub async fn delete_code_submit(
+    _auth: Authenticated,
+    mut db: Connection<Db>,
+    name: String,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    match delete_code(&mut db, &name).await {
+        Ok(unbound) => Ok(Flash::success(
+            Redirect::to(uri!(crate::frontend::codes::list_codes)),
+            format!(
+                "Code {name} deleted, {unbound} note(s) left without code."
+            ),
+        )),
+        Err(e) => Err(Flash::error(
+            Redirect::to(uri!(crate::frontend::codes::delete_code_confirm(
+                name = name.clone()
+            ))),
+            format!("Failed to delete code: {e}"),
+        )),
+    }
+}
+
+#[derive(FromForm)]
+pub struct CodeTestsForm {
+    pub tests: String,
+}
+
+#[post("/codes/<name>/tests", data = "<form>")]
+pub async fn save_code_tests_submit(
+    _auth: Authenticated,
+    mut db: Connection<Db>,
+    name: String,
+    form: Form<CodeTestsForm>,
+) -> Result<Flash<Redirect>, View> {
+    match set_code_tests(&mut db, &name, &form.tests).await {
+        Ok(()) => Ok(Flash::success(
+            Redirect::to(uri!(crate::frontend::codes::code_tests(
+                name = name.clone()
+            ))),
+            format!("Tests of {name} saved."),
+        )),
+        Err(e) => Err(View {
+            state: ViewState::CodeTests(name, form.into_inner().tests, None),
+            flash: vec![MyFlash {
+                flash_type: MyFlashType::Error,
+                message: html! { "Failed to save tests: " (e) },
+            }],
+        }),
+    }
+}
+
+/// Runs the saved test cases of a code, each on its own scratch database.
+#[post("/codes/<name>/tests/run")]
+pub async fn run_code_tests_submit(
+    _auth: Authenticated,
+    mut db: Connection<Db>,
+    name: String,
+) -> Result<View, Flash<Redirect>> {
+    let back = || {
+        Redirect::to(uri!(crate::frontend::codes::code_tests(
+            name = name.clone()
+        )))
+    };
+    let tests = get_code_tests(&mut db, &name).await.map_err(|e| {
+        Flash::error(back(), format!("Failed to get tests: {e}"))
+    })?;
+    let reports = run_code_tests(&mut db, &name).await.map_err(|e| {
+        Flash::error(back(), format!("Failed to run tests: {e}"))
+    })?;
+    let failed = reports.iter().filter(|r| !r.passed()).count();
+    let (flash_type, message) = match (reports.len(), failed) {
+        (0, _) => (MyFlashType::Info, "No tests to run.".to_string()),
+        (n, 0) => (MyFlashType::Success, format!("All {n} tests passed.")),
+        (n, f) => (MyFlashType::Error, format!("{f} of {n} tests failed.")),
+    };
+    Ok(View {
+        state: ViewState::CodeTests(name, tests, Some(reports)),
+        flash: vec![MyFlash {
+            flash_type,
+            message: html! { (message) },
+        }],
+    })
+}
+
+#[derive(FromForm)]
+pub struct TraceCodeForm {
+    pub note_id: i64,
+    pub entry_point: String,
+    // JSON given to the coroutine, as a form would send it
+    pub arguments: String,
+}
+
+#[post("/codes/<name>/trace", data = "<form>")]
+pub async fn trace_code_submit(
+    _auth: Authenticated,
+    mut db: Connection<Db>,
+    name: String,
+    form: Form<TraceCodeForm>,
+) -> Result<View, Flash<Redirect>> {
+    let back = || {
+        Redirect::to(uri!(view_code(
+            name = name.clone(),
+            note = None::<String>,
+            version = _
+        )))
+    };
+    let arguments = match form.arguments.trim() {
+        "" => JsonValue::Null,
+        text => serde_json::from_str(text)
+            .map_err(|e| Flash::error(back(), format!("Invalid input: {e}")))?,
+    };
+    let trace =
+        trace_code(&mut db, &name, form.note_id, &form.entry_point, arguments)
+            .await
+            .map_err(|e| Flash::error(back(), format!("Failed to run: {e}")))?;
+    Ok(View {
+        state: ViewState::CodeTrace(name, trace),
+        flash: vec![],
+    })
+}
+
 // struct StructType {
 //     fields: Vec<Action>
 // }
@@ -89,6 +481,7 @@ pub async fn edit_code_submit(
 pub enum FormType {
     UInt,
     Date,
+    Text,
     Empty,
     // Struct(StuctType)
     // Enum(EnumType)
@@ -127,5 +520,6 @@ impl Serialize for Date {
 pub enum Value {
     UInt(u64),
     Date(Date),
+    Text(String),
     Empty,
 }
@@ -1,4 +1,6 @@
+use crate::api::Authenticated;
 use crate::db_manage;
+use crate::db_manage::login::reset_calendar_token;
 use crate::db_manage::Db;
 use crate::utils::RateLimiter;
 
@@ -10,8 +12,8 @@ use rocket::form::Form;
 use rocket::http::{Cookie, CookieJar};
 use rocket::post;
 use rocket::response::{Flash, Redirect};
-use rocket::FromForm;
 use rocket::State;
+use rocket::{uri, FromForm};
 use rocket_db_pools::Connection;
 
 fn ok_or_redirect(next: Option<String>) -> Redirect {
@@ -77,3 +79,20 @@ pub async fn logout_submit(
         "You have been logged out.",
     ))
 }
+
+#[post("/calendar/token")]
+pub async fn reset_calendar_token_submit(
+    _auth: Authenticated,
+    mut db: Connection<Db>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    match reset_calendar_token(&mut db).await {
+        Ok(_) => Ok(Flash::success(
+            Redirect::to(uri!(crate::frontend::notes::agenda)),
+            "New calendar token created, previous feed links stopped working.",
+        )),
+        Err(e) => Err(Flash::error(
+            Redirect::to(uri!(crate::frontend::notes::agenda)),
+            format!("Failed to create calendar token: {e}"),
+        )),
+    }
+}
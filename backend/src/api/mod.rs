@@ -19,7 +19,7 @@ impl<'r> FromRequest<'r> for Authenticated {
     async fn from_request(req: &'r Request<'_>) -> Outcome<Self, ()> {
         let jar = req.cookies();
         if let Some(cookie) = jar.get_private("user_id") {
-            if cookie.value().to_string() == "admin" {
+            if cookie.value() == "admin" {
                 Outcome::Success(Authenticated)
             } else {
                 Outcome::Forward(Status::Unauthorized)
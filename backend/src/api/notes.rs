@@ -1,4 +1,6 @@
+use mlua::Lua;
 use rocket::form::Form;
+use rocket::http::{Cookie, CookieJar};
 use rocket::post;
 use rocket::response::{Flash, Redirect};
 use rocket::{uri, FromForm};
@@ -7,12 +9,26 @@ use sqlx::Acquire;
 use std::collections::HashMap;
 
 use crate::api::Authenticated;
-use crate::db_manage::attributes::{delete_attribute, set_attribute};
-use crate::db_manage::codes::{execute, get_forms, parse_fields};
-use crate::db_manage::notes::update_note;
-use crate::db_manage::{create_note, Db};
+use crate::db_manage::attributes::{
+    delete_attribute, get_attribute_type, set_attribute, AttributeType,
+    AttributeValue,
+};
+use crate::db_manage::board::move_card;
+use crate::db_manage::code_versions::pin_note;
+use crate::db_manage::codes::{execute_traced, get_forms, parse_fields};
+use crate::db_manage::logs::create_failure_log;
+use crate::db_manage::notes::{
+    copy_note, move_note, reorder_note, update_note,
+};
+use crate::db_manage::revisions::revert_to_revision;
+use crate::db_manage::simulation::simulate;
+use crate::db_manage::templates::{instantiate_template, set_template_params};
+use crate::db_manage::trace::Trace;
+use crate::db_manage::trash::{purge_note, restore_note};
+use crate::db_manage::{create_note, get_note, Db, Note, ROOT_NOTE_ID};
 use crate::frontend::notes::rocket_uri_macro_edit_note;
 use crate::frontend::notes::rocket_uri_macro_show_note;
+use crate::frontend::view::{View, ViewState};
 
 #[derive(FromForm)]
 pub struct CreateNoteForm {
@@ -50,11 +66,13 @@ pub async fn create_note_submit(
             "Note title cannot be empty.",
         ));
     }
+    // Notes created without a parent go under the root note
+    let parent_id = parent_id.unwrap_or(ROOT_NOTE_ID);
     let mut tx = db.begin().await.map_err(|e| {
         Flash::error(Redirect::to("/"), format!("Cannot begin tx: {e}."))
     })?;
     let note_id =
-        create_note(&mut *tx, parent_id, title, description, code_name)
+        create_note(&mut tx, parent_id, title, description, code_name)
             .await
             .map_err(|e| {
                 Flash::error(
@@ -116,12 +134,30 @@ pub async fn execute_action(
     let mut tx = db.begin().await.map_err(|e| {
         Flash::error(Redirect::to("/"), format!("Cannot begin tx: {e}."))
     })?;
-    let message = execute(&mut *tx, id, &form_container, &value).await.map_err(|e| {
-        Flash::error(
-            Redirect::to("/"),
-            format!("executing failed: id {id:?}, form {form_container:?}, value {:?}\n{e}", &value),
-        )
-    })?;
+    let mut trace = Trace::default();
+    let executed = execute_traced(
+        &mut tx,
+        &Lua::new(),
+        id,
+        form_container,
+        &value,
+        &mut trace,
+    )
+    .await;
+    let message = match executed {
+        Ok(message) => message,
+        Err(e) => {
+            drop(tx);
+            // Notes without code leave no trace
+            if trace.error.is_some() {
+                let _ = create_failure_log(&mut db, &trace).await;
+            }
+            return Err(Flash::error(
+                Redirect::to(uri!(show_note(id))),
+                format!("executing failed: id {id:?}, form {form_container:?}, value {:?}\n{e}", &value),
+            ));
+        }
+    };
     match tx.commit().await {
         Ok(_) => Ok(Flash::success(
             Redirect::to(uri!(show_note(id))),
@@ -134,6 +170,43 @@ pub async fn execute_action(
     }
 }
 
+/// Same form as [`execute_action`], but the action runs in a transaction
+/// that is rolled back and the page shows what it would have done.
+#[post("/notes/<id>/execute?simulate", data = "<form>")]
+pub async fn simulate_action(
+    _auth: Authenticated,
+    mut db: Connection<Db>,
+    id: i64,
+    form: Form<ExecuteForm>,
+) -> Result<View, Flash<Redirect>> {
+    let back = || Redirect::to(uri!(show_note(id)));
+    let note =
+        get_note(&mut db, id).await.ok().flatten().ok_or_else(|| {
+            Flash::error(back(), format!("note {id} not found"))
+        })?;
+    let forms = get_forms(&mut db, id).await.map_err(|e| {
+        Flash::error(back(), format!("could not get forms: {e}"))
+    })?;
+    let form_container = forms.get(&form.action_label).ok_or(Flash::error(
+        back(),
+        format!("action not found: {}", form.action_label),
+    ))?;
+    let action = &form_container.action;
+    let value = parse_fields(&action.form_type, &form.fields, &action.label)
+        .map_err(|e| Flash::error(back(), format!("parsing failed: {e}")))?;
+    let simulation = simulate(&mut db, id, form_container, &value)
+        .await
+        .map_err(|e| Flash::error(back(), format!("simulation failed: {e}")))?;
+    Ok(View {
+        state: ViewState::Simulation(
+            note,
+            form_container.title.clone(),
+            simulation,
+        ),
+        flash: vec![],
+    })
+}
+
 #[derive(FromForm)]
 pub struct EditNoteForm {
     pub title: String,
@@ -171,8 +244,18 @@ pub async fn edit_note_submit(
     }
 
     if let Some(map) = attributes {
-        for (key, value) in map {
-            if let Err(e) = set_attribute(&mut db, id, &key, &value).await {
+        for (key, raw) in map {
+            let result = match get_attribute_type(&mut db, id, &key).await {
+                Ok(value_type) => match AttributeValue::parse(value_type, &raw)
+                {
+                    Ok(value) => set_attribute(&mut db, id, &key, &value)
+                        .await
+                        .map_err(|e| e.to_string()),
+                    Err(e) => Err(e),
+                },
+                Err(e) => Err(e.to_string()),
+            };
+            if let Err(e) = result {
                 return Err(Flash::error(
                     Redirect::to(uri!(edit_note(id))),
                     format!("Failed to update attribute: {e}"),
@@ -209,6 +292,7 @@ pub async fn delete_attribute_submit(
 pub struct AttributeForm {
     pub key: String,
     pub value: String,
+    pub value_type: String,
 }
 
 #[post("/notes/<id>/attributes/add", data = "<form>")]
@@ -218,7 +302,21 @@ pub async fn update_or_add_attribute_submit(
     mut db: Connection<Db>,
     form: Form<AttributeForm>,
 ) -> Result<Flash<Redirect>, Flash<Redirect>> {
-    let AttributeForm { key, value } = form.into_inner();
+    let AttributeForm {
+        key,
+        value,
+        value_type,
+    } = form.into_inner();
+
+    let value = value_type
+        .parse::<AttributeType>()
+        .and_then(|t| AttributeValue::parse(t, &value))
+        .map_err(|e| {
+            Flash::error(
+                Redirect::to(uri!(crate::frontend::notes::edit_note(id))),
+                format!("Invalid value for attribute '{key}': {e}"),
+            )
+        })?;
 
     set_attribute(&mut db, id, &key, &value)
         .await
@@ -255,13 +353,331 @@ pub async fn delete_note_submit(
         };
 
     match crate::db_manage::notes::delete_note(&mut db, id).await {
-        Ok(_) => Ok(Flash::success(
-            parent_redirect,
-            "Note deleted successfully.",
-        )),
+        Ok(_) => Ok(Flash::success(parent_redirect, "Note moved to trash.")),
         Err(e) => Err(Flash::error(
             Redirect::to(uri!(crate::frontend::notes::edit_note(id))),
             format!("Failed to delete note: {e}"),
         )),
     }
 }
+
+#[derive(FromForm)]
+pub struct PinForm {
+    // Empty to follow the current version
+    pub version: Option<i64>,
+}
+
+#[post("/notes/<id>/pin", data = "<form>")]
+pub async fn pin_note_submit(
+    _auth: Authenticated,
+    mut db: Connection<Db>,
+    id: i64,
+    form: Form<PinForm>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    match pin_note(&mut db, id, form.version).await {
+        Ok(_) => Ok(Flash::success(
+            Redirect::to(uri!(edit_note(id))),
+            match form.version {
+                Some(version) => format!("Note pinned to version {version}."),
+                None => "Note follows the current code version.".to_string(),
+            },
+        )),
+        Err(e) => Err(Flash::error(
+            Redirect::to(uri!(edit_note(id))),
+            format!("Failed to pin note: {e}"),
+        )),
+    }
+}
+
+#[post("/notes/<id>/revisions/<revision_id>/revert")]
+pub async fn revert_revision_submit(
+    _auth: Authenticated,
+    mut db: Connection<Db>,
+    id: i64,
+    revision_id: i64,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    match revert_to_revision(&mut db, id, revision_id).await {
+        Ok(_) => Ok(Flash::success(
+            Redirect::to(uri!(show_note(id))),
+            format!("Note reverted to before revision {revision_id}."),
+        )),
+        Err(e) => Err(Flash::error(
+            Redirect::to(uri!(crate::frontend::notes::note_history(id))),
+            format!("Failed to revert: {e}"),
+        )),
+    }
+}
+
+#[post("/trash/<id>/restore")]
+pub async fn restore_note_submit(
+    _auth: Authenticated,
+    mut db: Connection<Db>,
+    id: i64,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    match restore_note(&mut db, id).await {
+        Ok(_) => Ok(Flash::success(
+            Redirect::to(uri!(show_note(id))),
+            "Note restored.",
+        )),
+        Err(e) => Err(Flash::error(
+            Redirect::to(uri!(crate::frontend::notes::trash)),
+            format!("Failed to restore note: {e}"),
+        )),
+    }
+}
+
+#[post("/trash/<id>/purge")]
+pub async fn purge_note_submit(
+    _auth: Authenticated,
+    mut db: Connection<Db>,
+    id: i64,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    match purge_note(&mut db, id).await {
+        Ok(_) => Ok(Flash::success(
+            Redirect::to(uri!(crate::frontend::notes::trash)),
+            "Note permanently deleted.",
+        )),
+        Err(e) => Err(Flash::error(
+            Redirect::to(uri!(crate::frontend::notes::trash)),
+            format!("Failed to purge note: {e}"),
+        )),
+    }
+}
+
+#[derive(FromForm)]
+pub struct MoveNoteForm {
+    pub parent_id: i64,
+}
+
+#[post("/notes/<id>/move", data = "<form>")]
+pub async fn move_note_submit(
+    _auth: Authenticated,
+    mut db: Connection<Db>,
+    id: i64,
+    form: Form<MoveNoteForm>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    match move_note(&mut db, id, form.parent_id).await {
+        Ok(_) => Ok(Flash::success(
+            Redirect::to(uri!(show_note(id))),
+            "Note moved.",
+        )),
+        Err(e) => Err(Flash::error(
+            Redirect::to(uri!(crate::frontend::notes::move_note_form(id))),
+            format!("Failed to move note: {e}"),
+        )),
+    }
+}
+
+#[derive(FromForm)]
+pub struct CopyNoteForm {
+    pub parent_id: i64,
+    pub with_logs: bool,
+}
+
+#[post("/notes/<id>/copy", data = "<form>")]
+pub async fn copy_note_submit(
+    _auth: Authenticated,
+    mut db: Connection<Db>,
+    id: i64,
+    form: Form<CopyNoteForm>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    match copy_note(&mut db, id, form.parent_id, form.with_logs).await {
+        Ok(copy_id) => Ok(Flash::success(
+            Redirect::to(uri!(show_note(copy_id))),
+            "Note copied.",
+        )),
+        Err(e) => Err(Flash::error(
+            Redirect::to(uri!(crate::frontend::notes::copy_note_form(id))),
+            format!("Failed to copy note: {e}"),
+        )),
+    }
+}
+
+#[derive(FromForm)]
+pub struct MoveCardForm {
+    pub key: String,
+    // Empty for the column without value
+    pub value: String,
+}
+
+#[post("/notes/<id>/board/move", data = "<form>")]
+pub async fn move_card_submit(
+    _auth: Authenticated,
+    mut db: Connection<Db>,
+    id: i64,
+    form: Form<MoveCardForm>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    let MoveCardForm { key, value } = form.into_inner();
+    let board_id = match get_note(&mut db, id).await {
+        Ok(Some(Note {
+            parent_id: Some(parent_id),
+            ..
+        })) => parent_id,
+        _ => {
+            return Err(Flash::error(
+                Redirect::to(uri!(show_note(id))),
+                "Only child notes can be moved on a board.",
+            ))
+        }
+    };
+    let board = uri!(crate::frontend::notes::note_board(
+        board_id,
+        Some(key.clone())
+    ));
+    let value = Some(value.trim()).filter(|v| !v.is_empty());
+    let mut tx = db.begin().await.map_err(|e| {
+        Flash::error(
+            Redirect::to(board.clone()),
+            format!("Cannot begin tx: {e}."),
+        )
+    })?;
+    let message = move_card(&mut tx, id, &key, value).await.map_err(|e| {
+        Flash::error(
+            Redirect::to(board.clone()),
+            format!("Failed to move card: {e}"),
+        )
+    })?;
+    match tx.commit().await {
+        Ok(_) => Ok(Flash::success(Redirect::to(board), message)),
+        Err(e) => Err(Flash::error(
+            Redirect::to(board),
+            format!("Cannot commit tx: {e}."),
+        )),
+    }
+}
+
+#[derive(FromForm)]
+pub struct TemplateForm {
+    pub is_template: bool,
+    // Comma separated parameter names
+    pub params: String,
+}
+
+#[post("/notes/<id>/template", data = "<form>")]
+pub async fn template_submit(
+    _auth: Authenticated,
+    mut db: Connection<Db>,
+    id: i64,
+    form: Form<TemplateForm>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    let TemplateForm {
+        is_template,
+        params,
+    } = form.into_inner();
+    let params = is_template.then(|| {
+        params
+            .split(',')
+            .map(|p| p.trim().to_string())
+            .filter(|p| !p.is_empty())
+            .collect()
+    });
+    match set_template_params(&mut db, id, params).await {
+        Ok(_) => Ok(Flash::success(
+            Redirect::to(uri!(crate::frontend::notes::note_template(id))),
+            "Template updated.",
+        )),
+        Err(e) => Err(Flash::error(
+            Redirect::to(uri!(crate::frontend::notes::note_template(id))),
+            format!("Failed to update template: {e}"),
+        )),
+    }
+}
+
+#[derive(FromForm)]
+pub struct InstantiateForm {
+    pub parent_id: i64,
+    pub values: HashMap<String, String>,
+}
+
+#[post("/notes/<id>/instantiate", data = "<form>")]
+pub async fn instantiate_submit(
+    _auth: Authenticated,
+    mut db: Connection<Db>,
+    id: i64,
+    form: Form<InstantiateForm>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    let InstantiateForm { parent_id, values } = form.into_inner();
+    match instantiate_template(&mut db, id, parent_id, values).await {
+        Ok(note_id) => Ok(Flash::success(
+            Redirect::to(uri!(show_note(note_id))),
+            "Template instantiated.",
+        )),
+        Err(e) => Err(Flash::error(
+            Redirect::to(uri!(crate::frontend::notes::note_template(id))),
+            format!("Failed to instantiate template: {e}"),
+        )),
+    }
+}
+
+/// Cookie remembering that done notes are hidden from child lists. It is
+/// not tied to the note it was set from: every list follows it.
+pub const HIDE_DONE_COOKIE: &str = "hide_done";
+
+#[derive(FromForm)]
+pub struct HideDoneForm {
+    pub hide: bool,
+}
+
+#[post("/notes/<id>/hide_done", data = "<form>")]
+pub async fn hide_done_submit(
+    _auth: Authenticated,
+    jar: &CookieJar<'_>,
+    id: i64,
+    form: Form<HideDoneForm>,
+) -> Redirect {
+    if form.hide {
+        jar.add(Cookie::new(HIDE_DONE_COOKIE, "true"));
+    } else {
+        jar.remove(Cookie::from(HIDE_DONE_COOKIE));
+    }
+    Redirect::to(uri!(show_note(id)))
+}
+
+/// Cookie remembering that child lists are sorted by priority, on every
+/// note like the hide done cookie.
+pub const PRIORITY_SORT_COOKIE: &str = "priority_sort";
+
+#[derive(FromForm)]
+pub struct PrioritySortForm {
+    pub enabled: bool,
+}
+
+#[post("/notes/<id>/priority_sort", data = "<form>")]
+pub async fn priority_sort_submit(
+    _auth: Authenticated,
+    jar: &CookieJar<'_>,
+    id: i64,
+    form: Form<PrioritySortForm>,
+) -> Redirect {
+    if form.enabled {
+        jar.add(Cookie::new(PRIORITY_SORT_COOKIE, "true"));
+    } else {
+        jar.remove(Cookie::from(PRIORITY_SORT_COOKIE));
+    }
+    Redirect::to(uri!(show_note(id)))
+}
+
+#[derive(FromForm)]
+pub struct ReorderNoteForm {
+    pub position: usize,
+}
+
+#[post("/notes/<id>/reorder", data = "<form>")]
+pub async fn reorder_note_submit(
+    _auth: Authenticated,
+    mut db: Connection<Db>,
+    id: i64,
+    form: Form<ReorderNoteForm>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    let back = match get_note(&mut db, id).await {
+        Ok(Some(Note {
+            parent_id: Some(pid),
+            ..
+        })) => Redirect::to(uri!(show_note(pid))),
+        _ => Redirect::to(uri!(crate::frontend::notes::root_notes)),
+    };
+    match reorder_note(&mut db, id, form.position).await {
+        Ok(_) => Ok(Flash::success(back, "Note reordered.")),
+        Err(e) => Err(Flash::error(back, format!("Failed to reorder: {e}"))),
+    }
+}
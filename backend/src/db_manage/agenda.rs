@@ -0,0 +1,177 @@
+use chrono::{NaiveDate, NaiveDateTime};
+use rocket_db_pools::sqlx::{self};
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+use sqlx::SqliteConnection;
+
+use super::attributes::{
+    AttributeType, AttributeValue, ALARM_ATTRIBUTE, DUE_ATTRIBUTE,
+    PRIORITY_ATTRIBUTE,
+};
+use super::errors::{DbError, SqlxSnafu};
+
+/// An open note with a due date.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgendaEntry {
+    pub id: i64,
+    pub title: String,
+    pub parent_title: Option<String>,
+    pub due: NaiveDate,
+    pub priority: i64,
+}
+
+/// Open notes with a due date, split around `today`. Each group is sorted
+/// by due date, then by priority.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Agenda {
+    pub overdue: Vec<AgendaEntry>,
+    pub today: Vec<AgendaEntry>,
+    pub upcoming: Vec<AgendaEntry>,
+}
+
+pub async fn get_agenda(
+    db: &mut SqliteConnection,
+    today: NaiveDate,
+) -> Result<Agenda, DbError> {
+    let rows = sqlx::query_as::<_, (i64, String, Option<String>, String, i64)>(
+        r#"
+        SELECT n.id, n.title, parent.title, due.value,
+               COALESCE(CAST(priority.value AS INTEGER), 0) AS priority
+        FROM notes n
+        JOIN note_status s ON s.note_id = n.id
+        JOIN attributes due
+          ON due.note_id = n.id AND due.key = ? AND due.value_type = 'Date'
+        LEFT JOIN attributes priority
+          ON priority.note_id = n.id AND priority.key = ?
+        LEFT JOIN notes parent ON parent.id = n.parent_id
+        WHERE n.deleted_at IS NULL AND NOT s.done
+        ORDER BY due.value, priority DESC, n.id
+        "#,
+    )
+    .bind(DUE_ATTRIBUTE)
+    .bind(PRIORITY_ATTRIBUTE)
+    .fetch_all(&mut *db)
+    .await
+    .context(SqlxSnafu {
+        task: "getting agenda",
+    })?;
+
+    let mut agenda = Agenda::default();
+    for (id, title, parent_title, due, priority) in rows {
+        let due = match AttributeValue::parse(AttributeType::Date, &due) {
+            Ok(AttributeValue::Date(date)) => date,
+            _ => {
+                return Err(DbError::ParseError {
+                    when: format!("reading due date of note {id}"),
+                });
+            }
+        };
+        let entry = AgendaEntry {
+            id,
+            title,
+            parent_title,
+            due,
+            priority,
+        };
+        let group = match due.cmp(&today) {
+            std::cmp::Ordering::Less => &mut agenda.overdue,
+            std::cmp::Ordering::Equal => &mut agenda.today,
+            std::cmp::Ordering::Greater => &mut agenda.upcoming,
+        };
+        group.push(entry);
+    }
+    Ok(agenda)
+}
+
+/// A note with a due date, as exported to calendar clients.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CalendarEntry {
+    pub id: i64,
+    pub title: String,
+    pub description: String,
+    pub due: NaiveDate,
+    pub done: bool,
+    pub priority: i64,
+    pub alarm: Option<NaiveDateTime>,
+}
+
+/// Every note with a due date below `root_id` (itself included), done or
+/// not, ordered by due date.
+pub async fn get_calendar_entries(
+    db: &mut SqliteConnection,
+    root_id: i64,
+) -> Result<Vec<CalendarEntry>, DbError> {
+    let rows = sqlx::query_as::<
+        _,
+        (i64, String, String, String, bool, i64, Option<String>),
+    >(
+        r#"
+        WITH RECURSIVE subtree(id) AS (
+            SELECT id FROM notes WHERE id = ? AND deleted_at IS NULL
+
+            UNION ALL
+
+            SELECT n.id
+            FROM notes n
+            JOIN subtree s ON n.parent_id = s.id
+            WHERE n.deleted_at IS NULL
+        )
+        SELECT n.id, n.title, COALESCE(n.description, ''), due.value, s.done,
+               COALESCE(CAST(priority.value AS INTEGER), 0),
+               alarm.value
+        FROM subtree t
+        JOIN notes n ON n.id = t.id
+        JOIN note_status s ON s.note_id = n.id
+        JOIN attributes due
+          ON due.note_id = n.id AND due.key = ? AND due.value_type = 'Date'
+        LEFT JOIN attributes priority
+          ON priority.note_id = n.id AND priority.key = ?
+        LEFT JOIN attributes alarm
+          ON alarm.note_id = n.id AND alarm.key = ?
+         AND alarm.value_type = 'DateTime'
+        ORDER BY due.value, n.id
+        "#,
+    )
+    .bind(root_id)
+    .bind(DUE_ATTRIBUTE)
+    .bind(PRIORITY_ATTRIBUTE)
+    .bind(ALARM_ATTRIBUTE)
+    .fetch_all(&mut *db)
+    .await
+    .context(SqlxSnafu {
+        task: "getting calendar entries",
+    })?;
+
+    let mut entries = vec![];
+    for (id, title, description, due, done, priority, alarm) in rows {
+        let due = match AttributeValue::parse(AttributeType::Date, &due) {
+            Ok(AttributeValue::Date(date)) => date,
+            _ => {
+                return Err(DbError::ParseError {
+                    when: format!("reading due date of note {id}"),
+                });
+            }
+        };
+        let alarm = match alarm
+            .map(|raw| AttributeValue::parse(AttributeType::DateTime, &raw))
+        {
+            None => None,
+            Some(Ok(AttributeValue::DateTime(time))) => Some(time),
+            Some(_) => {
+                return Err(DbError::ParseError {
+                    when: format!("reading alarm of note {id}"),
+                });
+            }
+        };
+        entries.push(CalendarEntry {
+            id,
+            title,
+            description,
+            due,
+            done,
+            priority,
+            alarm,
+        });
+    }
+    Ok(entries)
+}
@@ -1,34 +1,390 @@
+use chrono::{NaiveDate, NaiveDateTime};
 use rocket_db_pools::Connection;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
 use snafu::ResultExt;
 use sqlx::SqliteConnection;
+use std::fmt;
+use std::str::FromStr;
 
 use crate::db_manage::Db;
 
 use super::errors::{DbError, SqlxSnafu};
+use super::revisions::record_attribute_change;
 
-// #[derive(Debug, FromRow)]
-// pub struct Attribute {
-//     pub note_id: i64,
-//     pub key: String,
-//     pub value: String,
-// }
+const DATE_FORMAT: &str = "%Y-%m-%d";
+const DATETIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+// What `<input type="datetime-local">` submits when seconds are zero
+const DATETIME_SHORT_FORMAT: &str = "%Y-%m-%dT%H:%M";
+
+/// Date by which a note should be done
+pub const DUE_ATTRIBUTE: &str = "due";
+/// Integer priority, higher numbers first, notes without one count as 0
+pub const PRIORITY_ATTRIBUTE: &str = "priority";
+/// Local date and time at which calendar clients should remind of a note
+pub const ALARM_ATTRIBUTE: &str = "alarm";
+
+/// Type the backend requires for attributes it understands itself
+pub fn reserved_type(key: &str) -> Option<AttributeType> {
+    match key {
+        DUE_ATTRIBUTE => Some(AttributeType::Date),
+        PRIORITY_ATTRIBUTE => Some(AttributeType::Integer),
+        ALARM_ATTRIBUTE => Some(AttributeType::DateTime),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AttributeType {
+    String,
+    Integer,
+    Float,
+    Bool,
+    Date,
+    DateTime,
+    Json,
+    Note,
+}
+
+impl AttributeType {
+    pub const ALL: [AttributeType; 8] = [
+        AttributeType::String,
+        AttributeType::Integer,
+        AttributeType::Float,
+        AttributeType::Bool,
+        AttributeType::Date,
+        AttributeType::DateTime,
+        AttributeType::Json,
+        AttributeType::Note,
+    ];
+}
+
+impl fmt::Display for AttributeType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            AttributeType::String => "String",
+            AttributeType::Integer => "Integer",
+            AttributeType::Float => "Float",
+            AttributeType::Bool => "Bool",
+            AttributeType::Date => "Date",
+            AttributeType::DateTime => "DateTime",
+            AttributeType::Json => "Json",
+            AttributeType::Note => "Note",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for AttributeType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        AttributeType::ALL
+            .into_iter()
+            .find(|t| t.to_string() == s)
+            .ok_or(format!("Unknown attribute type: {s}"))
+    }
+}
+
+/// A typed attribute value. Stored as text next to its type and
+/// exchanged with Lua as a single-key table, e.g. `{ Date = "2025-07-01" }`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AttributeValue {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Bool(bool),
+    Date(NaiveDate),
+    DateTime(NaiveDateTime),
+    Json(JsonValue),
+    Note(i64),
+}
+
+impl AttributeValue {
+    pub fn value_type(&self) -> AttributeType {
+        match self {
+            AttributeValue::String(_) => AttributeType::String,
+            AttributeValue::Integer(_) => AttributeType::Integer,
+            AttributeValue::Float(_) => AttributeType::Float,
+            AttributeValue::Bool(_) => AttributeType::Bool,
+            AttributeValue::Date(_) => AttributeType::Date,
+            AttributeValue::DateTime(_) => AttributeType::DateTime,
+            AttributeValue::Json(_) => AttributeType::Json,
+            AttributeValue::Note(_) => AttributeType::Note,
+        }
+    }
+
+    /// Parses the textual form produced by `Display` (and by html inputs).
+    pub fn parse(value_type: AttributeType, raw: &str) -> Result<Self, String> {
+        match value_type {
+            AttributeType::String => {
+                Ok(AttributeValue::String(raw.to_string()))
+            }
+            AttributeType::Integer => raw
+                .trim()
+                .parse::<i64>()
+                .map(AttributeValue::Integer)
+                .map_err(|e| format!("Invalid integer: {e}")),
+            AttributeType::Float => raw
+                .trim()
+                .parse::<f64>()
+                .map(AttributeValue::Float)
+                .map_err(|e| format!("Invalid float: {e}")),
+            AttributeType::Bool => raw
+                .trim()
+                .parse::<bool>()
+                .map(AttributeValue::Bool)
+                .map_err(|e| format!("Invalid bool: {e}")),
+            AttributeType::Date => NaiveDate::parse_from_str(raw, DATE_FORMAT)
+                .map(AttributeValue::Date)
+                .map_err(|e| format!("Invalid date: {e}")),
+            AttributeType::DateTime => {
+                NaiveDateTime::parse_from_str(raw, DATETIME_FORMAT)
+                    .or_else(|_| {
+                        NaiveDateTime::parse_from_str(
+                            raw,
+                            DATETIME_SHORT_FORMAT,
+                        )
+                    })
+                    .map(AttributeValue::DateTime)
+                    .map_err(|e| format!("Invalid datetime: {e}"))
+            }
+            AttributeType::Json => serde_json::from_str(raw)
+                .map(AttributeValue::Json)
+                .map_err(|e| format!("Invalid JSON: {e}")),
+            AttributeType::Note => raw
+                .trim()
+                .trim_start_matches('#')
+                .parse::<i64>()
+                .map(AttributeValue::Note)
+                .map_err(|e| format!("Invalid note id: {e}")),
+        }
+    }
+}
+
+impl fmt::Display for AttributeValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttributeValue::String(s) => write!(f, "{s}"),
+            AttributeValue::Integer(i) => write!(f, "{i}"),
+            AttributeValue::Float(x) => write!(f, "{x}"),
+            AttributeValue::Bool(b) => write!(f, "{b}"),
+            AttributeValue::Date(d) => write!(f, "{}", d.format(DATE_FORMAT)),
+            AttributeValue::DateTime(d) => {
+                write!(f, "{}", d.format(DATETIME_FORMAT))
+            }
+            AttributeValue::Json(j) => write!(f, "{j}"),
+            AttributeValue::Note(id) => write!(f, "{id}"),
+        }
+    }
+}
+
+/// An attribute declared by a code. Required attributes must come with a
+/// default, so that every note bound to the code can have them from the start.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AttributeSpec {
+    pub name: String,
+    pub value_type: AttributeType,
+    #[serde(default)]
+    pub required: bool,
+    #[serde(default)]
+    pub default: Option<String>,
+}
+
+impl AttributeSpec {
+    pub fn default_value(&self) -> Option<AttributeValue> {
+        self.default
+            .as_ref()
+            .and_then(|d| AttributeValue::parse(self.value_type, d).ok())
+    }
+}
+
+pub fn parse_schema(schema: &str) -> Result<Vec<AttributeSpec>, DbError> {
+    let specs: Vec<AttributeSpec> =
+        serde_json::from_str(schema).map_err(|e| DbError::ParseError {
+            when: format!("loading attribute schema: {e}"),
+        })?;
+    for spec in &specs {
+        if let Some(expected) = reserved_type(&spec.name)
+            && expected != spec.value_type
+        {
+            return Err(DbError::ParseError {
+                when: format!(
+                    "attribute {} must be declared as {expected}",
+                    spec.name
+                ),
+            });
+        }
+        if let Some(default) = &spec.default {
+            AttributeValue::parse(spec.value_type, default).map_err(|e| {
+                DbError::ParseError {
+                    when: format!("default of attribute {}: {e}", spec.name),
+                }
+            })?;
+        } else if spec.required {
+            return Err(DbError::ParseError {
+                when: format!(
+                    "required attribute {} has no default",
+                    spec.name
+                ),
+            });
+        }
+    }
+    Ok(specs)
+}
+
+/// Schema declared by the code bound to a note (empty if it has none).
+pub async fn get_attribute_schema(
+    db: &mut SqliteConnection,
+    note_id: i64,
+) -> Result<Vec<AttributeSpec>, DbError> {
+    let schema = sqlx::query_scalar::<_, String>(
+        r#"
+        SELECT COALESCE(v.attribute_schema, c.attribute_schema)
+        FROM notes n
+        JOIN codes c ON c.name = n.code_name
+        LEFT JOIN code_versions v
+          ON v.code_name = c.name AND v.version = n.code_version
+        WHERE n.id = ?
+        "#,
+    )
+    .bind(note_id)
+    .fetch_optional(&mut *db)
+    .await
+    .context(SqlxSnafu {
+        task: "getting attribute schema",
+    })?;
+    match schema {
+        Some(schema) => parse_schema(&schema),
+        None => Ok(vec![]),
+    }
+}
+
+/// Type a value for `key` is parsed as: the one the code's schema
+/// declares, else the one of the current value, else a string.
+pub async fn get_attribute_type(
+    db: &mut SqliteConnection,
+    note_id: i64,
+    key: &str,
+) -> Result<AttributeType, DbError> {
+    let schema = get_attribute_schema(db, note_id).await?;
+    Ok(match schema.iter().find(|spec| spec.name == key) {
+        Some(spec) => spec.value_type,
+        None => get_attribute(db, note_id, key)
+            .await?
+            .map(|v| v.value_type())
+            .unwrap_or(AttributeType::String),
+    })
+}
+
+/// Fills in declared attributes that a note is still missing.
+pub async fn apply_attribute_defaults(
+    db: &mut SqliteConnection,
+    note_id: i64,
+) -> Result<(), DbError> {
+    for spec in get_attribute_schema(db, note_id).await? {
+        let Some(default) = spec.default_value() else {
+            continue;
+        };
+        if get_attribute(db, note_id, &spec.name).await?.is_none() {
+            set_attribute(db, note_id, &spec.name, &default).await?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn decode(
+    key: &str,
+    value_type: &str,
+    raw: &str,
+) -> Result<AttributeValue, DbError> {
+    value_type
+        .parse::<AttributeType>()
+        .and_then(|t| AttributeValue::parse(t, raw))
+        .map_err(|e| DbError::ParseError {
+            when: format!("reading attribute {key}: {e}"),
+        })
+}
+
+async fn validate(
+    db: &mut SqliteConnection,
+    note_id: i64,
+    key: &str,
+    value: &AttributeValue,
+) -> Result<(), DbError> {
+    if let Some(expected) = reserved_type(key)
+        && expected != value.value_type()
+    {
+        return Err(DbError::InvalidAttribute {
+            key: key.to_string(),
+            reason: format!(
+                "{key} is always a {expected}, got {}",
+                value.value_type()
+            ),
+        });
+    }
+    let schema = get_attribute_schema(db, note_id).await?;
+    if let Some(spec) = schema.iter().find(|spec| spec.name == key)
+        && spec.value_type != value.value_type()
+    {
+        return Err(DbError::InvalidAttribute {
+            key: key.to_string(),
+            reason: format!(
+                "code expects {}, got {}",
+                spec.value_type,
+                value.value_type()
+            ),
+        });
+    }
+    let reason = match value {
+        AttributeValue::Float(x) if !x.is_finite() => {
+            Some(format!("{x} is not a finite number"))
+        }
+        AttributeValue::Note(target) => {
+            let exists = sqlx::query_scalar::<_, i64>(
+                "SELECT COUNT(*) FROM notes WHERE id = ? AND deleted_at IS NULL",
+            )
+            .bind(target)
+            .fetch_one(&mut *db)
+            .await
+            .context(SqlxSnafu {
+                task: "checking referenced note",
+            })?;
+            (exists == 0).then(|| format!("note {target} does not exist"))
+        }
+        _ => None,
+    };
+    match reason {
+        Some(reason) => Err(DbError::InvalidAttribute {
+            key: key.to_string(),
+            reason,
+        }),
+        None => Ok(()),
+    }
+}
 
 pub async fn set_attribute(
     db: &mut SqliteConnection,
     note_id: i64,
     key: &str,
-    value: &str,
+    value: &AttributeValue,
 ) -> Result<(), DbError> {
+    validate(db, note_id, key, value).await?;
+    let before = get_attribute(db, note_id, key).await?;
+    record_attribute_change(db, note_id, key, before.as_ref(), Some(value))
+        .await?;
     sqlx::query(
         r#"
-        INSERT INTO attributes (note_id, key, value)
-        VALUES (?, ?, ?)
-        ON CONFLICT(note_id, key) DO UPDATE SET value = excluded.value
+        INSERT INTO attributes (note_id, key, value, value_type)
+        VALUES (?, ?, ?, ?)
+        ON CONFLICT(note_id, key) DO UPDATE
+        SET value = excluded.value, value_type = excluded.value_type
         "#,
     )
     .bind(note_id)
     .bind(key)
-    .bind(value)
+    .bind(value.to_string())
+    .bind(value.value_type().to_string())
     .execute(&mut *db)
     .await
     .context(SqlxSnafu {
@@ -42,9 +398,9 @@ pub async fn get_attribute(
     db: &mut SqliteConnection,
     note_id: i64,
     key: &str,
-) -> Result<Option<String>, DbError> {
-    let result = sqlx::query_scalar::<_, String>(
-        "SELECT value FROM attributes WHERE note_id = ? AND key = ?",
+) -> Result<Option<AttributeValue>, DbError> {
+    let result = sqlx::query_as::<_, (String, String)>(
+        "SELECT value, value_type FROM attributes WHERE note_id = ? AND key = ?",
     )
     .bind(note_id)
     .bind(key)
@@ -54,15 +410,17 @@ pub async fn get_attribute(
         task: "getting attributes",
     })?;
 
-    Ok(result)
+    result
+        .map(|(value, value_type)| decode(key, &value_type, &value))
+        .transpose()
 }
 
 pub async fn get_attributes(
     db: &mut Connection<Db>,
     note_id: i64,
-) -> Result<Vec<(String, String)>, DbError> {
-    let result = sqlx::query_as::<_, (String, String)>(
-        "SELECT key, value FROM attributes WHERE note_id = ?",
+) -> Result<Vec<(String, AttributeValue)>, DbError> {
+    let result = sqlx::query_as::<_, (String, String, String)>(
+        "SELECT key, value, value_type FROM attributes WHERE note_id = ?",
     )
     .bind(note_id)
     .fetch_all(&mut ***db)
@@ -71,18 +429,33 @@ pub async fn get_attributes(
         task: "getting attributes",
     })?;
 
-    Ok(result)
+    result
+        .into_iter()
+        .map(|(key, value, value_type)| {
+            let value = decode(&key, &value_type, &value)?;
+            Ok((key, value))
+        })
+        .collect()
 }
 
 pub async fn delete_attribute(
-    db: &mut Connection<Db>,
+    db: &mut SqliteConnection,
     note_id: i64,
     key: &str,
 ) -> Result<(), DbError> {
+    let schema = get_attribute_schema(db, note_id).await?;
+    if schema.iter().any(|spec| spec.name == key && spec.required) {
+        return Err(DbError::InvalidAttribute {
+            key: key.to_string(),
+            reason: "it is required by the note's code".to_string(),
+        });
+    }
+    let before = get_attribute(db, note_id, key).await?;
+    record_attribute_change(db, note_id, key, before.as_ref(), None).await?;
     sqlx::query("DELETE FROM attributes WHERE note_id = ? AND key = ?")
         .bind(note_id)
         .bind(key)
-        .execute(&mut ***db)
+        .execute(&mut *db)
         .await
         .context(SqlxSnafu {
             task: "deleting attribute",
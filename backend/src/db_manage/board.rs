@@ -0,0 +1,141 @@
+use rocket_db_pools::sqlx::FromRow;
+use rocket_db_pools::sqlx::{self};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use snafu::ResultExt;
+use sqlx::SqliteConnection;
+use std::collections::HashMap;
+
+use crate::api::codes::FormContainer;
+
+use super::attributes::{
+    delete_attribute, get_attribute_type, set_attribute, AttributeValue,
+};
+use super::codes::{execute, get_code, parse_fields, run};
+use super::errors::{DbError, SqlxSnafu};
+use super::notes::Note;
+use super::trash::check_not_trashed;
+
+/// Attribute a board groups by when none is chosen.
+pub const DEFAULT_BOARD_KEY: &str = "status";
+
+/// Children of a note sharing a value of the board attribute. The column
+/// without value holds the children that do not have the attribute.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BoardColumn {
+    pub value: Option<String>,
+    pub cards: Vec<(Note, bool)>,
+}
+
+#[derive(FromRow)]
+struct BoardCard {
+    #[sqlx(flatten)]
+    note: Note,
+    done: bool,
+    value: Option<String>,
+}
+
+/// Children of `note_id` grouped by the raw value of their `key` attribute.
+/// Columns come in the order their first card appears among the children,
+/// after the column without value which is always present.
+pub async fn get_board(
+    db: &mut SqliteConnection,
+    note_id: i64,
+    key: &str,
+) -> Result<Vec<BoardColumn>, DbError> {
+    let cards = sqlx::query_as::<_, BoardCard>(
+        r#"
+        SELECT n.id, n.parent_id, n.title, n.description, n.code_name,
+               s.done, a.value
+        FROM notes n
+        JOIN note_status s ON s.note_id = n.id
+        LEFT JOIN attributes a ON a.note_id = n.id AND a.key = ?
+        WHERE n.parent_id = ? AND n.deleted_at IS NULL
+        ORDER BY n.position, n.id
+        "#,
+    )
+    .bind(key)
+    .bind(note_id)
+    .fetch_all(&mut *db)
+    .await
+    .context(SqlxSnafu {
+        task: "getting board cards",
+    })?;
+
+    let mut columns = vec![BoardColumn {
+        value: None,
+        cards: vec![],
+    }];
+    for card in cards {
+        let index = match columns.iter().position(|c| c.value == card.value) {
+            Some(index) => index,
+            None => {
+                columns.push(BoardColumn {
+                    value: card.value,
+                    cards: vec![],
+                });
+                columns.len() - 1
+            }
+        };
+        columns[index].cards.push((card.note, card.done));
+    }
+    Ok(columns)
+}
+
+/// Moves a card to the column `value` (`None` for the column without value).
+///
+/// Notes whose code has a board action for `key` run that form with the
+/// new value, so the code can react to the change. Other notes get the
+/// attribute set directly, typed as the code's schema or the current value
+/// says.
+pub async fn move_card(
+    db: &mut SqliteConnection,
+    id: i64,
+    key: &str,
+    value: Option<&str>,
+) -> Result<String, DbError> {
+    check_not_trashed(db, id).await?;
+    if let Some(code) = get_code(db, id).await?
+        && code.board_action_key.as_deref() == Some(key)
+        && let Some(label) = code.board_action.clone()
+    {
+        let mut forms = run::<HashMap<String, FormContainer>>(
+            db,
+            code,
+            "forms",
+            id,
+            JsonValue::Null,
+        )
+        .await?;
+        let form = forms.remove(&label).ok_or(DbError::ExecutionError {
+            trace: format!(
+                "board action {label} is not available on note {id}"
+            ),
+        })?;
+        let inputs = HashMap::from([(
+            form.action.label.clone(),
+            value.unwrap_or_default().to_string(),
+        )]);
+        let input =
+            parse_fields(&form.action.form_type, &inputs, &form.action.label)
+                .map_err(|e| DbError::ParseError {
+                when: format!("moving note {id} with {label}: {e}"),
+            })?;
+        return execute(db, id, &form, &input).await;
+    }
+
+    let Some(raw) = value else {
+        delete_attribute(db, id, key).await?;
+        return Ok(format!("Removed {key} from note {id}."));
+    };
+    let value_type = get_attribute_type(db, id, key).await?;
+    let new_value =
+        AttributeValue::parse(value_type, raw).map_err(|reason| {
+            DbError::InvalidAttribute {
+                key: key.to_string(),
+                reason,
+            }
+        })?;
+    set_attribute(db, id, key, &new_value).await?;
+    Ok(format!("Set {key} of note {id} to {raw}."))
+}
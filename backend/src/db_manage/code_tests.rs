@@ -0,0 +1,253 @@
+use rocket_db_pools::sqlx::{self};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use snafu::ResultExt;
+use sqlx::{Connection, SqliteConnection};
+use std::collections::BTreeMap;
+
+use super::attributes::{get_attribute, set_attribute, AttributeValue};
+use super::codes::{get_code_by_name, run_traced, Code};
+use super::dependencies::imports;
+use super::errors::{DbError, SqlxSnafu};
+use super::trace::Trace;
+use super::validation::{limited_lua, TRIAL_TIME_LIMIT};
+use super::{create_note, migrate_connection, ROOT_NOTE_ID};
+
+/// A test case of a code: a note using the code is prepared with some
+/// attributes, one of its coroutines runs, and the outcome is compared
+/// with what is expected.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CodeTest {
+    pub name: String,
+    /// Attributes of the note before the run
+    #[serde(default)]
+    pub attributes: BTreeMap<String, AttributeValue>,
+    /// Coroutine to run, `forms` or an action label
+    pub action: String,
+    /// Input as the action gets it from a form, e.g. `{ "UInt": 3 }`
+    #[serde(default = "empty_input")]
+    pub input: JsonValue,
+    /// Expected result, not checked if missing
+    #[serde(default)]
+    pub result: Option<JsonValue>,
+    /// Attributes after the run, `null` for one that must be absent
+    #[serde(default)]
+    pub expected_attributes: BTreeMap<String, Option<AttributeValue>>,
+}
+
+fn empty_input() -> JsonValue {
+    "Empty".into()
+}
+
+/// Outcome of a test case, passed if nothing went wrong.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TestReport {
+    pub name: String,
+    pub failures: Vec<String>,
+}
+
+impl TestReport {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+pub fn parse_tests(tests: &str) -> Result<Vec<CodeTest>, DbError> {
+    serde_json::from_str(tests).map_err(|e| DbError::ParseError {
+        when: format!("reading code tests: {e}"),
+    })
+}
+
+/// The test cases of a code, as the JSON text they are stored in.
+pub async fn get_code_tests(
+    db: &mut SqliteConnection,
+    name: &str,
+) -> Result<String, DbError> {
+    sqlx::query_scalar("SELECT tests FROM codes WHERE name = ?")
+        .bind(name)
+        .fetch_optional(&mut *db)
+        .await
+        .context(SqlxSnafu {
+            task: "getting code tests",
+        })?
+        .ok_or(DbError::CodeNotFound {
+            name: name.to_string(),
+        })
+}
+
+/// Replaces the test cases of a code, which must parse.
+pub async fn set_code_tests(
+    db: &mut SqliteConnection,
+    name: &str,
+    tests: &str,
+) -> Result<(), DbError> {
+    parse_tests(tests)?;
+    let updated = sqlx::query("UPDATE codes SET tests = ? WHERE name = ?")
+        .bind(tests)
+        .bind(name)
+        .execute(&mut *db)
+        .await
+        .context(SqlxSnafu {
+            task: "setting code tests",
+        })?;
+    if updated.rows_affected() == 0 {
+        return Err(DbError::CodeNotFound {
+            name: name.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Names of the codes having test cases.
+pub async fn get_tested_codes(
+    db: &mut SqliteConnection,
+) -> Result<Vec<String>, DbError> {
+    sqlx::query_scalar(
+        "SELECT name FROM codes WHERE tests NOT IN ('', '[]') ORDER BY name",
+    )
+    .fetch_all(&mut *db)
+    .await
+    .context(SqlxSnafu {
+        task: "getting tested codes",
+    })
+}
+
+/// Codes imported by `code`, directly or not.
+async fn get_libraries(
+    db: &mut SqliteConnection,
+    code: &Code,
+) -> Result<Vec<Code>, DbError> {
+    let mut libraries: Vec<Code> = vec![];
+    let mut pending = imports(&code.script);
+    while let Some(name) = pending.pop() {
+        if name == code.name || libraries.iter().any(|l| l.name == name) {
+            continue;
+        }
+        if let Some(library) = get_code_by_name(db, &name).await? {
+            pending.extend(imports(&library.script));
+            libraries.push(library);
+        }
+    }
+    Ok(libraries)
+}
+
+async fn insert_code(
+    db: &mut SqliteConnection,
+    code: &Code,
+) -> Result<(), DbError> {
+    sqlx::query(
+        r#"
+        INSERT INTO codes
+            (name, capabilities, script, attribute_schema,
+             completion_attribute, board_action, board_action_key, version,
+             library)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(&code.name)
+    .bind(&code.capabilities)
+    .bind(&code.script)
+    .bind(&code.attribute_schema)
+    .bind(&code.completion_attribute)
+    .bind(&code.board_action)
+    .bind(&code.board_action_key)
+    .bind(code.version)
+    .bind(code.library)
+    .execute(&mut *db)
+    .await
+    .context(SqlxSnafu {
+        task: "copying code to scratch database",
+    })?;
+    Ok(())
+}
+
+/// Runs a test case on a fresh in-memory database holding only the code
+/// and its libraries, returning what did not match.
+async fn run_case(
+    code: &Code,
+    libraries: &[Code],
+    test: &CodeTest,
+) -> Result<Vec<String>, DbError> {
+    let mut db = SqliteConnection::connect("sqlite::memory:").await.context(
+        SqlxSnafu {
+            task: "opening scratch database",
+        },
+    )?;
+    migrate_connection(&mut db, false)
+        .await
+        .context(SqlxSnafu {
+            task: "migrating scratch database",
+        })?;
+    for c in std::iter::once(code).chain(libraries) {
+        insert_code(&mut db, c).await?;
+    }
+    let id = create_note(
+        &mut db,
+        ROOT_NOTE_ID,
+        test.name.clone(),
+        String::new(),
+        Some(code.name.clone()),
+    )
+    .await?;
+    for (key, value) in &test.attributes {
+        set_attribute(&mut db, id, key, value).await?;
+    }
+
+    let mut failures = vec![];
+    // A runaway case fails instead of blocking the page or the CLI
+    let result = run_traced::<JsonValue>(
+        &mut db,
+        &limited_lua(TRIAL_TIME_LIMIT),
+        code.clone(),
+        &test.action,
+        id,
+        test.input.clone(),
+        &mut Trace::default(),
+    )
+    .await?;
+    if let Some(expected) = test.result.as_ref().filter(|e| **e != result) {
+        failures.push(format!("result: expected {expected}, got {result}"));
+    }
+    for (key, expected) in &test.expected_attributes {
+        let actual = get_attribute(&mut db, id, key).await?;
+        if *expected != actual {
+            let show = |v: &Option<AttributeValue>| match v {
+                Some(v) => format!("{v:?}"),
+                None => "nothing".to_string(),
+            };
+            failures.push(format!(
+                "attribute {key}: expected {}, got {}",
+                show(expected),
+                show(&actual)
+            ));
+        }
+    }
+    Ok(failures)
+}
+
+/// Runs every test case of a code against its current version. A case
+/// whose run fails reports the error as its failure.
+pub async fn run_code_tests(
+    db: &mut SqliteConnection,
+    name: &str,
+) -> Result<Vec<TestReport>, DbError> {
+    let code =
+        get_code_by_name(db, name)
+            .await?
+            .ok_or(DbError::CodeNotFound {
+                name: name.to_string(),
+            })?;
+    let tests = parse_tests(&get_code_tests(db, name).await?)?;
+    let libraries = get_libraries(db, &code).await?;
+    let mut reports = vec![];
+    for test in &tests {
+        let failures = run_case(&code, &libraries, test)
+            .await
+            .unwrap_or_else(|e| vec![e.to_string()]);
+        reports.push(TestReport {
+            name: test.name.clone(),
+            failures,
+        });
+    }
+    Ok(reports)
+}
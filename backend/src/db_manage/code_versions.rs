@@ -0,0 +1,338 @@
+use mlua::Lua;
+use rocket_db_pools::sqlx::FromRow;
+use rocket_db_pools::sqlx::{self};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+use snafu::ResultExt;
+use sqlx::{Acquire, SqliteConnection};
+
+use super::attributes::apply_attribute_defaults;
+use super::codes::{get_code_by_name, run_traced, validate_code};
+use super::dependencies::check_import_cycle;
+use super::errors::{DbError, SqlxSnafu};
+use super::logs::{create_execution_log, create_log};
+use super::notes::get_note;
+use super::trace::Trace;
+
+/// A past or current state of a code. Versions are immutable.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct CodeVersion {
+    pub code_name: String,
+    pub version: i64,
+    pub created_at: String,
+    pub author: String,
+    pub message: String,
+    pub capabilities: String,
+    pub script: String,
+    pub attribute_schema: String,
+    pub completion_attribute: String,
+    pub board_action: Option<String>,
+    pub board_action_key: Option<String>,
+}
+
+/// Snapshots the current content of a code as its next version.
+pub(crate) async fn record_code_version(
+    db: &mut SqliteConnection,
+    name: &str,
+    author: &str,
+    message: &str,
+) -> Result<i64, DbError> {
+    let version = sqlx::query_scalar::<_, i64>(
+        r#"
+        INSERT INTO code_versions
+            (code_name, version, author, message, capabilities, script,
+             attribute_schema, completion_attribute, board_action,
+             board_action_key)
+        SELECT name,
+               (SELECT COALESCE(MAX(version), 0) + 1 FROM code_versions
+                WHERE code_name = codes.name),
+               ?, ?, capabilities, script, attribute_schema,
+               completion_attribute, board_action, board_action_key
+        FROM codes
+        WHERE name = ?
+        RETURNING version
+        "#,
+    )
+    .bind(author)
+    .bind(message)
+    .bind(name)
+    .fetch_one(&mut *db)
+    .await
+    .context(SqlxSnafu {
+        task: "recording code version",
+    })?;
+    sqlx::query("UPDATE codes SET version = ? WHERE name = ?")
+        .bind(version)
+        .bind(name)
+        .execute(&mut *db)
+        .await
+        .context(SqlxSnafu {
+            task: "updating code version",
+        })?;
+    Ok(version)
+}
+
+/// Versions of a code, newest first.
+pub async fn get_code_versions(
+    db: &mut SqliteConnection,
+    name: &str,
+) -> Result<Vec<CodeVersion>, DbError> {
+    sqlx::query_as::<_, CodeVersion>(
+        r#"
+        SELECT code_name, version, created_at, author, message, capabilities,
+               script, attribute_schema, completion_attribute, board_action,
+               board_action_key
+        FROM code_versions
+        WHERE code_name = ?
+        ORDER BY version DESC
+        "#,
+    )
+    .bind(name)
+    .fetch_all(&mut *db)
+    .await
+    .context(SqlxSnafu {
+        task: "getting code versions",
+    })
+}
+
+pub async fn get_code_version(
+    db: &mut SqliteConnection,
+    name: &str,
+    version: i64,
+) -> Result<Option<CodeVersion>, DbError> {
+    sqlx::query_as::<_, CodeVersion>(
+        r#"
+        SELECT code_name, version, created_at, author, message, capabilities,
+               script, attribute_schema, completion_attribute, board_action,
+               board_action_key
+        FROM code_versions
+        WHERE code_name = ? AND version = ?
+        "#,
+    )
+    .bind(name)
+    .bind(version)
+    .fetch_optional(&mut *db)
+    .await
+    .context(SqlxSnafu {
+        task: "getting code version",
+    })
+}
+
+/// Makes the content of an older version current again. History is kept:
+/// the rollback is recorded as a new version. The old content is checked
+/// like an edit, as the codes around it may have changed since.
+pub async fn rollback_code(
+    db: &mut SqliteConnection,
+    name: &str,
+    version: i64,
+    author: &str,
+) -> Result<i64, DbError> {
+    let mut tx = db.begin().await.context(SqlxSnafu {
+        task: "beginning rollback",
+    })?;
+    let old = get_code_version(&mut tx, name, version).await?.ok_or(
+        DbError::CodeVersionNotFound {
+            name: name.to_string(),
+            version,
+        },
+    )?;
+    let library = get_code_by_name(&mut tx, name)
+        .await?
+        .ok_or(DbError::CodeNotFound {
+            name: name.to_string(),
+        })?
+        .library;
+    validate_code(
+        &old.capabilities,
+        &old.script,
+        &old.attribute_schema,
+        library,
+    )
+    .await?;
+    check_import_cycle(&mut tx, name, &old.script).await?;
+    sqlx::query(
+        r#"
+        UPDATE codes
+        SET capabilities = ?, script = ?, attribute_schema = ?,
+            completion_attribute = ?, board_action = ?,
+            board_action_key = ?
+        WHERE name = ?
+        "#,
+    )
+    .bind(&old.capabilities)
+    .bind(&old.script)
+    .bind(&old.attribute_schema)
+    .bind(&old.completion_attribute)
+    .bind(&old.board_action)
+    .bind(&old.board_action_key)
+    .bind(name)
+    .execute(&mut *tx)
+    .await
+    .context(SqlxSnafu {
+        task: "rolling back code",
+    })?;
+    let new_version = record_code_version(
+        &mut tx,
+        name,
+        author,
+        &format!("Rolled back to version {version}"),
+    )
+    .await?;
+    tx.commit().await.context(SqlxSnafu {
+        task: "committing rollback",
+    })?;
+    Ok(new_version)
+}
+
+/// Version a note is pinned to, `None` if it follows the current one.
+pub async fn get_pinned_version(
+    db: &mut SqliteConnection,
+    note_id: i64,
+) -> Result<Option<i64>, DbError> {
+    let version = sqlx::query_scalar::<_, Option<i64>>(
+        "SELECT code_version FROM notes WHERE id = ?",
+    )
+    .bind(note_id)
+    .fetch_optional(&mut *db)
+    .await
+    .context(SqlxSnafu {
+        task: "getting pinned version",
+    })?;
+    Ok(version.flatten())
+}
+
+/// Pins a note to a version of its code, or unpins it with `None`.
+pub async fn pin_note(
+    db: &mut SqliteConnection,
+    note_id: i64,
+    version: Option<i64>,
+) -> Result<(), DbError> {
+    let note = get_note(db, note_id)
+        .await?
+        .ok_or(DbError::NoteNotFound { id: note_id })?;
+    if let Some(version) = version {
+        let Some(name) = note.code_name else {
+            return Err(DbError::ExecutionError {
+                trace: format!("note {note_id} has no code to pin"),
+            });
+        };
+        if get_code_version(db, &name, version).await?.is_none() {
+            return Err(DbError::CodeVersionNotFound { name, version });
+        }
+    }
+    sqlx::query("UPDATE notes SET code_version = ? WHERE id = ?")
+        .bind(version)
+        .bind(note_id)
+        .execute(&mut *db)
+        .await
+        .context(SqlxSnafu {
+            task: "pinning code version",
+        })?;
+    let message = match version {
+        Some(version) => format!("Note {note_id} pinned to version {version}"),
+        None => format!("Note {note_id} follows the current code version"),
+    };
+    create_log(db, note_id, "info".to_string(), message, None).await?;
+    Ok(())
+}
+
+/// A note pinned to an older version than the current one of its code.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct OutdatedNote {
+    pub id: i64,
+    pub title: String,
+    pub code_version: i64,
+}
+
+pub async fn get_outdated_notes(
+    db: &mut SqliteConnection,
+    name: &str,
+) -> Result<Vec<OutdatedNote>, DbError> {
+    sqlx::query_as::<_, OutdatedNote>(
+        r#"
+        SELECT n.id, n.title, n.code_version
+        FROM notes n
+        JOIN codes c ON c.name = n.code_name
+        WHERE c.name = ? AND n.code_version < c.version
+          AND n.deleted_at IS NULL
+        ORDER BY n.code_version, n.id
+        "#,
+    )
+    .bind(name)
+    .fetch_all(&mut *db)
+    .await
+    .context(SqlxSnafu {
+        task: "getting outdated notes",
+    })
+}
+
+/// Moves a pinned note to the current version of its code. If the new
+/// script defines a `migrate` entry point, it runs on the note with
+/// `{ from = old, to = new }` to transform its attributes, under the
+/// capabilities of the new version. The note stays pinned.
+pub async fn upgrade_note(
+    db: &mut SqliteConnection,
+    note_id: i64,
+) -> Result<i64, DbError> {
+    let mut tx = db.begin().await.context(SqlxSnafu {
+        task: "beginning upgrade",
+    })?;
+    let note = get_note(&mut tx, note_id)
+        .await?
+        .ok_or(DbError::NoteNotFound { id: note_id })?;
+    let (Some(name), Some(from)) =
+        (note.code_name, get_pinned_version(&mut tx, note_id).await?)
+    else {
+        return Err(DbError::ExecutionError {
+            trace: format!("note {note_id} is not pinned to a code version"),
+        });
+    };
+    let code = get_code_by_name(&mut tx, &name)
+        .await?
+        .ok_or(DbError::CodeNotFound { name: name.clone() })?;
+    let to = code.version;
+    if from >= to {
+        return Err(DbError::ExecutionError {
+            trace: format!("note {note_id} already runs version {from}"),
+        });
+    }
+    // The migration runs against the new attribute schema
+    sqlx::query("UPDATE notes SET code_version = ? WHERE id = ?")
+        .bind(to)
+        .bind(note_id)
+        .execute(&mut *tx)
+        .await
+        .context(SqlxSnafu {
+            task: "upgrading pinned version",
+        })?;
+    let mut migration = Trace::default();
+    let trace = match run_traced::<JsonValue>(
+        &mut tx,
+        &Lua::new(),
+        code,
+        "migrate",
+        note_id,
+        json!({ "from": from, "to": to }),
+        &mut migration,
+    )
+    .await
+    {
+        Ok(_) => Some(migration.to_blob()),
+        // Codes without a migration only need the new defaults
+        Err(DbError::NoEntryPoint { .. }) => None,
+        Err(e) => return Err(e),
+    };
+    apply_attribute_defaults(&mut tx, note_id).await?;
+    create_execution_log(
+        &mut tx,
+        note_id,
+        format!("Note {note_id} upgraded from version {from} to {to}"),
+        to,
+        trace,
+    )
+    .await?;
+    tx.commit().await.context(SqlxSnafu {
+        task: "committing upgrade",
+    })?;
+    Ok(to)
+}
@@ -2,52 +2,156 @@ use chrono::NaiveDate;
 use rocket_db_pools::sqlx::FromRow;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use snafu::ResultExt;
-use sqlx::SqliteConnection;
+use sqlx::{Acquire, SqliteConnection};
 use std::collections::HashMap;
 use std::fmt::Debug;
+use std::time::Instant;
 
 use crate::{
     api::codes::{Action, Date, FormContainer, FormType, Value},
-    db_manage::attributes::{get_attribute, set_attribute},
-    db_manage::notes::create_note,
+    db_manage::attributes::{get_attribute, set_attribute, AttributeValue},
+    db_manage::notes::{copy_note, create_note, reorder_note},
+    db_manage::progress::get_progress,
 };
-use mlua::{Lua, LuaSerdeExt, Thread, ThreadStatus};
+use mlua::{Lua, LuaSerdeExt, ThreadStatus};
+use rocket::tokio::task::spawn_blocking;
 use rocket_db_pools::Connection;
 use serde_json::Value as JsonValue;
 
-use crate::db_manage::Db;
+use crate::db_manage::{Db, ROOT_NOTE_ID};
 
 use super::{
+    board::DEFAULT_BOARD_KEY,
+    code_versions::record_code_version,
+    dependencies::{
+        check_import_cycle, get_importers, get_pinned_importers, rename_imports,
+    },
     errors::{DbError, LuaSnafu, SqlxSnafu},
     get_child_notes,
-    logs::create_log,
+    logs::create_execution_log,
+    revisions::record_field_change,
+    trace::{Trace, TraceError, TraceStep},
+    trash::check_not_trashed,
+    validation::{check_code, locate_lua_error},
 };
 
 #[allow(dead_code)]
-#[derive(Debug, FromRow, Serialize, Deserialize)]
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
 pub struct Code {
     pub name: String,
     pub capabilities: String,
     pub script: String,
+    pub attribute_schema: String,
+    pub completion_attribute: String,
+    pub board_action: Option<String>,
+    /// Board attribute whose moves run the board action
+    pub board_action_key: Option<String>,
+    pub version: i64,
+    /// Imported by other codes rather than used by notes
+    pub library: bool,
+}
+
+/// Schema of codes declaring no attributes, as migration 003 sets it
+pub const DEFAULT_ATTRIBUTE_SCHEMA: &str = "[]";
+/// Completion attribute of codes not choosing one, as migration 009 sets it
+pub const DEFAULT_COMPLETION_ATTRIBUTE: &str = "done";
+
+fn check_completion_attribute(key: &str) -> Result<(), DbError> {
+    if key.trim().is_empty() {
+        return Err(DbError::InvalidAttribute {
+            key: key.to_string(),
+            reason: "the completion attribute needs a name".to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Checks a code on a blocking task, as its script's top level runs.
+pub(crate) async fn validate_code(
+    capabilities: &str,
+    script: &str,
+    attribute_schema: &str,
+    library: bool,
+) -> Result<(), DbError> {
+    let (capabilities, script, attribute_schema) = (
+        capabilities.to_string(),
+        script.to_string(),
+        attribute_schema.to_string(),
+    );
+    let problems = spawn_blocking(move || {
+        check_code(&capabilities, &script, &attribute_schema, library)
+    })
+    .await
+    .map_err(|e| DbError::ExecutionError {
+        trace: format!("checking code: {e}"),
+    })?;
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(DbError::InvalidCode { problems })
+    }
+}
+
+/// Form a board runs, instead of setting `key` itself, when a card of a
+/// note using the code is moved on a board grouping by `key`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoardAction {
+    pub key: String,
+    pub label: String,
 }
 
+/// Blank board actions mean the board sets the attribute itself. A blank
+/// key is the default board key.
+pub fn parse_board_action(
+    key: Option<String>,
+    label: Option<String>,
+) -> Option<BoardAction> {
+    let label = label
+        .map(|l| l.trim().to_string())
+        .filter(|l| !l.is_empty())?;
+    let key = key
+        .map(|k| k.trim().to_string())
+        .filter(|k| !k.is_empty())
+        .unwrap_or(DEFAULT_BOARD_KEY.to_string());
+    Some(BoardAction { key, label })
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn create_code(
-    db: &mut Connection<Db>,
+    db: &mut SqliteConnection,
     name: String,
     capabilities: String,
     script: String,
+    attribute_schema: String,
+    completion_attribute: String,
+    board_action: Option<BoardAction>,
+    library: bool,
+    author: &str,
 ) -> Result<String, DbError> {
+    validate_code(&capabilities, &script, &attribute_schema, library).await?;
+    check_completion_attribute(&completion_attribute)?;
+    let mut tx = db.begin().await.context(SqlxSnafu {
+        task: "beginning code creation",
+    })?;
+    check_import_cycle(&mut tx, &name, &script).await?;
     sqlx::query(
         r#"
-    INSERT INTO codes (name, capabilities, script)
-    VALUES (?, ?, ?)
+    INSERT INTO codes
+        (name, capabilities, script, attribute_schema, completion_attribute,
+         board_action, board_action_key, library)
+    VALUES (?, ?, ?, ?, ?, ?, ?, ?)
     RETURNING name
         "#,
     )
     .bind(&name)
     .bind(capabilities)
     .bind(script)
-    .execute(&mut ***db)
+    .bind(attribute_schema)
+    .bind(completion_attribute.trim())
+    .bind(board_action.as_ref().map(|a| &a.label))
+    .bind(board_action.as_ref().map(|a| &a.key))
+    .bind(library)
+    .execute(&mut *tx)
     .await
     .context(SqlxSnafu {
         task: "creating code",
@@ -55,24 +159,43 @@ pub async fn create_code(
     let new_code_id: (String,) =
         sqlx::query_as("SELECT name FROM codes WHERE name = ?")
             .bind(&name)
-            .fetch_one(&mut ***db)
+            .fetch_one(&mut *tx)
             .await
             .context(SqlxSnafu {
                 task: "getting created code",
             })?;
+    record_code_version(&mut tx, &name, author, "Created").await?;
+    tx.commit().await.context(SqlxSnafu {
+        task: "committing code creation",
+    })?;
     Ok(new_code_id.0)
 }
 
+/// Code a note runs: the version it is pinned to, or the current one.
 pub async fn get_code(
     db: &mut SqliteConnection,
     note_id: i64,
 ) -> Result<Option<Code>, DbError> {
     let code = sqlx::query_as::<_, Code>(
         r#"
-        SELECT codes.name, codes.capabilities, codes.script
-        FROM notes
-        JOIN codes ON codes.name = notes.code_name
-        WHERE notes.id = ?
+        SELECT c.name,
+               COALESCE(v.capabilities, c.capabilities) AS capabilities,
+               COALESCE(v.script, c.script) AS script,
+               COALESCE(v.attribute_schema, c.attribute_schema)
+                 AS attribute_schema,
+               COALESCE(v.completion_attribute, c.completion_attribute)
+                 AS completion_attribute,
+               CASE WHEN v.version IS NULL THEN c.board_action
+                    ELSE v.board_action END AS board_action,
+               CASE WHEN v.version IS NULL THEN c.board_action_key
+                    ELSE v.board_action_key END AS board_action_key,
+               COALESCE(v.version, c.version) AS version,
+               c.library
+        FROM notes n
+        JOIN codes c ON c.name = n.code_name
+        LEFT JOIN code_versions v
+          ON v.code_name = c.name AND v.version = n.code_version
+        WHERE n.id = ?
         "#,
     )
     .bind(note_id)
@@ -84,30 +207,282 @@ pub async fn get_code(
     Ok(code)
 }
 
+/// Replaces the content of a code, recording it as a new version.
+#[allow(clippy::too_many_arguments)]
 pub async fn edit_code(
-    db: &mut Connection<Db>,
+    db: &mut SqliteConnection,
     name: &str,
     new_capabilities: &str,
     new_script: &str,
-) -> Result<(), DbError> {
-    sqlx::query(
+    new_attribute_schema: &str,
+    new_completion_attribute: &str,
+    new_board_action: Option<BoardAction>,
+    library: bool,
+    author: &str,
+    message: &str,
+) -> Result<i64, DbError> {
+    validate_code(new_capabilities, new_script, new_attribute_schema, library)
+        .await?;
+    check_completion_attribute(new_completion_attribute)?;
+    let mut tx = db.begin().await.context(SqlxSnafu {
+        task: "beginning code edition",
+    })?;
+    if library && !get_code_users(&mut tx, name).await?.is_empty() {
+        return Err(DbError::LibraryUsedByNote {
+            name: name.to_string(),
+        });
+    }
+    if !library {
+        let importers = get_importers(&mut tx, name).await?;
+        if !importers.is_empty() {
+            return Err(DbError::LibraryImported {
+                name: name.to_string(),
+                importers,
+            });
+        }
+    }
+    check_import_cycle(&mut tx, name, new_script).await?;
+    let updated = sqlx::query(
         r#"
         UPDATE codes
-        SET capabilities = ?, script = ?
+        SET capabilities = ?, script = ?, attribute_schema = ?,
+            completion_attribute = ?, board_action = ?,
+            board_action_key = ?, library = ?
         WHERE name = ?
         "#,
     )
     .bind(new_capabilities)
     .bind(new_script)
+    .bind(new_attribute_schema)
+    .bind(new_completion_attribute.trim())
+    .bind(new_board_action.as_ref().map(|a| &a.label))
+    .bind(new_board_action.as_ref().map(|a| &a.key))
+    .bind(library)
     .bind(name)
-    .execute(&mut ***db)
+    .execute(&mut *tx)
     .await
     .context(SqlxSnafu {
         task: "editing code",
     })?;
+    if updated.rows_affected() == 0 {
+        return Err(DbError::CodeNotFound {
+            name: name.to_string(),
+        });
+    }
+    let message = match message.trim() {
+        "" => "Edited",
+        message => message,
+    };
+    let version = record_code_version(&mut tx, name, author, message).await?;
+    tx.commit().await.context(SqlxSnafu {
+        task: "committing code edition",
+    })?;
+    Ok(version)
+}
+
+/// A note bound to a code, as listed before renaming or deleting it.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct CodeUser {
+    pub id: i64,
+    pub title: String,
+    pub in_trash: bool,
+}
+
+/// Notes bound to a code, trashed ones included.
+pub async fn get_code_users(
+    db: &mut SqliteConnection,
+    name: &str,
+) -> Result<Vec<CodeUser>, DbError> {
+    sqlx::query_as::<_, CodeUser>(
+        r#"
+        SELECT id, title, deleted_at IS NOT NULL AS in_trash
+        FROM notes
+        WHERE code_name = ?
+        ORDER BY id
+        "#,
+    )
+    .bind(name)
+    .fetch_all(&mut *db)
+    .await
+    .context(SqlxSnafu {
+        task: "getting notes using code",
+    })
+}
+
+async fn record_code_change(
+    db: &mut SqliteConnection,
+    name: &str,
+    new_name: Option<&str>,
+) -> Result<(), DbError> {
+    for user in get_code_users(db, name).await? {
+        record_field_change(db, user.id, "code_name", Some(name), new_name)
+            .await?;
+    }
     Ok(())
 }
 
+/// Renames a code, together with the notes using it, its versions and the
+/// imports of it in other codes, which get a new version. Returns the
+/// codes whose imports were rewritten. Refused while notes are pinned to
+/// a version importing the code, as versions cannot be rewritten.
+pub async fn rename_code(
+    db: &mut SqliteConnection,
+    name: &str,
+    new_name: &str,
+    author: &str,
+) -> Result<Vec<String>, DbError> {
+    let new_name = new_name.trim();
+    if new_name.is_empty() {
+        return Err(DbError::ParseError {
+            when: "renaming code: the new name is empty".to_string(),
+        });
+    }
+    let mut tx = db.begin().await.context(SqlxSnafu {
+        task: "beginning code rename",
+    })?;
+    let pinned = get_pinned_importers(&mut tx, name).await?;
+    if !pinned.is_empty() {
+        return Err(DbError::LibraryPinned {
+            name: name.to_string(),
+            pinned,
+        });
+    }
+    // Notes reference the name, so the code is copied before the old row
+    // goes away
+    let copied = sqlx::query(
+        r#"
+        INSERT INTO codes
+            (name, capabilities, script, attribute_schema,
+             completion_attribute, board_action, board_action_key, version,
+             tests, library)
+        SELECT ?, capabilities, script, attribute_schema,
+               completion_attribute, board_action, board_action_key, version,
+               tests, library
+        FROM codes
+        WHERE name = ?
+        "#,
+    )
+    .bind(new_name)
+    .bind(name)
+    .execute(&mut *tx)
+    .await
+    .context(SqlxSnafu {
+        task: "copying renamed code",
+    })?;
+    if copied.rows_affected() == 0 {
+        return Err(DbError::CodeNotFound {
+            name: name.to_string(),
+        });
+    }
+    record_code_change(&mut tx, name, Some(new_name)).await?;
+    for statement in [
+        "UPDATE notes SET code_name = ? WHERE code_name = ?",
+        "UPDATE code_versions SET code_name = ? WHERE code_name = ?",
+    ] {
+        sqlx::query(statement)
+            .bind(new_name)
+            .bind(name)
+            .execute(&mut *tx)
+            .await
+            .context(SqlxSnafu {
+                task: "moving references to renamed code",
+            })?;
+    }
+    sqlx::query("DELETE FROM codes WHERE name = ?")
+        .bind(name)
+        .execute(&mut *tx)
+        .await
+        .context(SqlxSnafu {
+            task: "removing old code name",
+        })?;
+
+    let importers = get_importers(&mut tx, name).await?;
+    for importer in &importers {
+        let code = get_code_by_name(&mut tx, importer).await?.ok_or(
+            DbError::CodeNotFound {
+                name: importer.clone(),
+            },
+        )?;
+        sqlx::query("UPDATE codes SET script = ? WHERE name = ?")
+            .bind(rename_imports(&code.script, name, new_name))
+            .bind(importer)
+            .execute(&mut *tx)
+            .await
+            .context(SqlxSnafu {
+                task: "rewriting imports",
+            })?;
+        record_code_version(
+            &mut tx,
+            importer,
+            author,
+            &format!("Import of {name} renamed to {new_name}"),
+        )
+        .await?;
+    }
+    tx.commit().await.context(SqlxSnafu {
+        task: "committing code rename",
+    })?;
+    Ok(importers)
+}
+
+/// Deletes a code and its versions. Notes using it are left without code.
+/// Refused while other codes, or versions notes are pinned to, import it.
+pub async fn delete_code(
+    db: &mut SqliteConnection,
+    name: &str,
+) -> Result<usize, DbError> {
+    let mut tx = db.begin().await.context(SqlxSnafu {
+        task: "beginning code deletion",
+    })?;
+    let importers = get_importers(&mut tx, name).await?;
+    if !importers.is_empty() {
+        return Err(DbError::CodeImported {
+            name: name.to_string(),
+            importers,
+        });
+    }
+    let pinned = get_pinned_importers(&mut tx, name).await?;
+    if !pinned.is_empty() {
+        return Err(DbError::LibraryPinned {
+            name: name.to_string(),
+            pinned,
+        });
+    }
+    record_code_change(&mut tx, name, None).await?;
+    let unbound = sqlx::query(
+        "UPDATE notes SET code_name = NULL, code_version = NULL WHERE code_name = ?",
+    )
+    .bind(name)
+    .execute(&mut *tx)
+    .await
+    .context(SqlxSnafu {
+        task: "unbinding notes from code",
+    })?;
+    sqlx::query("DELETE FROM code_versions WHERE code_name = ?")
+        .bind(name)
+        .execute(&mut *tx)
+        .await
+        .context(SqlxSnafu {
+            task: "deleting code versions",
+        })?;
+    let deleted = sqlx::query("DELETE FROM codes WHERE name = ?")
+        .bind(name)
+        .execute(&mut *tx)
+        .await
+        .context(SqlxSnafu {
+            task: "deleting code",
+        })?;
+    if deleted.rows_affected() == 0 {
+        return Err(DbError::CodeNotFound {
+            name: name.to_string(),
+        });
+    }
+    tx.commit().await.context(SqlxSnafu {
+        task: "committing code deletion",
+    })?;
+    Ok(unbound.rows_affected() as usize)
+}
+
 pub async fn get_all_code_names(
     db: &mut Connection<Db>,
 ) -> Result<Vec<String>, sqlx::Error> {
@@ -118,6 +493,18 @@ pub async fn get_all_code_names(
     Ok(names)
 }
 
+/// Codes a note can use, libraries left out.
+pub async fn get_note_code_names(
+    db: &mut Connection<Db>,
+) -> Result<Vec<String>, sqlx::Error> {
+    let names = sqlx::query_scalar::<_, String>(
+        "SELECT name FROM codes WHERE NOT library ORDER BY name",
+    )
+    .fetch_all(&mut ***db)
+    .await?;
+    Ok(names)
+}
+
 pub fn parse_fields(
     form_type: &FormType,
     inputs: &HashMap<String, String>,
@@ -131,7 +518,7 @@ pub fn parse_fields(
             value
                 .parse::<u64>()
                 .map(Value::UInt)
-                .map_err(|e| format!("Invalid integer: {e}").into())
+                .map_err(|e| format!("Invalid integer: {e}"))
         }
         FormType::Date => {
             let value = inputs
@@ -139,7 +526,13 @@ pub fn parse_fields(
                 .ok_or(format!("Missing field: {:?}", prefix))?;
             NaiveDate::parse_from_str(value, "%Y-%m-%d")
                 .map(|d| Value::Date(Date(d)))
-                .map_err(|e| format!("Invalid date: {e}").into())
+                .map_err(|e| format!("Invalid date: {e}"))
+        }
+        FormType::Text => {
+            let value = inputs
+                .get(prefix)
+                .ok_or(format!("Missing field: {:?}", prefix))?;
+            Ok(Value::Text(value.clone()))
         }
         FormType::Empty => Ok(Value::Empty),
     }
@@ -154,17 +547,30 @@ enum Command<T: Debug> {
     SetAttribute {
         id: i64,
         key: String,
-        value: String,
+        value: AttributeValue,
     },
     GetAttribute {
         id: i64,
         key: String,
     },
+    GetProgress {
+        id: i64,
+    },
     CreateChild {
-        parent_id: Option<i64>,
+        #[serde(default = "root_note_id")]
+        parent_id: i64,
         title: String,
         description: String,
         code_name: Option<String>,
+        // Index among the siblings, last if omitted
+        position: Option<usize>,
+    },
+    CopyNote {
+        id: i64,
+        #[serde(default = "root_note_id")]
+        parent_id: i64,
+        #[serde(default)]
+        with_logs: bool,
     },
 }
 
@@ -181,20 +587,73 @@ pub enum Capabilities {
     SysLog,
     GetAttribute(Range),
     SetAttribute(Range),
+    GetProgress(Range),
     CreateChild(Range),
+    CopyNote(Range),
+}
+
+impl Capabilities {
+    /// One capability of each kind, in declaration order.
+    pub const ALL: [Capabilities; 6] = [
+        Capabilities::SysLog,
+        Capabilities::GetAttribute(Range::Own),
+        Capabilities::SetAttribute(Range::Own),
+        Capabilities::GetProgress(Range::Own),
+        Capabilities::CreateChild(Range::Own),
+        Capabilities::CopyNote(Range::Own),
+    ];
+
+    /// Name of the command this capability allows.
+    pub fn command(&self) -> &'static str {
+        match self {
+            Capabilities::SysLog => "SysLog",
+            Capabilities::GetAttribute(_) => "GetAttribute",
+            Capabilities::SetAttribute(_) => "SetAttribute",
+            Capabilities::GetProgress(_) => "GetProgress",
+            Capabilities::CreateChild(_) => "CreateChild",
+            Capabilities::CopyNote(_) => "CopyNote",
+        }
+    }
+}
+
+/// Commands that need a capability, named like the capability itself.
+pub fn gated_commands() -> impl Iterator<Item = &'static str> {
+    Capabilities::ALL.iter().map(Capabilities::command)
+}
+
+/// Parent of notes created or copied with a nil parent, which used to
+/// leave them without one.
+fn root_note_id() -> i64 {
+    ROOT_NOTE_ID
 }
 
 fn within_range(
     _db: &mut SqliteConnection,
     range: &Range,
     id: i64,
-    _target_id: Option<i64>,
+    target_id: i64,
 ) -> bool {
     match range {
-        Range::Own => matches!(Some(id), _target_id),
+        Range::Own => id == target_id,
     }
 }
 
+/// Whether `command` may run with `capabilities`. Getting the id,
+/// importing and returning need no capability.
+fn allowed<R: Debug>(
+    db: &mut SqliteConnection,
+    id: i64,
+    command: &Command<R>,
+    capabilities: &[Capabilities],
+) -> bool {
+    matches!(
+        command,
+        Command::GetId | Command::Import(_) | Command::Result(_)
+    ) || capabilities
+        .iter()
+        .any(|c| authorized::<R>(db, id, command, c))
+}
+
 fn authorized<R: Debug>(
     db: &mut SqliteConnection,
     id: i64,
@@ -208,21 +667,41 @@ fn authorized<R: Debug>(
         Command::SysLog(_) => matches!(capability, Capabilities::SysLog),
         Command::GetAttribute { id: target_id, .. } => {
             if let Capabilities::GetAttribute(r) = capability {
-                return within_range(db, r, id, Some(*target_id));
+                within_range(db, r, id, *target_id)
             } else {
                 false
             }
         }
         Command::SetAttribute { id: target_id, .. } => {
             if let Capabilities::SetAttribute(r) = capability {
-                return within_range(db, r, id, Some(*target_id));
+                within_range(db, r, id, *target_id)
+            } else {
+                false
+            }
+        }
+        Command::GetProgress { id: target_id } => {
+            if let Capabilities::GetProgress(r) = capability {
+                within_range(db, r, id, *target_id)
             } else {
                 false
             }
         }
         Command::CreateChild { parent_id, .. } => {
             if let Capabilities::CreateChild(r) = capability {
-                return within_range(db, r, id, *parent_id);
+                within_range(db, r, id, *parent_id)
+            } else {
+                false
+            }
+        }
+        Command::CopyNote {
+            id: source_id,
+            parent_id,
+            ..
+        } => {
+            // Both ends, so that no note is copied to where it can be read
+            if let Capabilities::CopyNote(r) = capability {
+                within_range(db, r, id, *source_id)
+                    && within_range(db, r, id, *parent_id)
             } else {
                 false
             }
@@ -230,7 +709,7 @@ fn authorized<R: Debug>(
     }
 }
 
-pub async fn run<R: Debug>(
+pub async fn run<R>(
     db: &mut SqliteConnection,
     code: Code,
     command_name: &str,
@@ -238,106 +717,363 @@ pub async fn run<R: Debug>(
     arguments: JsonValue,
 ) -> Result<R, DbError>
 where
-    R: DeserializeOwned,
+    R: Debug + DeserializeOwned,
 {
+    let mut trace = Trace::default();
     let lua = Lua::new();
+    run_traced(db, &lua, code, command_name, id, arguments, &mut trace).await
+}
+
+/// Like [`run`] in the given Lua state, recording in `trace` the commands
+/// the script issues with their timings, and the result or the error
+/// ending the run.
+pub async fn run_traced<R>(
+    db: &mut SqliteConnection,
+    lua: &Lua,
+    code: Code,
+    command_name: &str,
+    id: i64,
+    arguments: JsonValue,
+    trace: &mut Trace,
+) -> Result<R, DbError>
+where
+    R: Debug + DeserializeOwned,
+{
+    *trace = Trace {
+        code_name: code.name.clone(),
+        code_version: code.version,
+        note_id: id,
+        entry_point: command_name.to_string(),
+        arguments: arguments.clone(),
+        ..Trace::default()
+    };
+    let start = Instant::now();
+    let outcome =
+        run_steps(db, lua, code, command_name, id, arguments, trace, start)
+            .await;
+    if let Err(e) = &outcome {
+        trace.error = Some(TraceError::from(e));
+    }
+    trace.duration = start.elapsed().as_micros() as u64;
+    outcome
+}
+
+/// A Lua error raised while loading or running a script.
+fn script_error(e: mlua::Error) -> DbError {
+    let text = match &e {
+        mlua::Error::SyntaxError { message, .. } => message.clone(),
+        mlua::Error::RuntimeError(message) => message.clone(),
+        mlua::Error::CallbackError { cause, .. } => cause.to_string(),
+        other => other.to_string(),
+    };
+    let (text, traceback) = match text.split_once("\nstack traceback:\n") {
+        Some((text, traceback)) => (text, Some(traceback.to_string())),
+        None => (text.as_str(), None),
+    };
+    match locate_lua_error(text) {
+        Some((chunk, line, message)) => DbError::ScriptError {
+            chunk: Some(chunk.to_string()),
+            line: Some(line),
+            message: message.to_string(),
+            traceback,
+        },
+        None => DbError::ScriptError {
+            chunk: None,
+            line: None,
+            message: text.to_string(),
+            traceback,
+        },
+    }
+}
+
+fn to_json(value: &mlua::Value) -> String {
+    serde_json::to_string(value).unwrap_or_else(|_| format!("{value:?}"))
+}
+
+/// What the trace shows of a module: the names it exports.
+fn describe_module(module: &mlua::Value) -> JsonValue {
+    match module {
+        mlua::Value::Table(table) => {
+            let mut names: Vec<String> = table
+                .pairs::<String, mlua::Value>()
+                .filter_map(|pair| pair.ok().map(|(name, _)| name))
+                .collect();
+            names.sort();
+            names.into()
+        }
+        other => serde_json::to_value(other).unwrap_or_default(),
+    }
+}
+
+/// Loads the library `name`, returning the value its script returns or,
+/// if it returns nothing, the table of what it defined. Libraries run in
+/// their own environment, and their top level may only yield imports of
+/// other libraries. Each library is loaded once per run and kept in
+/// `modules`.
+///
+/// Libraries written before they could return a table define globals
+/// for their importers. So that they keep working, what a library
+/// returning nothing defines is also copied to the shared globals.
+async fn import_module(
+    db: &mut SqliteConnection,
+    lua: &Lua,
+    name: &str,
+    modules: &mut HashMap<String, mlua::Value>,
+) -> Result<mlua::Value, DbError> {
+    if let Some(module) = modules.get(name) {
+        return Ok(module.clone());
+    }
+    // Libraries being loaded, each waiting for the one after it
+    let mut loading: Vec<(String, mlua::Thread, mlua::Table)> = vec![];
+    let mut next = Some(name.to_string());
+    let mut value = mlua::Value::Nil;
+    loop {
+        if let Some(name) = next.take() {
+            if let Some(start) = loading.iter().position(|(n, ..)| *n == name) {
+                let mut cycle: Vec<String> =
+                    loading[start..].iter().map(|(n, ..)| n.clone()).collect();
+                cycle.push(name);
+                return Err(DbError::ImportCycle { cycle });
+            }
+            let code = get_code_by_name(db, &name)
+                .await?
+                .ok_or(DbError::LibNotFound { name: name.clone() })?;
+            if !code.library {
+                return Err(DbError::NotALibrary { name });
+            }
+            let environment = module_environment(lua).context(LuaSnafu {
+                task: format!("preparing library {name}"),
+            })?;
+            let function = lua
+                .load(&code.script)
+                .set_name(format!("={name}"))
+                .set_environment(environment.clone())
+                .into_function()
+                .map_err(script_error)?;
+            let thread = lua.create_thread(function).context(LuaSnafu {
+                task: format!("loading library {name}"),
+            })?;
+            loading.push((name, thread, environment));
+            value = mlua::Value::Nil;
+        }
+        let (name, thread, environment) = loading.last().unwrap().clone();
+        let yielded: mlua::Value =
+            thread.resume(value).map_err(script_error)?;
+        if thread.status() != ThreadStatus::Resumable {
+            let module = match yielded {
+                mlua::Value::Nil => {
+                    share_globals(lua, &environment).context(LuaSnafu {
+                        task: format!("sharing globals of library {name}"),
+                    })?;
+                    mlua::Value::Table(environment)
+                }
+                module => module,
+            };
+            modules.insert(name, module.clone());
+            loading.pop();
+            if loading.is_empty() {
+                return Ok(module);
+            }
+            value = module;
+            continue;
+        }
+        match lua.from_value::<Command<JsonValue>>(yielded.clone()) {
+            Ok(Command::Import(import)) => match modules.get(&import) {
+                Some(module) => value = module.clone(),
+                None => {
+                    value = mlua::Value::Nil;
+                    next = Some(import);
+                }
+            },
+            _ => {
+                return Err(DbError::InvalidCommand {
+                    command: to_json(&yielded),
+                    reason: format!(
+                        "library {name} can only import other libraries \
+                         while loading"
+                    ),
+                })
+            }
+        }
+    }
+}
+
+/// Copies what a library defined to the shared globals.
+fn share_globals(lua: &Lua, environment: &mlua::Table) -> mlua::Result<()> {
+    let globals = lua.globals();
+    for pair in environment.pairs::<mlua::Value, mlua::Value>() {
+        let (key, value) = pair?;
+        globals.raw_set(key, value)?;
+    }
+    Ok(())
+}
+
+/// A table for a library's globals, falling back to the shared ones.
+fn module_environment(lua: &Lua) -> mlua::Result<mlua::Table> {
+    let environment = lua.create_table()?;
+    let fallback = lua.create_table()?;
+    fallback.set("__index", lua.globals())?;
+    environment.set_metatable(Some(fallback));
+    Ok(environment)
+}
+
+/// The value a script returned, either plain or as `{ Result = ... }`.
+fn returned_value(value: mlua::Value) -> mlua::Value {
+    let wrapped = match &value {
+        mlua::Value::Table(table)
+            if table.pairs::<mlua::Value, mlua::Value>().count() == 1 =>
+        {
+            table
+                .get::<mlua::Value>("Result")
+                .ok()
+                .filter(|r| !r.is_nil())
+        }
+        _ => None,
+    };
+    wrapped.unwrap_or(value)
+}
 
+/// Runs the coroutine `command_name` of `code`. Each value it yields is a
+/// command, carried out and answered by resuming the coroutine; the
+/// value it returns is the result. Yielding `{ Result = ... }` also ends
+/// the run. An import is answered with the library's exports.
+#[allow(clippy::too_many_arguments)]
+async fn run_steps<R>(
+    db: &mut SqliteConnection,
+    lua: &Lua,
+    code: Code,
+    command_name: &str,
+    id: i64,
+    arguments: JsonValue,
+    trace: &mut Trace,
+    start: Instant,
+) -> Result<R, DbError>
+where
+    R: Debug + DeserializeOwned,
+{
     let capabilities: Vec<Capabilities> =
         serde_json::from_str(code.capabilities.as_str()).map_err(|e| {
             DbError::ParseError {
                 when: format!("loading capabilities: {e}"),
             }
         })?;
-    //println!("{}", code.script);
-    lua.load(code.script).exec().context(LuaSnafu {
-        task: "loading code",
-    })?;
-    let globals = lua.globals();
-    let thread: Thread = globals.get(command_name).context(LuaSnafu {
-        task: "getting forms",
-    })?;
+    // Named after the code, so that errors read `name:line`
+    lua.load(code.script)
+        .set_name(format!("={}", code.name))
+        .exec()
+        .map_err(script_error)?;
+    let thread = match lua.globals().get::<mlua::Value>(command_name) {
+        Ok(mlua::Value::Thread(thread)) => thread,
+        _ => {
+            return Err(DbError::NoEntryPoint {
+                name: command_name.to_string(),
+            })
+        }
+    };
     let arg_as_value: mlua::Value = lua.to_value(&arguments).unwrap();
+    // Libraries imported so far, and the one the script gets back next
+    let mut modules: HashMap<String, mlua::Value> = HashMap::new();
+    let mut imported: Option<mlua::Value> = None;
     let mut result: mlua::Value =
-        thread.resume(arg_as_value).context(LuaSnafu {
-            task: "first resuming",
-        })?;
+        thread.resume(arg_as_value).map_err(script_error)?;
     loop {
+        if thread.status() != ThreadStatus::Resumable {
+            let value = returned_value(result);
+            trace.result = serde_json::to_value(&value).ok();
+            return lua.from_value(value.clone()).map_err(|e| {
+                DbError::InvalidResult {
+                    result: to_json(&value),
+                    reason: e.to_string(),
+                }
+            });
+        }
         let command: Command<R> =
             lua.from_value(result.clone()).map_err(|e| {
-                DbError::ParseError {
-                    when: format!(
-                        "deserializing command {:?}: {}",
-                        serde_json::to_string(&result),
-                        e
-                    ),
+                DbError::InvalidCommand {
+                    command: to_json(&result),
+                    reason: e.to_string(),
                 }
             })?;
-        if !capabilities
-            .iter()
-            .any(|c| authorized::<R>(db, id, &command, c))
-        {
-            return Err(DbError::ExecutionError {
-                trace: format!(
-                    "command {command:?} not authorized in {capabilities:?}"
-                )
-                .to_string(),
+        if !allowed(db, id, &command, &capabilities) {
+            return Err(DbError::Unauthorized {
+                command: format!("{command:?}"),
+                capabilities: code.capabilities.clone(),
             });
         }
+        let at = start.elapsed();
         let response: JsonValue = match command {
             Command::GetId => id.into(),
             Command::Import(name) => {
-                let code: Code = get_code_by_name(db, &name)
-                    .await?
-                    .ok_or(DbError::LibNotFound { name: name.clone() })?;
-                let script = code.script;
-                println!("{:}", script);
-                lua.load(&script).exec().context(LuaSnafu {
-                    task: format!("loading requirement {name}"),
-                })?;
-                ().into()
+                let module =
+                    import_module(db, lua, &name, &mut modules).await?;
+                let described = describe_module(&module);
+                imported = Some(module);
+                described
+            }
+            Command::Result(a) => {
+                trace.result = serde_json::to_value(&result)
+                    .ok()
+                    .and_then(|r| r.get("Result").cloned());
+                return Ok(a);
             }
-            Command::Result(a) => return Ok(a),
             Command::SysLog(s) => {
                 println!("{s}");
                 ().into()
             }
             Command::SetAttribute { id, key, value } => {
-                set_attribute(db, id, &key.clone(), &value.clone()).await?;
+                set_attribute(db, id, &key, &value).await?;
                 ().into()
             }
             Command::GetAttribute { id, key } => {
-                get_attribute(db, id, &key.clone()).await?.into()
+                let value = get_attribute(db, id, &key).await?;
+                serde_json::to_value(value).map_err(|e| {
+                    DbError::ParseError {
+                        when: format!("serializing attribute {key}: {e}"),
+                    }
+                })?
+            }
+            Command::GetProgress { id } => {
+                let progress = get_progress(db, id).await?;
+                serde_json::to_value(progress).map_err(|e| {
+                    DbError::ParseError {
+                        when: format!("serializing progress of {id}: {e}"),
+                    }
+                })?
             }
             Command::CreateChild {
                 parent_id,
                 title,
                 description,
                 code_name,
-            } => create_note(db, parent_id, title, description, code_name)
-                .await?
-                .into(),
-        };
-        match thread.status() {
-            ThreadStatus::Finished => {
-                return Err(DbError::ExecutionError {
-                    trace: "script did not return".to_string(),
-                })
-            }
-            ThreadStatus::Error => {
-                return Err(DbError::ExecutionError {
-                    trace: "script did not return".to_string(),
-                })
+                position,
+            } => {
+                let child_id =
+                    create_note(db, parent_id, title, description, code_name)
+                        .await?;
+                if let Some(position) = position {
+                    reorder_note(db, child_id, position).await?;
+                }
+                child_id.into()
             }
-            _ => {}
-        }
+            Command::CopyNote {
+                id,
+                parent_id,
+                with_logs,
+            } => copy_note(db, id, parent_id, with_logs).await?.into(),
+        };
+        trace.steps.push(TraceStep {
+            command: serde_json::to_value(&result).unwrap_or_default(),
+            response: response.clone(),
+            at: at.as_micros() as u64,
+            took: (start.elapsed() - at).as_micros() as u64,
+        });
         let response_as_value: mlua::Value = lua.to_value(&response).unwrap();
-        result = match response {
-            JsonValue::Null => thread.resume(()),
-            _ => thread.resume(response_as_value),
+        result = match (imported.take(), response) {
+            (Some(module), _) => thread.resume(module),
+            (None, JsonValue::Null) => thread.resume(()),
+            (None, _) => thread.resume(response_as_value),
         }
-        .context(LuaSnafu {
-            task: "resuming again",
-        })?;
+        .map_err(script_error)?;
     }
 }
 
@@ -368,7 +1104,7 @@ pub async fn get_forms(
                 return Ok(result);
             }
             let done_status = get_attribute(db, id, "done").await?;
-            if let Some(_) = done_status {
+            if done_status.is_some() {
                 return Ok(result);
             }
             let action = Action {
@@ -395,12 +1131,31 @@ pub async fn execute(
     form_container: &FormContainer,
     value: &Value,
 ) -> Result<String, DbError> {
+    let lua = Lua::new();
+    execute_traced(db, &lua, id, form_container, value, &mut Trace::default())
+        .await
+}
+
+/// Like [`execute`] in the given Lua state, recording the run in `trace`
+/// as [`run_traced`]. The execution log keeps the trace; a failed run
+/// leaves it to the caller, whose transaction is rolled back.
+pub async fn execute_traced(
+    db: &mut SqliteConnection,
+    lua: &Lua,
+    id: i64,
+    form_container: &FormContainer,
+    value: &Value,
+    trace: &mut Trace,
+) -> Result<String, DbError> {
+    check_not_trashed(db, id).await?;
     let option_code = get_code(db, id).await?;
     match option_code {
         None => execute_done(db, id, &form_container.action, value).await,
         Some(code) => {
-            let message = run::<String>(
+            let version = code.version;
+            let message = run_traced::<String>(
                 db,
+                lua,
                 code,
                 form_container.label.as_str(),
                 id,
@@ -408,14 +1163,15 @@ pub async fn execute(
                     serde_json::to_string(value).unwrap().as_str(),
                 )
                 .unwrap(),
+                trace,
             )
             .await?;
-            let _ = create_log(
+            let _ = create_execution_log(
                 &mut *db,
                 id,
-                "info".to_string(),
                 format!("Note {id} executed form {form_container:?} with value {value:?}"),
-                None,
+                version,
+                Some(trace.to_blob()),
             ).await?;
             Ok(message)
         }
@@ -447,7 +1203,7 @@ pub async fn execute_done(
     }
     match value {
         Value::Date(date) => {
-            set_attribute(db, id, "done", date.0.to_string().as_str()).await?
+            set_attribute(db, id, "done", &AttributeValue::Date(date.0)).await?
         }
         _ => {
             return Err(DbError::ExecutionError {
@@ -464,7 +1220,9 @@ pub async fn get_code_by_name(
 ) -> Result<Option<Code>, DbError> {
     let code = sqlx::query_as::<_, Code>(
         r#"
-        SELECT name, capabilities, script
+        SELECT name, capabilities, script, attribute_schema,
+               completion_attribute, board_action, board_action_key, version,
+               library
         FROM codes
         WHERE name = ?
         "#,
@@ -0,0 +1,345 @@
+use rocket_db_pools::sqlx::{self};
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+use sqlx::SqliteConnection;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+use super::codes::get_code_by_name;
+use super::errors::{DbError, SqlxSnafu};
+use super::validation::{capability_report, CapabilityReport};
+
+/// What matters of Lua source to find imports: comments and whitespace
+/// are dropped, and string literals are kept apart from the code.
+enum Token<'a> {
+    Name(&'a str),
+    /// Content of a string literal, with its byte range in the script
+    Text(Range<usize>),
+    Symbol(u8),
+}
+
+/// Length of the opening long bracket `[==[` at `start`, and its level.
+fn long_bracket(bytes: &[u8], start: usize) -> Option<(usize, usize)> {
+    if bytes.get(start) != Some(&b'[') {
+        return None;
+    }
+    let level = bytes[start + 1..]
+        .iter()
+        .take_while(|b| **b == b'=')
+        .count();
+    (bytes.get(start + 1 + level) == Some(&b'[')).then_some((level + 2, level))
+}
+
+/// Position right after the `]==]` closing a long bracket of `level`.
+fn long_bracket_end(script: &str, from: usize, level: usize) -> (usize, usize) {
+    let close = format!("]{}]", "=".repeat(level));
+    match script[from..].find(&close) {
+        Some(found) => (from + found, from + found + close.len()),
+        None => (script.len(), script.len()),
+    }
+}
+
+fn tokenize(script: &str) -> Vec<Token<'_>> {
+    let bytes = script.as_bytes();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        if b.is_ascii_whitespace() {
+            i += 1;
+        } else if bytes[i..].starts_with(b"--") {
+            i += 2;
+            if let Some((open, level)) = long_bracket(bytes, i) {
+                i = long_bracket_end(script, i + open, level).1;
+            } else {
+                i = script[i..].find('\n').map_or(bytes.len(), |n| i + n);
+            }
+        } else if b == b'"' || b == b'\'' {
+            let start = i + 1;
+            i = start;
+            while i < bytes.len() && bytes[i] != b && bytes[i] != b'\n' {
+                i += if bytes[i] == b'\\' { 2 } else { 1 };
+            }
+            tokens.push(Token::Text(start..i.min(bytes.len())));
+            i += 1;
+        } else if let Some((open, level)) = long_bracket(bytes, i) {
+            let (end, after) = long_bracket_end(script, i + open, level);
+            tokens.push(Token::Text(i + open..end));
+            i = after;
+        } else if b.is_ascii_alphanumeric() || b == b'_' {
+            let start = i;
+            while i < bytes.len()
+                && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_')
+            {
+                i += 1;
+            }
+            tokens.push(Token::Name(&script[start..i]));
+        } else if b.is_ascii() {
+            tokens.push(Token::Symbol(b));
+            i += 1;
+        } else {
+            // Lua has no use for other characters outside strings, skip
+            // them whole so `i` stays on a char boundary
+            tokens.push(Token::Symbol(b));
+            while i < bytes.len() && !bytes[i].is_ascii() {
+                i += 1;
+            }
+        }
+    }
+    tokens
+}
+
+/// Names a script imports through `{ Import = "name" }` table
+/// constructors, with the byte range of each name inside the script.
+/// Comments and text inside strings are not imports.
+fn import_spans(script: &str) -> Vec<(String, Range<usize>)> {
+    tokenize(script)
+        .windows(4)
+        .filter_map(|window| match window {
+            [
+                Token::Symbol(b'{' | b',' | b';'),
+                Token::Name("Import"),
+                Token::Symbol(b'='),
+                Token::Text(range),
+            ] => Some((script[range.clone()].to_string(), range.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Codes a script imports, sorted and without duplicates.
+pub fn imports(script: &str) -> Vec<String> {
+    let mut names: Vec<String> = import_spans(script)
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// The script with its imports of `from` turned into imports of `to`.
+pub fn rename_imports(script: &str, from: &str, to: &str) -> String {
+    let mut result = script.to_string();
+    // From the end, so earlier ranges stay valid
+    for (name, range) in import_spans(script).into_iter().rev() {
+        if name == from {
+            result.replace_range(range, to);
+        }
+    }
+    result
+}
+
+/// Codes whose current script imports `name`.
+pub async fn get_importers(
+    db: &mut SqliteConnection,
+    name: &str,
+) -> Result<Vec<String>, DbError> {
+    let scripts = sqlx::query_as::<_, (String, String)>(
+        "SELECT name, script FROM codes ORDER BY name",
+    )
+    .fetch_all(&mut *db)
+    .await
+    .context(SqlxSnafu {
+        task: "scanning code imports",
+    })?;
+    Ok(scripts
+        .into_iter()
+        .filter(|(_, script)| imports(script).iter().any(|i| i == name))
+        .map(|(importer, _)| importer)
+        .collect())
+}
+
+/// Code versions that notes are pinned to and that import `name`, as
+/// `code@version`. Versions cannot be modified, so renaming `name` would
+/// break these notes.
+pub async fn get_pinned_importers(
+    db: &mut SqliteConnection,
+    name: &str,
+) -> Result<Vec<String>, DbError> {
+    let versions = sqlx::query_as::<_, (String, i64, String)>(
+        r#"
+        SELECT DISTINCT v.code_name, v.version, v.script
+        FROM notes n
+        JOIN code_versions v
+          ON v.code_name = n.code_name AND v.version = n.code_version
+        ORDER BY v.code_name, v.version
+        "#,
+    )
+    .fetch_all(&mut *db)
+    .await
+    .context(SqlxSnafu {
+        task: "scanning pinned code versions",
+    })?;
+    Ok(versions
+        .into_iter()
+        .filter(|(_, _, script)| imports(script).iter().any(|i| i == name))
+        .map(|(code_name, version, _)| format!("{code_name}@{version}"))
+        .collect())
+}
+
+/// Compares granted capabilities with the commands of `script` and of
+/// every code it imports, directly or not. Missing libraries are skipped,
+/// running the code reports them.
+pub async fn check_capabilities(
+    db: &mut SqliteConnection,
+    capabilities: &str,
+    script: &str,
+) -> Result<CapabilityReport, DbError> {
+    let mut seen: Vec<String> = vec![];
+    let mut scripts = vec![script.to_string()];
+    let mut pending = imports(script);
+    while let Some(name) = pending.pop() {
+        if seen.contains(&name) {
+            continue;
+        }
+        if let Some(code) = get_code_by_name(db, &name).await? {
+            pending.extend(imports(&code.script));
+            scripts.push(code.script);
+        }
+        seen.push(name);
+    }
+    let scripts: Vec<&str> = scripts.iter().map(String::as_str).collect();
+    Ok(capability_report(capabilities, &scripts))
+}
+
+/// Script and library flag of every code, by name.
+async fn get_code_scripts(
+    db: &mut SqliteConnection,
+) -> Result<HashMap<String, (String, bool)>, DbError> {
+    let codes = sqlx::query_as::<_, (String, String, bool)>(
+        "SELECT name, script, library FROM codes",
+    )
+    .fetch_all(&mut *db)
+    .await
+    .context(SqlxSnafu {
+        task: "loading code scripts",
+    })?;
+    Ok(codes
+        .into_iter()
+        .map(|(name, script, library)| (name, (script, library)))
+        .collect())
+}
+
+/// Imports leading from `from` back to `target`, as the list of codes
+/// along the way. `visited` holds codes already explored.
+fn path_to(
+    codes: &HashMap<String, (String, bool)>,
+    from: &str,
+    target: &str,
+    visited: &mut Vec<String>,
+) -> Option<Vec<String>> {
+    let (script, _) = codes.get(from)?;
+    for next in imports(script) {
+        if next == target {
+            return Some(vec![from.to_string(), next]);
+        }
+        if visited.contains(&next) {
+            continue;
+        }
+        visited.push(next.clone());
+        if let Some(mut path) = path_to(codes, &next, target, visited) {
+            path.insert(0, from.to_string());
+            return Some(path);
+        }
+    }
+    None
+}
+
+/// Flags as libraries the codes other codes import. Before libraries
+/// existed any code could be imported, this keeps those imports working
+/// once migration 014 adds the flag.
+pub(crate) async fn mark_imported_libraries(
+    db: &mut SqliteConnection,
+) -> Result<(), sqlx::Error> {
+    let scripts: Vec<String> = sqlx::query_scalar("SELECT script FROM codes")
+        .fetch_all(&mut *db)
+        .await?;
+    let mut imported: Vec<String> =
+        scripts.iter().flat_map(|script| imports(script)).collect();
+    imported.sort();
+    imported.dedup();
+    for name in imported {
+        sqlx::query("UPDATE codes SET library = 1 WHERE name = ?")
+            .bind(name)
+            .execute(&mut *db)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Fails if saving `script` as the code `name` would let it import
+/// itself, directly or through other codes.
+pub async fn check_import_cycle(
+    db: &mut SqliteConnection,
+    name: &str,
+    script: &str,
+) -> Result<(), DbError> {
+    let mut codes = get_code_scripts(db).await?;
+    let library = codes.get(name).is_some_and(|(_, library)| *library);
+    codes.insert(name.to_string(), (script.to_string(), library));
+    match path_to(&codes, name, name, &mut vec![]) {
+        Some(cycle) => Err(DbError::ImportCycle { cycle }),
+        None => Ok(()),
+    }
+}
+
+/// A code and the codes it imports, as shown on the code page.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DependencyNode {
+    pub name: String,
+    /// Whether the code is a library, `None` if it does not exist
+    pub library: Option<bool>,
+    /// Imported again below itself, so its imports are not repeated
+    pub cycle: bool,
+    /// Its imports are already shown higher in the tree
+    pub repeated: bool,
+    pub imports: Vec<DependencyNode>,
+}
+
+/// Builds the tree below `name`. The imports of each code are listed
+/// once, in `expanded`, so that shared libraries do not multiply it.
+fn dependency_node(
+    codes: &HashMap<String, (String, bool)>,
+    name: &str,
+    path: &mut Vec<String>,
+    expanded: &mut HashSet<String>,
+) -> DependencyNode {
+    let code = codes.get(name);
+    let cycle = path.iter().any(|p| p == name);
+    let mut imported = vec![];
+    let mut repeated = false;
+    if let Some((script, _)) = code.filter(|_| !cycle) {
+        let script_imports = imports(script);
+        repeated = !script_imports.is_empty() && expanded.contains(name);
+        if !repeated {
+            expanded.insert(name.to_string());
+            path.push(name.to_string());
+            for import in script_imports {
+                imported.push(dependency_node(codes, &import, path, expanded));
+            }
+            path.pop();
+        }
+    }
+    DependencyNode {
+        name: name.to_string(),
+        library: code.map(|(_, library)| *library),
+        cycle,
+        repeated,
+        imports: imported,
+    }
+}
+
+/// The codes `name` imports, directly or not, as a tree.
+pub async fn get_dependency_tree(
+    db: &mut SqliteConnection,
+    name: &str,
+) -> Result<DependencyNode, DbError> {
+    let codes = get_code_scripts(db).await?;
+    Ok(dependency_node(
+        &codes,
+        name,
+        &mut vec![],
+        &mut HashSet::new(),
+    ))
+}
@@ -1,5 +1,23 @@
 use snafu::Snafu;
 
+use super::validation::CodeProblem;
+
+fn describe(problems: &[CodeProblem]) -> String {
+    problems
+        .iter()
+        .map(|p| p.to_string())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+fn location(chunk: &Option<String>, line: &Option<usize>) -> String {
+    match (chunk, line) {
+        (Some(chunk), Some(line)) => format!(" at {chunk}:{line}"),
+        (Some(chunk), None) => format!(" in {chunk}"),
+        _ => String::new(),
+    }
+}
+
 #[derive(Debug, Snafu)]
 #[snafu(visibility(pub(crate)))]
 pub enum DbError {
@@ -7,14 +25,78 @@ pub enum DbError {
     SqlxError { task: String, source: sqlx::Error },
     #[snafu(display("Note not found {id}"))]
     NoNoteError { id: i64, source: sqlx::Error },
+    #[snafu(display("Note {id} not found"))]
+    NoteNotFound { id: i64 },
+    #[snafu(display("Note {id} is in the trash"))]
+    NoteInTrash { id: i64 },
     #[snafu(display("Lob not found {id}"))]
     NoLogError { id: i64, source: sqlx::Error },
     #[snafu(display("Execution error: {trace}"))]
     ExecutionError { trace: String },
     #[snafu(display("Lua error when {task}: {source}"))]
     LuaError { task: String, source: mlua::Error },
+    /// A Lua error raised by a script, located in the code (or imported
+    /// library) named `chunk`
+    #[snafu(display("Script error{}: {message}", location(chunk, line)))]
+    ScriptError {
+        chunk: Option<String>,
+        line: Option<usize>,
+        message: String,
+        traceback: Option<String>,
+    },
+    #[snafu(display("The script defines no coroutine {name}"))]
+    NoEntryPoint { name: String },
+    #[snafu(display("Invalid command {command}: {reason}"))]
+    InvalidCommand { command: String, reason: String },
+    #[snafu(display("Invalid result {result}: {reason}"))]
+    InvalidResult { result: String, reason: String },
+    #[snafu(display(
+        "Command {command} not allowed by capabilities {capabilities}"
+    ))]
+    Unauthorized {
+        command: String,
+        capabilities: String,
+    },
     #[snafu(display("Required file not found {name}"))]
     LibNotFound { name: String },
+    #[snafu(display("Code {name} not found"))]
+    CodeNotFound { name: String },
+    #[snafu(display("Code {name} has no version {version}"))]
+    CodeVersionNotFound { name: String, version: i64 },
+    #[snafu(display("Code {name} is not a library, it cannot be imported"))]
+    NotALibrary { name: String },
+    #[snafu(display("Code {name} is a library, notes cannot use it"))]
+    LibraryUsedByNote { name: String },
+    #[snafu(display(
+        "Code {name} is imported by {}, it must stay a library",
+        importers.join(", ")
+    ))]
+    LibraryImported {
+        name: String,
+        importers: Vec<String>,
+    },
+    #[snafu(display(
+        "Code {name} is imported by {}, remove those imports first",
+        importers.join(", ")
+    ))]
+    CodeImported {
+        name: String,
+        importers: Vec<String>,
+    },
+    #[snafu(display(
+        "Notes are pinned to {} importing {name}, it cannot be renamed or \
+         deleted",
+        pinned.join(", ")
+    ))]
+    LibraryPinned { name: String, pinned: Vec<String> },
+    #[snafu(display("Import cycle {}", cycle.join(" -> ")))]
+    ImportCycle { cycle: Vec<String> },
     #[snafu(display("Parsing error when {when}"))]
     ParseError { when: String },
+    #[snafu(display("Invalid value for attribute {key}: {reason}"))]
+    InvalidAttribute { key: String, reason: String },
+    #[snafu(display("Invalid template: {reason}"))]
+    InvalidTemplate { reason: String },
+    #[snafu(display("Invalid code: {}", describe(problems)))]
+    InvalidCode { problems: Vec<CodeProblem> },
 }
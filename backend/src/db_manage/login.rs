@@ -1,14 +1,18 @@
+use base64::{engine::general_purpose, Engine as _};
 use rocket_db_pools::sqlx::pool::PoolConnection;
 use rocket_db_pools::sqlx::Sqlite;
 use rocket_db_pools::sqlx::{self, Row};
+use snafu::ResultExt;
+use sqlx::SqliteConnection;
 
+use super::errors::{DbError, SqlxSnafu};
 use super::Db;
 
 pub async fn get_password(conn: &mut PoolConnection<Sqlite>) -> Option<String> {
     sqlx::query("SELECT value FROM meta WHERE key = 'password_hash';")
         .fetch_one(&mut **conn)
         .await
-        .and_then(|r| Ok(r.try_get(0)?))
+        .and_then(|r| r.try_get(0))
         .ok()
 }
 
@@ -22,3 +26,35 @@ pub async fn set_password(conn: &Db, hash: String) -> Result<(), sqlx::Error> {
 
     Ok(())
 }
+
+/// Token giving read access to the calendar feed, if one was created.
+pub async fn get_calendar_token(
+    conn: &mut SqliteConnection,
+) -> Result<Option<String>, DbError> {
+    sqlx::query_scalar::<_, String>(
+        "SELECT value FROM meta WHERE key = 'calendar_token'",
+    )
+    .fetch_optional(&mut *conn)
+    .await
+    .context(SqlxSnafu {
+        task: "getting calendar token",
+    })
+}
+
+/// Replaces the calendar token, invalidating previously shared feeds.
+pub async fn reset_calendar_token(
+    conn: &mut SqliteConnection,
+) -> Result<String, DbError> {
+    let bytes: [u8; 24] = rand::random();
+    let token = general_purpose::URL_SAFE_NO_PAD.encode(bytes);
+    sqlx::query(
+        "INSERT OR REPLACE INTO meta (key, value) VALUES ('calendar_token', ?)",
+    )
+    .bind(&token)
+    .execute(&mut *conn)
+    .await
+    .context(SqlxSnafu {
+        task: "setting calendar token",
+    })?;
+    Ok(token)
+}
@@ -1,12 +1,10 @@
 use crate::db_manage::errors::{DbError, NoLogSnafu, SqlxSnafu};
+use crate::db_manage::trace::Trace;
 use rocket_db_pools::sqlx::FromRow;
-use rocket_db_pools::Connection;
 use serde::{Deserialize, Serialize};
 use snafu::ResultExt;
 use sqlx::SqliteConnection;
 
-use crate::Db;
-
 #[derive(Debug, FromRow, Serialize, Deserialize)]
 pub struct Log {
     pub id: i64,
@@ -15,6 +13,7 @@ pub struct Log {
     pub kind: String,
     pub message: String,
     pub data: Option<Vec<u8>>,
+    pub code_version: Option<i64>,
 }
 
 pub async fn create_log(
@@ -51,19 +50,67 @@ pub async fn create_log(
     Ok(row.0)
 }
 
+/// Log of a code execution, recording which version of the code ran and
+/// the trace of the run as data.
+pub async fn create_execution_log(
+    db: &mut SqliteConnection,
+    note_id: i64,
+    message: String,
+    code_version: i64,
+    data: Option<Vec<u8>>,
+) -> Result<i64, DbError> {
+    let id = create_log(db, note_id, "info".to_string(), message, data).await?;
+    sqlx::query("UPDATE logs SET code_version = ? WHERE id = ?")
+        .bind(code_version)
+        .bind(id)
+        .execute(&mut *db)
+        .await
+        .context(SqlxSnafu {
+            task: "recording code version of log",
+        })?;
+    Ok(id)
+}
+
+/// Error log of a failed run, written once its transaction is rolled
+/// back so that the trace survives.
+pub async fn create_failure_log(
+    db: &mut SqliteConnection,
+    trace: &Trace,
+) -> Result<i64, DbError> {
+    let error = trace.error.as_ref().map_or("", |e| e.message.as_str());
+    let id = create_log(
+        db,
+        trace.note_id,
+        "error".to_string(),
+        format!("Running {} failed: {error}", trace.entry_point),
+        Some(trace.to_blob()),
+    )
+    .await?;
+    sqlx::query("UPDATE logs SET code_version = ? WHERE id = ?")
+        .bind(trace.code_version)
+        .bind(id)
+        .execute(&mut *db)
+        .await
+        .context(SqlxSnafu {
+            task: "recording code version of log",
+        })?;
+    Ok(id)
+}
+
 pub async fn get_log(
-    db: &mut Connection<Db>,
+    db: &mut SqliteConnection,
     log_id: i64,
 ) -> Result<Option<Log>, DbError> {
     let log = sqlx::query_as::<_, Log>(
         r#"
-        SELECT note_id, created_at, kind, message, data
+        SELECT id, note_id, created_at AS timestamp, kind, message,
+               blob_data AS data, code_version
         FROM logs
         WHERE id = ?
         "#,
     )
     .bind(log_id)
-    .fetch_optional(&mut ***db)
+    .fetch_optional(&mut *db)
     .await
     .context(NoLogSnafu { id: log_id })?;
 
@@ -71,15 +118,16 @@ pub async fn get_log(
 }
 
 pub async fn get_logs_from_note(
-    db: &mut Connection<Db>,
+    db: &mut SqliteConnection,
     note_id: i64,
 ) -> Result<Vec<Log>, DbError> {
     let result = sqlx::query_as::<_, Log>(
-        r#"SELECT id, note_id, created_at as timestamp, kind, message, blob_data as data
+        r#"SELECT id, note_id, created_at as timestamp, kind, message, blob_data as data,
+               code_version
         FROM logs WHERE note_id = ? ORDER BY created_at"#,
     )
     .bind(note_id)
-    .fetch_all(&mut ***db)
+    .fetch_all(&mut *db)
     .await
     .context(SqlxSnafu {
         task: "getting logs",
@@ -1,4 +1,6 @@
-use std::fs;
+use std::time::Duration;
+
+use rocket::fairing::AdHoc;
 
 use rocket_db_pools::sqlx::{self};
 use rocket_db_pools::Database;
@@ -6,17 +8,66 @@ use rocket_db_pools::Database;
 pub mod login;
 pub use login::{get_password, set_password};
 pub mod notes;
-pub use notes::{create_note, get_child_notes, get_note, get_root_notes, Note};
+pub use notes::{create_note, get_child_notes, get_note, Note, ROOT_NOTE_ID};
+pub mod agenda;
 pub mod attributes;
+pub mod board;
+pub mod code_tests;
+pub mod code_versions;
 pub mod codes;
+pub mod dependencies;
 pub mod errors;
 pub mod logs;
+pub mod progress;
+pub mod revisions;
+pub mod simulation;
+pub mod templates;
+pub mod trace;
+pub mod trash;
+pub mod tree;
+pub mod validation;
+use trash::purge_expired_trash;
+
+/// A migration embedded in the binary, so that it runs from any directory
+macro_rules! migration {
+    ($file:literal) => {
+        ($file, include_str!(concat!("../../migrations/", $file)))
+    };
+}
+
+/// Migrations in order, the n-th one upgrading the schema to version n
+const MIGRATIONS: &[(&str, &str)] = &[
+    migration!("001-init.sql"),
+    migration!("002-typed-attributes.sql"),
+    migration!("003-attribute-schemas.sql"),
+    migration!("004-revisions.sql"),
+    migration!("005-trash.sql"),
+    migration!("006-note-positions.sql"),
+    migration!("007-templates.sql"),
+    migration!("008-root-note.sql"),
+    migration!("009-completion-attribute.sql"),
+    migration!("010-board-action.sql"),
+    migration!("011-code-versions.sql"),
+    migration!("012-pinned-versions.sql"),
+    migration!("013-code-tests.sql"),
+    migration!("014-code-libraries.sql"),
+];
 
 #[derive(Database)]
 #[database("db")]
 pub struct Db(sqlx::SqlitePool);
 
 pub async fn migrate(conn: &Db) -> Result<(), sqlx::Error> {
+    let mut conn = conn.acquire().await?;
+    migrate_connection(&mut conn, true).await
+}
+
+/// Brings the schema of `conn` up to date, telling which migrations run
+/// when `verbose`.
+pub async fn migrate_connection(
+    conn: &mut sqlx::SqliteConnection,
+    verbose: bool,
+) -> Result<(), sqlx::Error> {
     // Create meta table if it doesn't exist
     sqlx::query(
         r#"
@@ -26,36 +77,77 @@ pub async fn migrate(conn: &Db) -> Result<(), sqlx::Error> {
         );
         "#,
     )
-    .execute(&**conn)
+    .execute(&mut *conn)
     .await?;
 
     // Read current schema version
     let version: Option<String> = sqlx::query_scalar(
         "SELECT value FROM meta WHERE key = 'schema_version';",
     )
-    .fetch_optional(&**conn)
+    .fetch_optional(&mut *conn)
     .await?;
 
     let version = version.unwrap_or_else(|| "0".to_string());
-    println!("Current schema version: {}", version);
-
-    match version.as_str() {
-        "0" => {
-            println!("Running initial migration...");
+    if verbose {
+        println!("Current schema version: {}", version);
+    }
 
-            let sql = fs::read_to_string("./migrations/001-init.sql")
-                .expect("Could not read migration file");
-            sqlx::query(&sql).execute(&**conn).await?;
+    let current: usize = match version.parse() {
+        Ok(v) if v <= MIGRATIONS.len() => v,
+        _ => {
+            return Err(sqlx::Error::Protocol(format!(
+                "unknown schema version {version}"
+            )))
         }
+    };
 
-        "1" => {
-            // Schema is up to date — do nothing
+    // Each migration bumps `schema_version` itself
+    for (name, sql) in &MIGRATIONS[current..] {
+        if verbose {
+            println!("Running migration {name}...");
         }
-
-        _ => {
-            panic!("Unknown schema version: {}", version);
+        sqlx::query(sql).execute(&mut *conn).await?;
+        if *name == "014-code-libraries.sql" {
+            dependencies::mark_imported_libraries(conn).await?;
         }
     }
 
     Ok(())
 }
+
+/// Purges notes that stayed in the trash longer than `trash_retention_days`
+/// (30 unless configured), once at liftoff and then every hour.
+pub fn trash_purge_fairing() -> AdHoc {
+    AdHoc::on_liftoff("Trash purge", |rocket| {
+        Box::pin(async move {
+            let retention_days: i64 = rocket
+                .figment()
+                .extract_inner("trash_retention_days")
+                .unwrap_or(30);
+            let Some(db) = Db::fetch(rocket) else {
+                return;
+            };
+            let pool = (**db).clone();
+            rocket::tokio::spawn(async move {
+                let mut interval =
+                    rocket::tokio::time::interval(Duration::from_secs(3600));
+                loop {
+                    interval.tick().await;
+                    let purged = match pool.acquire().await {
+                        Ok(mut conn) => {
+                            purge_expired_trash(&mut conn, retention_days)
+                                .await
+                                .map_err(|e| e.to_string())
+                        }
+                        Err(e) => Err(e.to_string()),
+                    };
+                    match purged {
+                        Ok(0) => {}
+                        Ok(n) => println!("Purged {n} notes from trash"),
+                        Err(e) => println!("Failed to purge trash: {e}"),
+                    }
+                }
+            });
+        })
+    })
+}
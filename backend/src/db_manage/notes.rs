@@ -5,10 +5,15 @@ use serde::{Deserialize, Serialize};
 use snafu::ResultExt;
 use sqlx::{Acquire, SqliteConnection};
 
+use super::attributes::{apply_attribute_defaults, decode, PRIORITY_ATTRIBUTE};
 use super::errors::{DbError, NoNoteSnafu, SqlxSnafu};
 use super::logs::create_log;
+use super::revisions::record_field_change;
 use super::Db;
 
+/// The permanent note every other note descends from
+pub const ROOT_NOTE_ID: i64 = 0;
+
 #[derive(Debug, FromRow, Serialize, Deserialize)]
 pub struct Note {
     pub id: i64,
@@ -18,23 +23,50 @@ pub struct Note {
     pub code_name: Option<String>,
 }
 
+/// Fails if `code_name` is a library, which notes cannot use.
+async fn check_not_library(
+    db: &mut SqliteConnection,
+    code_name: Option<&str>,
+) -> Result<(), DbError> {
+    let Some(name) = code_name else {
+        return Ok(());
+    };
+    let library: Option<bool> =
+        sqlx::query_scalar("SELECT library FROM codes WHERE name = ?")
+            .bind(name)
+            .fetch_optional(&mut *db)
+            .await
+            .context(SqlxSnafu {
+                task: "checking note code",
+            })?;
+    if library == Some(true) {
+        return Err(DbError::LibraryUsedByNote {
+            name: name.to_string(),
+        });
+    }
+    Ok(())
+}
+
 pub async fn create_note(
     db: &mut SqliteConnection,
-    parent_id: Option<i64>,
+    parent_id: i64,
     title: String,
     description: String,
     code_name: Option<String>,
 ) -> Result<i64, DbError> {
+    check_not_library(db, code_name.as_deref()).await?;
     sqlx::query(
         r#"
-      INSERT INTO notes (parent_id, title, description, code_name)
-      VALUES (?, ?, ?, ?)
+      INSERT INTO notes (parent_id, title, description, code_name, position)
+      VALUES (?, ?, ?, ?, (SELECT COALESCE(MAX(position), 0) + 1
+                           FROM notes WHERE parent_id = ?))
       "#,
     )
-    .bind(&parent_id)
+    .bind(parent_id)
     .bind(&title)
     .bind(&description)
     .bind(&code_name)
+    .bind(parent_id)
     .execute(&mut *db)
     .await
     .context(SqlxSnafu {
@@ -48,6 +80,7 @@ pub async fn create_note(
             task: "getting created note",
         })?;
     let inserted_id = row.0;
+    apply_attribute_defaults(&mut *db, inserted_id).await?;
     let _ = create_log(
         &mut *db,
         inserted_id,
@@ -60,18 +93,18 @@ pub async fn create_note(
 }
 
 pub async fn get_note(
-    db: &mut Connection<Db>,
+    db: &mut SqliteConnection,
     note_id: i64,
 ) -> Result<Option<Note>, DbError> {
     let note = sqlx::query_as::<_, Note>(
         r#"
         SELECT id, parent_id, title, description, code_name
         FROM notes
-        WHERE id = ?
+        WHERE id = ? AND deleted_at IS NULL
         "#,
     )
     .bind(note_id)
-    .fetch_optional(&mut ***db)
+    .fetch_optional(&mut *db)
     .await
     .context(NoNoteSnafu { id: note_id })?;
 
@@ -83,7 +116,7 @@ pub async fn get_child_notes(
     note_id: i64,
 ) -> Result<Vec<Note>, sqlx::Error> {
     let notes = sqlx::query_as::<_, Note>(
-        "SELECT id, parent_id, title, description, code_name FROM notes WHERE parent_id = ? ORDER BY id"
+        "SELECT id, parent_id, title, description, code_name FROM notes WHERE parent_id = ? AND deleted_at IS NULL ORDER BY position, id"
     )
     .bind(note_id)
     .fetch_all(&mut *db)
@@ -92,16 +125,42 @@ pub async fn get_child_notes(
     Ok(notes)
 }
 
-pub async fn get_root_notes(
-    db: &mut Connection<Db>,
-) -> Result<Vec<Note>, sqlx::Error> {
-    let notes = sqlx::query_as::<_, Note>(
-        "SELECT id, parent_id, title, description, code_name FROM notes WHERE parent_id IS NULL ORDER BY id"
-    )
-    .fetch_all(&mut ***db)
-    .await?;
+#[derive(FromRow)]
+struct NoteWithStatus {
+    #[sqlx(flatten)]
+    note: Note,
+    done: bool,
+}
 
-    Ok(notes)
+/// Children of a note, each with whether it is done (see `note_status`).
+/// With `by_priority` higher priorities come first, then the manual order.
+pub async fn get_child_notes_with_status(
+    db: &mut SqliteConnection,
+    note_id: i64,
+    by_priority: bool,
+) -> Result<Vec<(Note, bool)>, DbError> {
+    let rows = sqlx::query_as::<_, NoteWithStatus>(
+        r#"
+        SELECT n.id, n.parent_id, n.title, n.description, n.code_name,
+               s.done
+        FROM notes n
+        JOIN note_status s ON s.note_id = n.id
+        LEFT JOIN attributes p ON p.note_id = n.id AND p.key = ?
+        WHERE n.parent_id = ? AND n.deleted_at IS NULL
+        ORDER BY CASE WHEN ? THEN -COALESCE(CAST(p.value AS INTEGER), 0)
+                 ELSE 0 END,
+                 n.position, n.id
+        "#,
+    )
+    .bind(PRIORITY_ATTRIBUTE)
+    .bind(note_id)
+    .bind(by_priority)
+    .fetch_all(&mut *db)
+    .await
+    .context(SqlxSnafu {
+        task: "getting children with status",
+    })?;
+    Ok(rows.into_iter().map(|r| (r.note, r.done)).collect())
 }
 
 pub async fn get_all_notes(
@@ -109,7 +168,7 @@ pub async fn get_all_notes(
 ) -> Result<Vec<Note>, DbError> {
     let notes = sqlx::query_as::<_, Note>(
         r#"SELECT id, parent_id, title, description, code_name
-           FROM notes ORDER BY id"#,
+           FROM notes WHERE deleted_at IS NULL ORDER BY position, id"#,
     )
     .fetch_all(&mut ***db)
     .await
@@ -121,7 +180,7 @@ pub async fn get_all_notes(
 }
 
 pub async fn update_note(
-    db: &mut Connection<Db>,
+    db: &mut SqliteConnection,
     note_id: i64,
     title: String,
     description: String,
@@ -130,30 +189,60 @@ pub async fn update_note(
     let mut tx = db.begin().await.context(SqlxSnafu {
         task: "beginning transaction",
     })?;
+    check_not_library(&mut tx, code_name.as_deref()).await?;
+    let old =
+        get_note(&mut tx, note_id)
+            .await?
+            .ok_or(DbError::ExecutionError {
+                trace: format!("note {note_id} not found"),
+            })?;
+    let changes = [
+        ("title", Some(old.title.as_str()), Some(title.as_str())),
+        (
+            "description",
+            Some(old.description.as_str()),
+            Some(description.as_str()),
+        ),
+        ("code_name", old.code_name.as_deref(), code_name.as_deref()),
+    ];
+    let mut changed = vec![];
+    for (field, before, after) in changes {
+        if before != after {
+            record_field_change(&mut tx, note_id, field, before, after).await?;
+            changed.push(field);
+        }
+    }
     sqlx::query(
         r#"
         UPDATE notes
-        SET title = ?, description = ?, code_name = ?
+        SET title = ?, description = ?, code_name = ?,
+            -- A pinned version only makes sense for the same code
+            code_version = CASE WHEN code_name IS ? THEN code_version END
         WHERE id = ?
         "#,
     )
     .bind(title)
     .bind(description)
-    .bind(code_name)
+    .bind(&code_name)
+    .bind(&code_name)
     .bind(note_id)
     .execute(&mut *tx)
     .await
     .context(SqlxSnafu {
         task: "updating note",
     })?;
-    let _ = create_log(
-        &mut *tx,
-        note_id,
-        "info".to_string(),
-        format!("Note {note_id} updated"),
-        None,
-    )
-    .await?;
+    // A newly bound code may declare attributes the note does not have yet
+    apply_attribute_defaults(&mut tx, note_id).await?;
+    if !changed.is_empty() {
+        let _ = create_log(
+            &mut tx,
+            note_id,
+            "info".to_string(),
+            format!("Note {note_id} updated: {}", changed.join(", ")),
+            None,
+        )
+        .await?;
+    }
     tx.commit().await.context(SqlxSnafu {
         task: "commiting create note tx",
     })?;
@@ -161,7 +250,7 @@ pub async fn update_note(
 }
 
 pub async fn get_ancestors(
-    db: &mut Connection<Db>,
+    db: &mut SqliteConnection,
     id: i64,
 ) -> Result<Vec<(i64, String)>, DbError> {
     let ancestors = sqlx::query_as::<_, (i64, String)>(
@@ -185,7 +274,7 @@ pub async fn get_ancestors(
     )
     .bind(id)
     .bind(id)
-    .fetch_all(&mut ***db)
+    .fetch_all(&mut *db)
     .await
     .context(SqlxSnafu {
         task: "getting ancestors for breadcrumb",
@@ -194,21 +283,368 @@ pub async fn get_ancestors(
     Ok(ancestors)
 }
 
-pub async fn delete_note(
-    db: &mut Connection<Db>,
+/// Ids of a note and all its (non trashed) descendants.
+pub async fn get_subtree_ids(
+    db: &mut SqliteConnection,
+    id: i64,
+) -> Result<Vec<i64>, DbError> {
+    let ids = sqlx::query_scalar::<_, i64>(
+        r#"
+        WITH RECURSIVE subtree(id) AS (
+            SELECT id FROM notes WHERE id = ? AND deleted_at IS NULL
+
+            UNION ALL
+
+            SELECT n.id
+            FROM notes n
+            JOIN subtree s ON n.parent_id = s.id
+            WHERE n.deleted_at IS NULL
+        )
+        SELECT id FROM subtree
+        "#,
+    )
+    .bind(id)
+    .fetch_all(&mut *db)
+    .await
+    .context(SqlxSnafu {
+        task: "getting subtree",
+    })?;
+    Ok(ids)
+}
+
+/// Gives a note a new parent.
+pub async fn move_note(
+    db: &mut SqliteConnection,
     note_id: i64,
+    new_parent_id: i64,
 ) -> Result<(), DbError> {
+    let mut tx = db.begin().await.context(SqlxSnafu {
+        task: "beginning transaction",
+    })?;
+    let note =
+        get_note(&mut tx, note_id)
+            .await?
+            .ok_or(DbError::ExecutionError {
+                trace: format!("note {note_id} not found"),
+            })?;
+    if note.parent_id == Some(new_parent_id) {
+        return Ok(());
+    }
+    if note_id == ROOT_NOTE_ID {
+        return Err(DbError::ExecutionError {
+            trace: "the root note cannot be moved".to_string(),
+        });
+    }
+    if get_note(&mut tx, new_parent_id).await?.is_none() {
+        return Err(DbError::ExecutionError {
+            trace: format!("destination note {new_parent_id} not found"),
+        });
+    }
+    let ancestors = get_ancestors(&mut tx, new_parent_id).await?;
+    if new_parent_id == note_id
+        || ancestors.iter().any(|(id, _)| *id == note_id)
+    {
+        return Err(DbError::ExecutionError {
+            trace: format!(
+                "cannot move note {note_id} under its own descendant {new_parent_id}"
+            ),
+        });
+    }
+    // Moved notes go after their new siblings
     sqlx::query(
         r#"
-        DELETE FROM notes
+        UPDATE notes
+        SET parent_id = ?,
+            position = (SELECT COALESCE(MAX(position), 0) + 1
+                        FROM notes WHERE parent_id = ?)
         WHERE id = ?
         "#,
     )
+    .bind(new_parent_id)
+    .bind(new_parent_id)
     .bind(note_id)
-    .execute(&mut ***db)
+    .execute(&mut *tx)
+    .await
+    .context(SqlxSnafu {
+        task: "moving note",
+    })?;
+    let before = note.parent_id.map(|id| id.to_string());
+    let after = new_parent_id.to_string();
+    record_field_change(
+        &mut tx,
+        note_id,
+        "parent_id",
+        before.as_deref(),
+        Some(&after),
+    )
+    .await?;
+
+    let message = format!(
+        "Note {note_id} moved from note {} to note {new_parent_id}",
+        note.parent_id.unwrap_or(ROOT_NOTE_ID),
+    );
+    for id in [Some(note_id), note.parent_id, Some(new_parent_id)]
+        .into_iter()
+        .flatten()
+    {
+        create_log(&mut tx, id, "info".to_string(), message.clone(), None)
+            .await?;
+    }
+    tx.commit().await.context(SqlxSnafu {
+        task: "commiting move note tx",
+    })?;
+    Ok(())
+}
+
+/// Deep copies a note and its descendants under `new_parent_id`, with
+/// their attributes and code bindings. Logs are only copied when
+/// `with_logs` is set. Returns the id of the copy.
+pub async fn copy_note(
+    db: &mut SqliteConnection,
+    note_id: i64,
+    new_parent_id: i64,
+    with_logs: bool,
+) -> Result<i64, DbError> {
+    let mut tx = db.begin().await.context(SqlxSnafu {
+        task: "beginning transaction",
+    })?;
+    let (copy_id, count) =
+        copy_subtree(&mut tx, note_id, new_parent_id, with_logs, &|text| {
+            text.to_string()
+        })
+        .await?;
+    let _ = create_log(
+        &mut tx,
+        copy_id,
+        "info".to_string(),
+        format!("Note {copy_id} copied from note {note_id} with {count} notes"),
+        None,
+    )
+    .await?;
+    tx.commit().await.context(SqlxSnafu {
+        task: "commiting copy note tx",
+    })?;
+    Ok(copy_id)
+}
+
+/// Copies a subtree through `create_note`, passing every title,
+/// description and attribute value through `fill` on the way. Returns the
+/// id of the new root and the number of notes created.
+pub(crate) async fn copy_subtree(
+    db: &mut SqliteConnection,
+    note_id: i64,
+    new_parent_id: i64,
+    with_logs: bool,
+    fill: &(dyn Fn(&str) -> String + Sync),
+) -> Result<(i64, usize), DbError> {
+    if get_note(db, new_parent_id).await?.is_none() {
+        return Err(DbError::ExecutionError {
+            trace: format!("destination note {new_parent_id} not found"),
+        });
+    }
+    // Collected upfront, so copying a note under itself terminates
+    let subtree = get_subtree_ids(db, note_id).await?;
+    if subtree.is_empty() {
+        return Err(DbError::ExecutionError {
+            trace: format!("note {note_id} not found"),
+        });
+    }
+    let mut root_copy = None;
+    let mut queue = vec![(note_id, new_parent_id)];
+    while let Some((original_id, parent_id)) = queue.pop() {
+        let original = get_note(db, original_id).await?.ok_or(
+            DbError::ExecutionError {
+                trace: format!("note {original_id} not found"),
+            },
+        )?;
+        let copy_id = create_note(
+            db,
+            parent_id,
+            fill(&original.title),
+            fill(&original.description),
+            original.code_name,
+        )
+        .await?;
+        root_copy.get_or_insert(copy_id);
+        sqlx::query(
+            r#"
+            UPDATE notes
+            SET code_version = (SELECT code_version FROM notes WHERE id = ?)
+            WHERE id = ?
+            "#,
+        )
+        .bind(original_id)
+        .bind(copy_id)
+        .execute(&mut *db)
+        .await
+        .context(SqlxSnafu {
+            task: "copying pinned code version",
+        })?;
+        let attributes = sqlx::query_as::<_, (String, String, String)>(
+            "SELECT key, value, value_type FROM attributes WHERE note_id = ?",
+        )
+        .bind(original_id)
+        .fetch_all(&mut *db)
+        .await
+        .context(SqlxSnafu {
+            task: "getting attributes to copy",
+        })?;
+        for (key, value, value_type) in attributes {
+            let value = fill(&value);
+            // Filled in values must still be of the attribute's type
+            decode(&key, &value_type, &value)?;
+            // Defaults applied by create_note are overwritten
+            sqlx::query(
+                r#"
+                INSERT OR REPLACE INTO attributes
+                    (note_id, key, value, value_type)
+                VALUES (?, ?, ?, ?)
+                "#,
+            )
+            .bind(copy_id)
+            .bind(key)
+            .bind(value)
+            .bind(value_type)
+            .execute(&mut *db)
+            .await
+            .context(SqlxSnafu {
+                task: "copying attributes",
+            })?;
+        }
+        if with_logs {
+            sqlx::query(
+                r#"
+                INSERT INTO logs
+                    (note_id, created_at, kind, message, blob_data, code_version)
+                SELECT ?, created_at, kind, message, blob_data, code_version
+                FROM logs
+                WHERE note_id = ?
+                ORDER BY id
+                "#,
+            )
+            .bind(copy_id)
+            .bind(original_id)
+            .execute(&mut *db)
+            .await
+            .context(SqlxSnafu {
+                task: "copying logs",
+            })?;
+        }
+        let children =
+            get_child_notes(db, original_id).await.context(SqlxSnafu {
+                task: "getting children to copy",
+            })?;
+        // Reversed so children are popped, and thus created, in order
+        queue.extend(
+            children
+                .into_iter()
+                .rev()
+                .filter(|c| subtree.contains(&c.id))
+                .map(|c| (c.id, copy_id)),
+        );
+    }
+    let root_copy = root_copy.expect("the subtree root is always copied");
+    Ok((root_copy, subtree.len()))
+}
+
+/// Places a note at `position` (0 being first) among its siblings,
+/// renumbering them. Positions past the end put it last.
+pub async fn reorder_note(
+    db: &mut SqliteConnection,
+    note_id: i64,
+    position: usize,
+) -> Result<(), DbError> {
+    let mut tx = db.begin().await.context(SqlxSnafu {
+        task: "beginning transaction",
+    })?;
+    let note =
+        get_note(&mut tx, note_id)
+            .await?
+            .ok_or(DbError::ExecutionError {
+                trace: format!("note {note_id} not found"),
+            })?;
+    let mut siblings = sqlx::query_scalar::<_, i64>(
+        r#"
+        SELECT id FROM notes
+        WHERE parent_id IS ? AND deleted_at IS NULL AND id != ?
+        ORDER BY position, id
+        "#,
+    )
+    .bind(note.parent_id)
+    .bind(note_id)
+    .fetch_all(&mut *tx)
+    .await
+    .context(SqlxSnafu {
+        task: "getting siblings",
+    })?;
+    siblings.insert(position.min(siblings.len()), note_id);
+    for (index, id) in siblings.into_iter().enumerate() {
+        sqlx::query("UPDATE notes SET position = ? WHERE id = ?")
+            .bind(index as i64)
+            .bind(id)
+            .execute(&mut *tx)
+            .await
+            .context(SqlxSnafu {
+                task: "renumbering siblings",
+            })?;
+    }
+    tx.commit().await.context(SqlxSnafu {
+        task: "commiting reorder tx",
+    })?;
+    Ok(())
+}
+
+/// Moves a note and its whole subtree to the trash.
+pub async fn delete_note(
+    db: &mut SqliteConnection,
+    note_id: i64,
+) -> Result<(), DbError> {
+    if note_id == ROOT_NOTE_ID {
+        return Err(DbError::ExecutionError {
+            trace: "the root note cannot be deleted".to_string(),
+        });
+    }
+    let mut tx = db.begin().await.context(SqlxSnafu {
+        task: "beginning transaction",
+    })?;
+    let trashed = sqlx::query(
+        r#"
+        WITH RECURSIVE subtree(id) AS (
+            SELECT id FROM notes WHERE id = ? AND deleted_at IS NULL
+
+            UNION ALL
+
+            SELECT n.id
+            FROM notes n
+            JOIN subtree s ON n.parent_id = s.id
+            WHERE n.deleted_at IS NULL
+        )
+        UPDATE notes
+        SET deleted_at = CURRENT_TIMESTAMP, trashed_with = ?
+        WHERE id IN subtree
+        "#,
+    )
+    .bind(note_id)
+    .bind(note_id)
+    .execute(&mut *tx)
     .await
     .context(SqlxSnafu {
         task: "deleting note",
     })?;
+    // Missing, or already in the trash
+    if trashed.rows_affected() == 0 {
+        return Err(DbError::NoteNotFound { id: note_id });
+    }
+    let _ = create_log(
+        &mut tx,
+        note_id,
+        "info".to_string(),
+        format!("Note {note_id} moved to trash"),
+        None,
+    )
+    .await?;
+    tx.commit().await.context(SqlxSnafu {
+        task: "commiting delete note tx",
+    })?;
     Ok(())
 }
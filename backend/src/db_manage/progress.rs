@@ -0,0 +1,149 @@
+use chrono::NaiveDate;
+use rocket_db_pools::sqlx::{self};
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+use sqlx::SqliteConnection;
+use std::collections::HashMap;
+
+use super::attributes::{AttributeType, AttributeValue, DUE_ATTRIBUTE};
+use super::errors::{DbError, SqlxSnafu};
+
+/// Completion of the leaves below a note, the tasks that actually get done.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Progress {
+    pub open: i64,
+    pub done: i64,
+    /// Latest due date in the subtree, the note itself included
+    pub latest_due: Option<NaiveDate>,
+}
+
+impl Progress {
+    pub fn total(&self) -> i64 {
+        self.open + self.done
+    }
+
+    /// Share of done leaves, `None` for notes without subnotes
+    pub fn percent(&self) -> Option<i64> {
+        (self.total() > 0).then(|| self.done * 100 / self.total())
+    }
+}
+
+pub async fn get_progress(
+    db: &mut SqliteConnection,
+    note_id: i64,
+) -> Result<Progress, DbError> {
+    let (open, done, latest_due) =
+        sqlx::query_as::<_, (i64, i64, Option<String>)>(
+            r#"
+        WITH RECURSIVE subtree(id) AS (
+            SELECT id FROM notes WHERE id = ?
+
+            UNION ALL
+
+            SELECT n.id
+            FROM notes n
+            JOIN subtree s ON n.parent_id = s.id
+            WHERE n.deleted_at IS NULL
+        ),
+        leaves(id) AS (
+            SELECT s.id FROM subtree s
+            WHERE s.id != ?
+              AND NOT EXISTS (SELECT 1 FROM notes c
+                              WHERE c.parent_id = s.id
+                                AND c.deleted_at IS NULL)
+        )
+        SELECT
+            (SELECT COUNT(*) FROM leaves l
+             JOIN note_status st ON st.note_id = l.id WHERE NOT st.done),
+            (SELECT COUNT(*) FROM leaves l
+             JOIN note_status st ON st.note_id = l.id WHERE st.done),
+            (SELECT MAX(a.value) FROM attributes a
+             WHERE a.note_id IN subtree
+               AND a.key = ? AND a.value_type = 'Date')
+        "#,
+        )
+        .bind(note_id)
+        .bind(note_id)
+        .bind(DUE_ATTRIBUTE)
+        .fetch_one(&mut *db)
+        .await
+        .context(SqlxSnafu {
+            task: "computing progress",
+        })?;
+    Ok(Progress {
+        open,
+        done,
+        latest_due: parse_due(latest_due)?,
+    })
+}
+
+/// Progress of every child of `parent_id` in one query, keyed by child id.
+pub async fn get_children_progress(
+    db: &mut SqliteConnection,
+    parent_id: i64,
+) -> Result<HashMap<i64, Progress>, DbError> {
+    let rows = sqlx::query_as::<_, (i64, i64, i64, Option<String>)>(
+        r#"
+        WITH RECURSIVE subtree(root, id) AS (
+            SELECT id, id FROM notes
+            WHERE parent_id = ? AND deleted_at IS NULL
+
+            UNION ALL
+
+            SELECT s.root, n.id
+            FROM notes n
+            JOIN subtree s ON n.parent_id = s.id
+            WHERE n.deleted_at IS NULL
+        ),
+        leaves(root, id, done) AS (
+            SELECT s.root, s.id, st.done FROM subtree s
+            JOIN note_status st ON st.note_id = s.id
+            WHERE s.id != s.root
+              AND NOT EXISTS (SELECT 1 FROM notes c
+                              WHERE c.parent_id = s.id
+                                AND c.deleted_at IS NULL)
+        )
+        SELECT r.root,
+            (SELECT COUNT(*) FROM leaves l
+             WHERE l.root = r.root AND NOT l.done),
+            (SELECT COUNT(*) FROM leaves l
+             WHERE l.root = r.root AND l.done),
+            (SELECT MAX(a.value) FROM attributes a
+             JOIN subtree s ON s.id = a.note_id
+             WHERE s.root = r.root
+               AND a.key = ? AND a.value_type = 'Date')
+        FROM (SELECT DISTINCT root FROM subtree) r
+        "#,
+    )
+    .bind(parent_id)
+    .bind(DUE_ATTRIBUTE)
+    .fetch_all(&mut *db)
+    .await
+    .context(SqlxSnafu {
+        task: "computing children progress",
+    })?;
+    rows.into_iter()
+        .map(|(id, open, done, latest_due)| {
+            Ok((
+                id,
+                Progress {
+                    open,
+                    done,
+                    latest_due: parse_due(latest_due)?,
+                },
+            ))
+        })
+        .collect()
+}
+
+fn parse_due(raw: Option<String>) -> Result<Option<NaiveDate>, DbError> {
+    let Some(raw) = raw else {
+        return Ok(None);
+    };
+    match AttributeValue::parse(AttributeType::Date, &raw) {
+        Ok(AttributeValue::Date(date)) => Ok(Some(date)),
+        _ => Err(DbError::ParseError {
+            when: format!("reading due date {raw}"),
+        }),
+    }
+}
@@ -0,0 +1,234 @@
+use rocket_db_pools::sqlx::FromRow;
+use rocket_db_pools::sqlx::{self};
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+use sqlx::{Acquire, SqliteConnection};
+use std::collections::HashMap;
+
+use super::attributes::{delete_attribute, set_attribute, AttributeValue};
+use super::errors::{DbError, SqlxSnafu};
+use super::notes::{get_note, move_note, update_note, ROOT_NOTE_ID};
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct Revision {
+    pub id: i64,
+    pub note_id: i64,
+    pub created_at: String,
+    pub field: String,
+    pub key: Option<String>,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+impl Revision {
+    pub fn label(&self) -> String {
+        match &self.key {
+            Some(key) => format!("{} {}", self.field, key),
+            None => self.field.clone(),
+        }
+    }
+
+    fn decode(&self, raw: &Option<String>) -> Option<String> {
+        let raw = raw.as_ref()?;
+        if self.field != "attribute" {
+            return Some(raw.clone());
+        }
+        serde_json::from_str::<AttributeValue>(raw)
+            .map(|v| v.to_string())
+            .ok()
+            .or(Some(raw.clone()))
+    }
+
+    /// Human readable value the revision replaced
+    pub fn before_text(&self) -> Option<String> {
+        self.decode(&self.before)
+    }
+
+    /// Human readable value the revision introduced
+    pub fn after_text(&self) -> Option<String> {
+        self.decode(&self.after)
+    }
+}
+
+async fn insert_revision(
+    db: &mut SqliteConnection,
+    note_id: i64,
+    field: &str,
+    key: Option<&str>,
+    before: Option<String>,
+    after: Option<String>,
+) -> Result<(), DbError> {
+    if before == after {
+        return Ok(());
+    }
+    sqlx::query(
+        r#"
+        INSERT INTO revisions (note_id, field, key, before, after)
+        VALUES (?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(note_id)
+    .bind(field)
+    .bind(key)
+    .bind(before)
+    .bind(after)
+    .execute(&mut *db)
+    .await
+    .context(SqlxSnafu {
+        task: "recording revision",
+    })?;
+    Ok(())
+}
+
+pub async fn record_field_change(
+    db: &mut SqliteConnection,
+    note_id: i64,
+    field: &str,
+    before: Option<&str>,
+    after: Option<&str>,
+) -> Result<(), DbError> {
+    insert_revision(
+        db,
+        note_id,
+        field,
+        None,
+        before.map(str::to_string),
+        after.map(str::to_string),
+    )
+    .await
+}
+
+pub async fn record_attribute_change(
+    db: &mut SqliteConnection,
+    note_id: i64,
+    key: &str,
+    before: Option<&AttributeValue>,
+    after: Option<&AttributeValue>,
+) -> Result<(), DbError> {
+    let encode = |v: Option<&AttributeValue>| {
+        v.map(|v| serde_json::to_string(v).unwrap_or_default())
+    };
+    insert_revision(
+        db,
+        note_id,
+        "attribute",
+        Some(key),
+        encode(before),
+        encode(after),
+    )
+    .await
+}
+
+pub async fn get_revisions(
+    db: &mut SqliteConnection,
+    note_id: i64,
+) -> Result<Vec<Revision>, DbError> {
+    let revisions = sqlx::query_as::<_, Revision>(
+        r#"
+        SELECT id, note_id, created_at, field, key, before, after
+        FROM revisions
+        WHERE note_id = ?
+        ORDER BY id DESC
+        "#,
+    )
+    .bind(note_id)
+    .fetch_all(&mut *db)
+    .await
+    .context(SqlxSnafu {
+        task: "getting revisions",
+    })?;
+    Ok(revisions)
+}
+
+/// Restores the note as it was right before `revision_id`, undoing that
+/// revision and every later one. The revert is itself recorded.
+pub async fn revert_to_revision(
+    db: &mut SqliteConnection,
+    note_id: i64,
+    revision_id: i64,
+) -> Result<(), DbError> {
+    let mut tx = db.begin().await.context(SqlxSnafu {
+        task: "beginning transaction",
+    })?;
+    let later = sqlx::query_as::<_, Revision>(
+        r#"
+        SELECT id, note_id, created_at, field, key, before, after
+        FROM revisions
+        WHERE note_id = ? AND id >= ?
+        ORDER BY id ASC
+        "#,
+    )
+    .bind(note_id)
+    .bind(revision_id)
+    .fetch_all(&mut *tx)
+    .await
+    .context(SqlxSnafu {
+        task: "getting revisions to revert",
+    })?;
+    if later.first().is_none_or(|r| r.id != revision_id) {
+        return Err(DbError::ExecutionError {
+            trace: format!(
+                "revision {revision_id} not found in note {note_id}"
+            ),
+        });
+    }
+
+    // The oldest revision of each field holds the value to go back to
+    let mut targets: HashMap<(String, Option<String>), Option<String>> =
+        HashMap::new();
+    for r in later {
+        targets.entry((r.field, r.key)).or_insert(r.before);
+    }
+
+    let note =
+        get_note(&mut tx, note_id)
+            .await?
+            .ok_or(DbError::ExecutionError {
+                trace: format!("note {note_id} not found"),
+            })?;
+    let mut title = note.title;
+    let mut description = note.description;
+    let mut code_name = note.code_name;
+    let mut parent_id = note.parent_id;
+    let mut attributes = vec![];
+    for ((field, key), before) in targets {
+        match (field.as_str(), key) {
+            ("title", _) => title = before.unwrap_or_default(),
+            ("description", _) => description = before.unwrap_or_default(),
+            ("code_name", _) => code_name = before,
+            ("parent_id", _) => {
+                // Top level notes were moved under the root note
+                parent_id = before.and_then(|id| id.parse().ok());
+            }
+            ("attribute", Some(key)) => attributes.push((key, before)),
+            (other, _) => {
+                return Err(DbError::ParseError {
+                    when: format!("reverting unknown field {other}"),
+                });
+            }
+        }
+    }
+    // Fields first, so attributes are checked against the restored code
+    update_note(&mut tx, note_id, title, description, code_name).await?;
+    if note_id != ROOT_NOTE_ID {
+        let parent_id = parent_id.unwrap_or(ROOT_NOTE_ID);
+        move_note(&mut tx, note_id, parent_id).await?;
+    }
+    for (key, before) in attributes {
+        match before {
+            Some(raw) => {
+                let value: AttributeValue = serde_json::from_str(&raw)
+                    .map_err(|e| DbError::ParseError {
+                        when: format!("reverting attribute {key}: {e}"),
+                    })?;
+                set_attribute(&mut tx, note_id, &key, &value).await?
+            }
+            None => delete_attribute(&mut tx, note_id, &key).await?,
+        }
+    }
+
+    tx.commit().await.context(SqlxSnafu {
+        task: "commiting revert tx",
+    })?;
+    Ok(())
+}
@@ -0,0 +1,203 @@
+use rocket_db_pools::sqlx::{self};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use snafu::ResultExt;
+use sqlx::{Acquire, SqliteConnection};
+use std::collections::BTreeMap;
+
+use crate::api::codes::{FormContainer, Value};
+
+use super::codes::{execute_traced, get_code_by_name, run_traced};
+use super::errors::{DbError, SqlxSnafu};
+use super::logs::Log;
+use super::notes::Note;
+use super::trace::Trace;
+use super::validation::{limited_lua, TRIAL_TIME_LIMIT};
+
+/// An attribute the action would change, with its stored values.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AttributeDiff {
+    pub note_id: i64,
+    pub key: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+/// What executing an action would have done.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Simulation {
+    /// The message of the action, or why it failed
+    pub outcome: Result<String, String>,
+    pub trace: Trace,
+    pub attributes: Vec<AttributeDiff>,
+    pub notes: Vec<Note>,
+    pub logs: Vec<Log>,
+}
+
+type Attributes = BTreeMap<(i64, String), String>;
+
+/// Attributes of a note and its descendants, the only notes an action
+/// can change.
+async fn snapshot_attributes(
+    db: &mut SqliteConnection,
+    id: i64,
+) -> Result<Attributes, DbError> {
+    let rows = sqlx::query_as::<_, (i64, String, String)>(
+        r#"
+        WITH RECURSIVE subtree(id) AS (
+            SELECT id FROM notes WHERE id = ? AND deleted_at IS NULL
+
+            UNION ALL
+
+            SELECT n.id
+            FROM notes n
+            JOIN subtree s ON n.parent_id = s.id
+            WHERE n.deleted_at IS NULL
+        )
+        SELECT a.note_id, a.key, a.value
+        FROM attributes a
+        JOIN subtree s ON s.id = a.note_id
+        "#,
+    )
+    .bind(id)
+    .fetch_all(&mut *db)
+    .await
+    .context(SqlxSnafu {
+        task: "taking attributes snapshot",
+    })?;
+    Ok(rows
+        .into_iter()
+        .map(|(note_id, key, value)| ((note_id, key), value))
+        .collect())
+}
+
+fn diff_attributes(
+    before: &Attributes,
+    after: &Attributes,
+) -> Vec<AttributeDiff> {
+    let mut keys: Vec<&(i64, String)> =
+        before.keys().chain(after.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    keys.into_iter()
+        .filter(|k| before.get(*k) != after.get(*k))
+        .map(|k| AttributeDiff {
+            note_id: k.0,
+            key: k.1.clone(),
+            before: before.get(k).cloned(),
+            after: after.get(k).cloned(),
+        })
+        .collect()
+}
+
+async fn last_id(
+    db: &mut SqliteConnection,
+    table: &str,
+) -> Result<i64, DbError> {
+    sqlx::query_scalar(&format!("SELECT COALESCE(MAX(id), 0) FROM {table}"))
+        .fetch_one(&mut *db)
+        .await
+        .context(SqlxSnafu {
+            task: format!("getting last id of {table}"),
+        })
+}
+
+/// Runs an action inside a transaction that is always rolled back, and
+/// reports the commands issued and the attributes, notes and logs it
+/// would have changed. A failing action still reports what it did
+/// before failing.
+pub async fn simulate(
+    db: &mut SqliteConnection,
+    id: i64,
+    form_container: &FormContainer,
+    value: &Value,
+) -> Result<Simulation, DbError> {
+    let mut tx = db.begin().await.context(SqlxSnafu {
+        task: "beginning simulation",
+    })?;
+    let attributes = snapshot_attributes(&mut tx, id).await?;
+    let last_note = last_id(&mut tx, "notes").await?;
+    let last_log = last_id(&mut tx, "logs").await?;
+
+    // Runaway scripts are stopped, they would hold the transaction open
+    let lua = limited_lua(TRIAL_TIME_LIMIT);
+    let mut trace = Trace::default();
+    let outcome =
+        execute_traced(&mut tx, &lua, id, form_container, value, &mut trace)
+            .await
+            .map_err(|e| e.to_string());
+
+    let attributes =
+        diff_attributes(&attributes, &snapshot_attributes(&mut tx, id).await?);
+    let notes = sqlx::query_as::<_, Note>(
+        r#"
+        SELECT id, parent_id, title, description, code_name
+        FROM notes WHERE id > ? ORDER BY id
+        "#,
+    )
+    .bind(last_note)
+    .fetch_all(&mut *tx)
+    .await
+    .context(SqlxSnafu {
+        task: "getting simulated notes",
+    })?;
+    let logs = sqlx::query_as::<_, Log>(
+        r#"
+        SELECT id, note_id, created_at AS timestamp, kind, message,
+               blob_data AS data, code_version
+        FROM logs WHERE id > ? ORDER BY id
+        "#,
+    )
+    .bind(last_log)
+    .fetch_all(&mut *tx)
+    .await
+    .context(SqlxSnafu {
+        task: "getting simulated logs",
+    })?;
+    tx.rollback().await.context(SqlxSnafu {
+        task: "rolling back simulation",
+    })?;
+    Ok(Simulation {
+        outcome,
+        trace,
+        attributes,
+        notes,
+        logs,
+    })
+}
+
+/// Runs `entry_point` of the current version of a code on a note, which
+/// need not use the code, and returns the trace of the run. Like a
+/// simulation, nothing is saved.
+pub async fn trace_code(
+    db: &mut SqliteConnection,
+    name: &str,
+    note_id: i64,
+    entry_point: &str,
+    arguments: JsonValue,
+) -> Result<Trace, DbError> {
+    let mut tx = db.begin().await.context(SqlxSnafu {
+        task: "beginning traced run",
+    })?;
+    let code = get_code_by_name(&mut tx, name).await?.ok_or(
+        DbError::CodeNotFound {
+            name: name.to_string(),
+        },
+    )?;
+    let mut trace = Trace::default();
+    // Failures end up in the trace
+    let _ = run_traced::<JsonValue>(
+        &mut tx,
+        &limited_lua(TRIAL_TIME_LIMIT),
+        code,
+        entry_point,
+        note_id,
+        arguments,
+        &mut trace,
+    )
+    .await;
+    tx.rollback().await.context(SqlxSnafu {
+        task: "rolling back traced run",
+    })?;
+    Ok(trace)
+}
@@ -0,0 +1,154 @@
+use chrono::Local;
+use rocket_db_pools::sqlx::{self};
+use snafu::ResultExt;
+use sqlx::{Acquire, SqliteConnection};
+use std::collections::HashMap;
+
+use super::errors::{DbError, SqlxSnafu};
+use super::logs::create_log;
+use super::notes::copy_subtree;
+
+/// Placeholder filled in without being declared
+pub const TODAY: &str = "today";
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Parameters declared by a template, `None` if the note is not one.
+pub async fn get_template_params(
+    db: &mut SqliteConnection,
+    note_id: i64,
+) -> Result<Option<Vec<String>>, DbError> {
+    let raw = sqlx::query_scalar::<_, Option<String>>(
+        "SELECT template_params FROM notes WHERE id = ?",
+    )
+    .bind(note_id)
+    .fetch_optional(&mut *db)
+    .await
+    .context(SqlxSnafu {
+        task: "getting template parameters",
+    })?
+    .flatten();
+    raw.map(|raw| {
+        serde_json::from_str(&raw).map_err(|e| DbError::ParseError {
+            when: format!("reading template parameters: {e}"),
+        })
+    })
+    .transpose()
+}
+
+/// Turns a note into a template declaring `params`, or back into an
+/// ordinary note with `None`.
+pub async fn set_template_params(
+    db: &mut SqliteConnection,
+    note_id: i64,
+    params: Option<Vec<String>>,
+) -> Result<(), DbError> {
+    if let Some(params) = &params {
+        if let Some(bad) = params.iter().find(|p| !valid_name(p)) {
+            return Err(DbError::InvalidTemplate {
+                reason: format!(
+                    "parameter {bad:?} must only contain letters, digits and _"
+                ),
+            });
+        }
+        if params.iter().any(|p| p == TODAY) {
+            return Err(DbError::InvalidTemplate {
+                reason: format!("{{{{{TODAY}}}}} is always available"),
+            });
+        }
+    }
+    let raw = params.map(|p| serde_json::to_string(&p).unwrap_or_default());
+    sqlx::query("UPDATE notes SET template_params = ? WHERE id = ?")
+        .bind(raw)
+        .bind(note_id)
+        .execute(&mut *db)
+        .await
+        .context(SqlxSnafu {
+            task: "setting template parameters",
+        })?;
+    Ok(())
+}
+
+/// Replaces every `{{name}}` in `text` by its value. Braces that do not
+/// close or hold an unknown name are text, and are left as they are.
+pub fn fill_placeholders(
+    text: &str,
+    values: &HashMap<String, String>,
+) -> String {
+    let mut filled = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let value = rest[start..].find("}}").and_then(|end| {
+            let name = rest[start + 2..start + end].trim();
+            values.get(name).map(|value| (value, end))
+        });
+        match value {
+            Some((value, end)) => {
+                filled.push_str(&rest[..start]);
+                filled.push_str(value);
+                rest = &rest[start + end + 2..];
+            }
+            // The second brace may open a placeholder, as in `{{{name}}`
+            None => {
+                filled.push_str(&rest[..start + 1]);
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+/// Creates a copy of the template under `parent_id` with its placeholders
+/// filled in by `values`. Returns the id of the new note.
+pub async fn instantiate_template(
+    db: &mut SqliteConnection,
+    template_id: i64,
+    parent_id: i64,
+    mut values: HashMap<String, String>,
+) -> Result<i64, DbError> {
+    let params = get_template_params(db, template_id).await?.ok_or(
+        DbError::InvalidTemplate {
+            reason: format!("note {template_id} is not a template"),
+        },
+    )?;
+    if let Some(missing) = params
+        .iter()
+        .find(|p| values.get(*p).is_none_or(|v| v.trim().is_empty()))
+    {
+        return Err(DbError::InvalidTemplate {
+            reason: format!("no value given for {missing}"),
+        });
+    }
+    values.retain(|name, _| params.contains(name));
+    values.insert(
+        TODAY.to_string(),
+        Local::now().date_naive().format("%Y-%m-%d").to_string(),
+    );
+
+    let mut tx = db.begin().await.context(SqlxSnafu {
+        task: "beginning transaction",
+    })?;
+    let (note_id, count) =
+        copy_subtree(&mut tx, template_id, parent_id, false, &|text| {
+            fill_placeholders(text, &values)
+        })
+        .await?;
+    let _ = create_log(
+        &mut tx,
+        note_id,
+        "info".to_string(),
+        format!(
+            "Note {note_id} instantiated from template {template_id} with {count} notes"
+        ),
+        None,
+    )
+    .await?;
+    tx.commit().await.context(SqlxSnafu {
+        task: "commiting instantiate tx",
+    })?;
+    Ok(note_id)
+}
@@ -0,0 +1,62 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+use super::errors::DbError;
+
+/// A command yielded by a script and what it got back.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceStep {
+    pub command: JsonValue,
+    pub response: JsonValue,
+    /// Microseconds from the start of the run to the command
+    pub at: u64,
+    /// Microseconds spent carrying the command out
+    pub took: u64,
+}
+
+/// Why a run failed, with the Lua traceback when there is one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceError {
+    pub message: String,
+    pub traceback: Option<String>,
+}
+
+impl From<&DbError> for TraceError {
+    fn from(error: &DbError) -> Self {
+        let traceback = match error {
+            DbError::ScriptError { traceback, .. } => traceback.clone(),
+            _ => None,
+        };
+        TraceError {
+            message: error.to_string(),
+            traceback,
+        }
+    }
+}
+
+/// Everything a script run did, kept as the `blob_data` of its log.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Trace {
+    pub code_name: String,
+    pub code_version: i64,
+    pub note_id: i64,
+    /// Coroutine run, `forms` or an action label
+    pub entry_point: String,
+    pub arguments: JsonValue,
+    pub steps: Vec<TraceStep>,
+    pub result: Option<JsonValue>,
+    pub error: Option<TraceError>,
+    /// Microseconds the whole run took
+    pub duration: u64,
+}
+
+impl Trace {
+    pub fn to_blob(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap_or_default()
+    }
+
+    /// The trace stored in a log, if its data is one.
+    pub fn from_blob(data: &[u8]) -> Option<Trace> {
+        serde_json::from_slice(data).ok()
+    }
+}
@@ -0,0 +1,164 @@
+use rocket_db_pools::sqlx::FromRow;
+use rocket_db_pools::sqlx::{self};
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+use sqlx::{Acquire, SqliteConnection};
+
+use super::errors::{DbError, SqlxSnafu};
+use super::logs::create_log;
+
+/// A note deleted on its own, together with the size of its trashed subtree.
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+pub struct TrashEntry {
+    pub id: i64,
+    pub title: String,
+    pub deleted_at: String,
+    pub subtree_size: i64,
+}
+
+pub async fn get_trash(
+    db: &mut SqliteConnection,
+) -> Result<Vec<TrashEntry>, DbError> {
+    let entries = sqlx::query_as::<_, TrashEntry>(
+        r#"
+        SELECT n.id, n.title, n.deleted_at,
+               (SELECT COUNT(*) FROM notes t
+                WHERE t.trashed_with = n.id) AS subtree_size
+        FROM notes n
+        WHERE n.trashed_with = n.id
+        ORDER BY n.deleted_at DESC
+        "#,
+    )
+    .fetch_all(&mut *db)
+    .await
+    .context(SqlxSnafu {
+        task: "getting trash",
+    })?;
+    Ok(entries)
+}
+
+/// Fails for notes in the trash, which can be looked at but not run.
+pub async fn check_not_trashed(
+    db: &mut SqliteConnection,
+    note_id: i64,
+) -> Result<(), DbError> {
+    let trashed = sqlx::query_scalar::<_, bool>(
+        "SELECT deleted_at IS NOT NULL FROM notes WHERE id = ?",
+    )
+    .bind(note_id)
+    .fetch_optional(&mut *db)
+    .await
+    .context(SqlxSnafu {
+        task: "checking whether note is trashed",
+    })?;
+    match trashed {
+        Some(true) => Err(DbError::NoteInTrash { id: note_id }),
+        _ => Ok(()),
+    }
+}
+
+/// Brings back a trashed subtree. Its parent must not be in the trash.
+pub async fn restore_note(
+    db: &mut SqliteConnection,
+    note_id: i64,
+) -> Result<(), DbError> {
+    let mut tx = db.begin().await.context(SqlxSnafu {
+        task: "beginning transaction",
+    })?;
+    let parent_trashed = sqlx::query_scalar::<_, bool>(
+        r#"
+        SELECT p.deleted_at IS NOT NULL
+        FROM notes n
+        JOIN notes p ON p.id = n.parent_id
+        WHERE n.id = ?
+        "#,
+    )
+    .bind(note_id)
+    .fetch_optional(&mut *tx)
+    .await
+    .context(SqlxSnafu {
+        task: "checking parent of trashed note",
+    })?;
+    if parent_trashed == Some(true) {
+        return Err(DbError::ExecutionError {
+            trace: format!(
+                "the parent of note {note_id} is in the trash, restore it first"
+            ),
+        });
+    }
+    let restored = sqlx::query(
+        r#"
+        UPDATE notes
+        SET deleted_at = NULL, trashed_with = NULL
+        WHERE trashed_with = ?
+        "#,
+    )
+    .bind(note_id)
+    .execute(&mut *tx)
+    .await
+    .context(SqlxSnafu {
+        task: "restoring note",
+    })?;
+    if restored.rows_affected() == 0 {
+        return Err(DbError::ExecutionError {
+            trace: format!("note {note_id} is not in the trash"),
+        });
+    }
+    let _ = create_log(
+        &mut tx,
+        note_id,
+        "info".to_string(),
+        format!("Note {note_id} restored from trash"),
+        None,
+    )
+    .await?;
+    tx.commit().await.context(SqlxSnafu {
+        task: "commiting restore tx",
+    })?;
+    Ok(())
+}
+
+/// Permanently deletes a trashed subtree, with its attributes and logs.
+/// Only the note that was trashed can be purged, not one of its
+/// descendants.
+pub async fn purge_note(
+    db: &mut SqliteConnection,
+    note_id: i64,
+) -> Result<(), DbError> {
+    let purged =
+        sqlx::query("DELETE FROM notes WHERE id = ? AND trashed_with = id")
+            .bind(note_id)
+            .execute(&mut *db)
+            .await
+            .context(SqlxSnafu {
+                task: "purging note",
+            })?;
+    if purged.rows_affected() == 0 {
+        return Err(DbError::ExecutionError {
+            trace: format!("note {note_id} is not in the trash"),
+        });
+    }
+    Ok(())
+}
+
+/// Purges everything that has been in the trash for longer than
+/// `retention_days`. Returns how many subtrees were removed.
+pub async fn purge_expired_trash(
+    db: &mut SqliteConnection,
+    retention_days: i64,
+) -> Result<u64, DbError> {
+    let result = sqlx::query(
+        r#"
+        DELETE FROM notes
+        WHERE trashed_with = id
+          AND deleted_at < datetime('now', '-' || ? || ' days')
+        "#,
+    )
+    .bind(retention_days)
+    .execute(&mut *db)
+    .await
+    .context(SqlxSnafu {
+        task: "purging expired trash",
+    })?;
+    Ok(result.rows_affected())
+}
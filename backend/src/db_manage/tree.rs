@@ -0,0 +1,82 @@
+use rocket_db_pools::sqlx::FromRow;
+use rocket_db_pools::sqlx::{self};
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+use sqlx::SqliteConnection;
+use std::collections::{HashMap, HashSet};
+
+use super::errors::{DbError, SqlxSnafu};
+use super::notes::ROOT_NOTE_ID;
+
+/// A note as listed in the tree, in depth first order.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct TreeNode {
+    pub id: i64,
+    pub parent_id: Option<i64>,
+    pub title: String,
+    pub code_name: Option<String>,
+    pub depth: i64,
+    pub done: bool,
+    pub open_children: i64,
+}
+
+/// The whole hierarchy below the root note, fetched in one query.
+///
+/// With `code_name`, only notes running that code are kept, together with
+/// their ancestors so the tree stays connected.
+pub async fn get_tree(
+    db: &mut SqliteConnection,
+    code_name: Option<&str>,
+) -> Result<Vec<TreeNode>, DbError> {
+    let nodes = sqlx::query_as::<_, TreeNode>(
+        r#"
+        WITH RECURSIVE tree(id, parent_id, title, code_name, depth, path) AS (
+            SELECT id, parent_id, title, code_name, 0, ''
+            FROM notes
+            WHERE id = ?
+
+            UNION ALL
+
+            SELECT n.id, n.parent_id, n.title, n.code_name, t.depth + 1,
+                   t.path || printf('%012d.%012d/', n.position, n.id)
+            FROM notes n
+            JOIN tree t ON n.parent_id = t.id
+            WHERE n.deleted_at IS NULL
+        )
+        SELECT t.id, t.parent_id, t.title, t.code_name, t.depth, s.done,
+               (SELECT COUNT(*) FROM notes c
+                JOIN note_status cs ON cs.note_id = c.id
+                WHERE c.parent_id = t.id AND c.deleted_at IS NULL
+                  AND NOT cs.done) AS open_children
+        FROM tree t
+        JOIN note_status s ON s.note_id = t.id
+        ORDER BY t.path
+        "#,
+    )
+    .bind(ROOT_NOTE_ID)
+    .fetch_all(&mut *db)
+    .await
+    .context(SqlxSnafu {
+        task: "getting note tree",
+    })?;
+
+    let Some(code_name) = code_name else {
+        return Ok(nodes);
+    };
+    let parents: HashMap<i64, Option<i64>> =
+        nodes.iter().map(|n| (n.id, n.parent_id)).collect();
+    let mut kept = HashSet::new();
+    for node in nodes.iter() {
+        if node.code_name.as_deref() != Some(code_name) {
+            continue;
+        }
+        let mut current = Some(node.id);
+        while let Some(id) = current {
+            if !kept.insert(id) {
+                break;
+            }
+            current = parents.get(&id).copied().flatten();
+        }
+    }
+    Ok(nodes.into_iter().filter(|n| kept.contains(&n.id)).collect())
+}
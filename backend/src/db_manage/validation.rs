@@ -0,0 +1,232 @@
+use mlua::{Lua, VmState};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use super::attributes::{parse_schema, AttributeSpec};
+use super::codes::{gated_commands, Capabilities};
+
+/// Something wrong with a code being saved, located when possible.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CodeProblem {
+    /// Form field holding the problem: capabilities, script...
+    pub field: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for CodeProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.field)?;
+        if let Some(line) = self.line {
+            write!(f, " line {line}")?;
+        }
+        if let Some(column) = self.column {
+            write!(f, ", column {column}")?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+fn problem(field: &str, message: String) -> CodeProblem {
+    CodeProblem {
+        field: field.to_string(),
+        line: None,
+        column: None,
+        message,
+    }
+}
+
+fn json_problem(field: &str, e: serde_json::Error) -> CodeProblem {
+    let location = format!(" at line {} column {}", e.line(), e.column());
+    let message = e.to_string();
+    CodeProblem {
+        line: Some(e.line()),
+        column: Some(e.column()),
+        ..problem(field, message.trim_end_matches(&location).to_string())
+    }
+}
+
+/// Splits a Lua error reported as `chunk:line: message`.
+pub(crate) fn locate_lua_error(text: &str) -> Option<(&str, usize, &str)> {
+    let (chunk, rest) = text.split_once(':')?;
+    let (line, message) = rest.split_once(':')?;
+    Some((chunk, line.trim().parse().ok()?, message.trim()))
+}
+
+fn lua_problem(e: mlua::Error) -> CodeProblem {
+    let text = match &e {
+        mlua::Error::SyntaxError { message, .. } => message.clone(),
+        mlua::Error::RuntimeError(message) => message.clone(),
+        other => other.to_string(),
+    };
+    match locate_lua_error(&text) {
+        Some((_, line, message)) => CodeProblem {
+            line: Some(line),
+            ..problem("script", message.to_string())
+        },
+        None => problem("script", text),
+    }
+}
+
+/// How long the top level of a checked script may run.
+const CHECK_TIME_LIMIT: Duration = Duration::from_millis(500);
+/// How long a run tried out from the code or note pages may take:
+/// simulations, traces and test cases.
+pub(crate) const TRIAL_TIME_LIMIT: Duration = Duration::from_secs(5);
+/// How much memory a limited script may use.
+const CHECK_MEMORY_LIMIT: usize = 16 * 1024 * 1024;
+
+/// A Lua state stopping scripts that run longer than `time_limit` or use
+/// too much memory, for scripts that run outside of a note action.
+pub(crate) fn limited_lua(time_limit: Duration) -> Lua {
+    let lua = Lua::new();
+    let _ = lua.set_memory_limit(CHECK_MEMORY_LIMIT);
+    let deadline = Instant::now() + time_limit;
+    lua.set_interrupt(move |_| {
+        if Instant::now() > deadline {
+            return Err(mlua::Error::runtime(format!(
+                "the script ran for more than {} ms",
+                time_limit.as_millis()
+            )));
+        }
+        Ok(VmState::Continue)
+    });
+    lua
+}
+
+/// Everything wrong with a code, checked without touching the database:
+/// capabilities and attribute schema must parse, and the script must
+/// compile and define the `forms` coroutine. The script runs in a
+/// throwaway, [limited](limited_lua) Lua state, so only its top level
+/// executes. Libraries are only compiled, their top level may import other
+/// libraries.
+pub fn check_code(
+    capabilities: &str,
+    script: &str,
+    attribute_schema: &str,
+    library: bool,
+) -> Vec<CodeProblem> {
+    let mut problems = vec![];
+    if let Err(e) = serde_json::from_str::<Vec<Capabilities>>(capabilities) {
+        problems.push(json_problem("capabilities", e));
+    }
+    match serde_json::from_str::<Vec<AttributeSpec>>(attribute_schema) {
+        Err(e) => problems.push(json_problem("attribute_schema", e)),
+        Ok(_) => {
+            if let Err(e) = parse_schema(attribute_schema) {
+                problems.push(problem("attribute_schema", e.to_string()));
+            }
+        }
+    }
+    let lua = limited_lua(CHECK_TIME_LIMIT);
+    let chunk = lua.load(script).set_name("script");
+    if library {
+        if let Err(e) = chunk.into_function() {
+            problems.push(lua_problem(e));
+        }
+        return problems;
+    }
+    match chunk.exec() {
+        Err(e) => problems.push(lua_problem(e)),
+        Ok(()) => {
+            let forms = lua.globals().get::<mlua::Value>("forms");
+            if !matches!(forms, Ok(mlua::Value::Thread(_))) {
+                problems.push(problem(
+                    "script",
+                    "no forms coroutine, define one with \
+                     forms = coroutine.create(function() ... end)"
+                        .to_string(),
+                ));
+            }
+        }
+    }
+    problems
+}
+
+fn is_identifier(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Gated commands a script yields, found as `Command =` table keys.
+/// This is a textual scan: a key built at run time goes unnoticed, and one
+/// inside a comment or a string counts as used.
+pub fn used_commands(script: &str) -> Vec<&'static str> {
+    gated_commands()
+        .filter(|command| {
+            script.match_indices(command).any(|(start, _)| {
+                let before = script[..start].chars().next_back();
+                let after = &script[start + command.len()..];
+                let value = after.trim_start();
+                !before.is_some_and(is_identifier)
+                    && !after.starts_with(is_identifier)
+                    && value.starts_with('=')
+                    && !value.starts_with("==")
+            })
+        })
+        .collect()
+}
+
+/// How the granted capabilities compare with the commands a code yields.
+#[derive(Debug, Default, PartialEq)]
+pub struct CapabilityReport {
+    /// Yielded but not granted, these calls would be rejected
+    pub missing: Vec<&'static str>,
+    /// Granted but never yielded
+    pub unused: Vec<&'static str>,
+}
+
+impl CapabilityReport {
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.unused.is_empty()
+    }
+}
+
+impl fmt::Display for CapabilityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = vec![];
+        if !self.missing.is_empty() {
+            parts.push(format!(
+                "used but not granted: {}",
+                self.missing.join(", ")
+            ));
+        }
+        if !self.unused.is_empty() {
+            parts.push(format!(
+                "granted but unused: {}",
+                self.unused.join(", ")
+            ));
+        }
+        write!(f, "Capabilities {}.", parts.join("; "))
+    }
+}
+
+/// Compares `capabilities` with the commands used by `scripts`, which are
+/// a code's script followed by those of the libraries it imports, as
+/// these run with the importer's capabilities. Capabilities that do not
+/// parse are left to [`check_code`].
+pub fn capability_report(
+    capabilities: &str,
+    scripts: &[&str],
+) -> CapabilityReport {
+    let granted: Vec<&'static str> =
+        serde_json::from_str::<Vec<Capabilities>>(capabilities)
+            .map(|c| c.iter().map(Capabilities::command).collect())
+            .unwrap_or_default();
+    let mut used: Vec<&'static str> =
+        scripts.iter().flat_map(|s| used_commands(s)).collect();
+    used.sort();
+    used.dedup();
+    let mut unused: Vec<&'static str> = granted
+        .iter()
+        .copied()
+        .filter(|c| !used.contains(c))
+        .collect();
+    unused.sort();
+    unused.dedup();
+    CapabilityReport {
+        missing: used.into_iter().filter(|c| !granted.contains(c)).collect(),
+        unused,
+    }
+}
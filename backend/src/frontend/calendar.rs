@@ -0,0 +1,154 @@
+use chrono::{Duration, Local, NaiveDateTime, TimeZone, Utc};
+use rocket::get;
+use rocket::http::{ContentType, Status};
+use rocket_db_pools::Connection;
+
+use crate::db_manage::agenda::{get_calendar_entries, CalendarEntry};
+use crate::db_manage::errors::DbError;
+use crate::db_manage::login::get_calendar_token;
+use crate::db_manage::{Db, ROOT_NOTE_ID};
+
+const DATE: &str = "%Y%m%d";
+const UTC_TIME: &str = "%Y%m%dT%H%M%SZ";
+
+/// Escapes text values as required by RFC 5545.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+/// Folds a content line so that no line exceeds 75 octets.
+fn push_line(ics: &mut String, line: &str) {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            ics.push_str("\r\n ");
+            width = 1;
+        }
+        ics.push(c);
+        width += c.len_utf8();
+    }
+    ics.push_str("\r\n");
+}
+
+fn to_utc(time: NaiveDateTime) -> String {
+    Local
+        .from_local_datetime(&time)
+        .earliest()
+        .map(|t| t.with_timezone(&Utc).naive_utc())
+        .unwrap_or(time)
+        .format(UTC_TIME)
+        .to_string()
+}
+
+/// iCalendar priorities go from 1 (highest) to 9, ours grow the other way.
+fn ical_priority(priority: i64) -> Option<i64> {
+    match priority {
+        0 => None,
+        p if p > 0 => Some((5 - p).max(1)),
+        p => Some((5 - p).min(9)),
+    }
+}
+
+fn push_alarm(ics: &mut String, entry: &CalendarEntry) {
+    if let (Some(alarm), false) = (entry.alarm, entry.done) {
+        push_line(ics, "BEGIN:VALARM");
+        push_line(ics, "ACTION:DISPLAY");
+        push_line(ics, &format!("DESCRIPTION:{}", escape(&entry.title)));
+        push_line(ics, &format!("TRIGGER;VALUE=DATE-TIME:{}", to_utc(alarm)));
+        push_line(ics, "END:VALARM");
+    }
+}
+
+/// Each note becomes a VTODO carrying its status, and an all-day VEVENT
+/// on its due date for clients that do not show tasks. The alarm goes on
+/// the event only, which every client shows, so it does not ring twice.
+pub fn render_ics(entries: &[CalendarEntry], stamp: NaiveDateTime) -> String {
+    let stamp = stamp.format(UTC_TIME);
+    let mut ics = String::new();
+    push_line(&mut ics, "BEGIN:VCALENDAR");
+    push_line(&mut ics, "VERSION:2.0");
+    push_line(&mut ics, "PRODID:-//Orbitask//Notes//EN");
+    for entry in entries {
+        let summary = escape(&entry.title);
+        let description = escape(&entry.description);
+        let due = entry.due.format(DATE);
+
+        push_line(&mut ics, "BEGIN:VTODO");
+        push_line(&mut ics, &format!("UID:note-{}-todo@orbitask", entry.id));
+        push_line(&mut ics, &format!("DTSTAMP:{stamp}"));
+        push_line(&mut ics, &format!("SUMMARY:{summary}"));
+        if !description.is_empty() {
+            push_line(&mut ics, &format!("DESCRIPTION:{description}"));
+        }
+        push_line(&mut ics, &format!("DUE;VALUE=DATE:{due}"));
+        let status = if entry.done {
+            "COMPLETED"
+        } else {
+            "NEEDS-ACTION"
+        };
+        push_line(&mut ics, &format!("STATUS:{status}"));
+        if let Some(priority) = ical_priority(entry.priority) {
+            push_line(&mut ics, &format!("PRIORITY:{priority}"));
+        }
+        push_line(&mut ics, "END:VTODO");
+
+        push_line(&mut ics, "BEGIN:VEVENT");
+        push_line(&mut ics, &format!("UID:note-{}-event@orbitask", entry.id));
+        push_line(&mut ics, &format!("DTSTAMP:{stamp}"));
+        push_line(&mut ics, &format!("SUMMARY:{summary}"));
+        push_line(&mut ics, &format!("DTSTART;VALUE=DATE:{due}"));
+        let end = (entry.due + Duration::days(1)).format(DATE);
+        push_line(&mut ics, &format!("DTEND;VALUE=DATE:{end}"));
+        push_line(&mut ics, "TRANSP:TRANSPARENT");
+        push_alarm(&mut ics, entry);
+        push_line(&mut ics, "END:VEVENT");
+    }
+    push_line(&mut ics, "END:VCALENDAR");
+    ics
+}
+
+/// Compares every byte up to the longer length, with the lengths folded
+/// in, so that response times do not tell how much of a guessed token
+/// is right nor whether its length is.
+fn same_token(expected: &str, given: &str) -> bool {
+    let (expected, given) = (expected.as_bytes(), given.as_bytes());
+    let length = expected.len().max(given.len());
+    let diff = (0..length).fold(expected.len() ^ given.len(), |diff, i| {
+        let a = expected.get(i).copied().unwrap_or(0);
+        let b = given.get(i).copied().unwrap_or(0);
+        diff | usize::from(a ^ b)
+    });
+    diff == 0
+}
+
+/// Calendar clients only get a status, the cause goes to the server log.
+fn feed_error(e: DbError) -> Status {
+    println!("Calendar feed failed: {e}");
+    Status::InternalServerError
+}
+
+/// Calendar clients cannot log in, so the feed checks a token instead of
+/// the session cookie. `note` restricts it to a subtree.
+#[get("/calendar.ics?<token>&<note>")]
+pub async fn calendar_feed(
+    token: Option<&str>,
+    note: Option<i64>,
+    mut db: Connection<Db>,
+) -> Result<(ContentType, String), Status> {
+    let expected = get_calendar_token(&mut db).await.map_err(feed_error)?;
+    match (expected, token) {
+        (Some(expected), Some(token)) if same_token(&expected, token) => {}
+        _ => return Err(Status::Forbidden),
+    }
+    let entries = get_calendar_entries(&mut db, note.unwrap_or(ROOT_NOTE_ID))
+        .await
+        .map_err(feed_error)?;
+    Ok((
+        ContentType::Calendar,
+        render_ics(&entries, Utc::now().naive_utc()),
+    ))
+}
@@ -1,3 +1,7 @@
+use crate::db_manage::code_tests::get_code_tests;
+use crate::db_manage::code_versions::{get_code_versions, get_outdated_notes};
+use crate::db_manage::codes::get_code_users;
+use crate::db_manage::dependencies::{get_dependency_tree, get_importers};
 use crate::frontend::notes::rocket_uri_macro_root_notes;
 use crate::frontend::notes::rocket_uri_macro_show_note;
 use crate::{api::Authenticated, db_manage::Db};
@@ -11,12 +15,15 @@ use rocket_db_pools::Connection;
 
 use crate::frontend::view::{MyFlash, View, ViewState};
 
-#[get("/codes/<name>?<note>")]
+/// `version` selects the version whose changes are shown, the current one
+/// by default.
+#[get("/codes/<name>?<note>&<version>")]
 pub async fn view_code(
     _auth: Authenticated,
     mut db: Connection<Db>,
     name: String,
     note: Option<String>,
+    version: Option<i64>,
     flash: Option<FlashMessage<'_>>,
 ) -> Result<View, Flash<Redirect>> {
     use crate::db_manage::codes::get_code_by_name;
@@ -30,12 +37,93 @@ pub async fn view_code(
             )
         })?
         .ok_or_else(|| Flash::error(Redirect::to("/"), "Code not found"))?;
+    let versions = get_code_versions(&mut db, &name).await.map_err(|e| {
+        Flash::error(Redirect::to(uri!(root_notes)), format!("DB error: {e}"))
+    })?;
+    let selected = version.unwrap_or(code.version);
+    let importers = get_importers(&mut db, &name).await.map_err(|e| {
+        Flash::error(Redirect::to(uri!(root_notes)), format!("DB error: {e}"))
+    })?;
+    let dependencies =
+        get_dependency_tree(&mut db, &name).await.map_err(|e| {
+            Flash::error(
+                Redirect::to(uri!(root_notes)),
+                format!("DB error: {e}"),
+            )
+        })?;
 
     let next: Option<String> =
         note.map(|id| uri!(show_note(id.parse::<i64>().unwrap())).to_string());
 
     Ok(View {
-        state: ViewState::Code(code, next),
+        state: ViewState::Code(
+            code,
+            versions,
+            importers,
+            dependencies,
+            selected,
+            next,
+        ),
+        flash: flash.into_iter().map(MyFlash::from).collect(),
+    })
+}
+
+#[get("/codes/<name>/upgrade")]
+pub async fn upgrade_code(
+    _auth: Authenticated,
+    mut db: Connection<Db>,
+    name: String,
+    flash: Option<FlashMessage<'_>>,
+) -> Result<View, Flash<Redirect>> {
+    use crate::db_manage::codes::get_code_by_name;
+
+    let code = get_code_by_name(&mut db, &name)
+        .await
+        .map_err(|e| Flash::error(Redirect::to("/"), format!("DB error: {e}")))?
+        .ok_or_else(|| Flash::error(Redirect::to("/"), "Code not found"))?;
+    let notes = get_outdated_notes(&mut db, &name).await.map_err(|e| {
+        Flash::error(Redirect::to("/"), format!("DB error: {e}"))
+    })?;
+
+    Ok(View {
+        state: ViewState::CodeUpgrade(code, notes),
+        flash: flash.into_iter().map(MyFlash::from).collect(),
+    })
+}
+
+#[get("/codes/<name>/delete/confirm")]
+pub async fn delete_code_confirm(
+    _auth: Authenticated,
+    mut db: Connection<Db>,
+    name: String,
+    flash: Option<FlashMessage<'_>>,
+) -> Result<View, Flash<Redirect>> {
+    let users = get_code_users(&mut db, &name).await.map_err(|e| {
+        Flash::error(Redirect::to("/"), format!("DB error: {e}"))
+    })?;
+    let importers = get_importers(&mut db, &name).await.map_err(|e| {
+        Flash::error(Redirect::to("/"), format!("DB error: {e}"))
+    })?;
+
+    Ok(View {
+        state: ViewState::CodeConfirmDelete(name, users, importers),
+        flash: flash.into_iter().map(MyFlash::from).collect(),
+    })
+}
+
+#[get("/codes/<name>/tests")]
+pub async fn code_tests(
+    _auth: Authenticated,
+    mut db: Connection<Db>,
+    name: String,
+    flash: Option<FlashMessage<'_>>,
+) -> Result<View, Flash<Redirect>> {
+    let tests = get_code_tests(&mut db, &name).await.map_err(|e| {
+        Flash::error(Redirect::to("/"), format!("DB error: {e}"))
+    })?;
+
+    Ok(View {
+        state: ViewState::CodeTests(name, tests, None),
         flash: flash.into_iter().map(MyFlash::from).collect(),
     })
 }
@@ -46,7 +134,7 @@ pub async fn new_code(
     flash: Option<FlashMessage<'_>>,
 ) -> Result<View, Flash<Redirect>> {
     Ok(View {
-        state: ViewState::CodeNew(),
+        state: ViewState::CodeNew(None, vec![]),
         flash: flash.into_iter().map(MyFlash::from).collect(),
     })
 }
@@ -67,7 +155,7 @@ pub async fn edit_code(
         .ok_or_else(|| Flash::error(Redirect::to("/"), "Code not found"))?;
 
     Ok(View {
-        state: ViewState::CodeEdit(code, next),
+        state: ViewState::CodeEdit(code, next, vec![]),
         flash: flash.into_iter().map(MyFlash::from).collect(),
     })
 }
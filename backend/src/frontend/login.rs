@@ -4,8 +4,8 @@ use rocket::request::FlashMessage;
 
 #[get("/login")]
 pub async fn login(flash: Option<FlashMessage<'_>>) -> View {
-    return View {
+    View {
         state: ViewState::Login,
         flash: flash.into_iter().map(MyFlash::from).collect(),
-    };
+    }
 }
@@ -1,3 +1,4 @@
+pub mod calendar;
 pub mod codes;
 pub mod login;
 pub mod notes;
@@ -1,31 +1,54 @@
-use crate::db_manage::attributes::get_attributes;
+use crate::api::notes::{HIDE_DONE_COOKIE, PRIORITY_SORT_COOKIE};
+use crate::db_manage::agenda::get_agenda;
+use crate::db_manage::attributes::{
+    get_attribute_schema, get_attributes, AttributeValue,
+};
+use crate::db_manage::board::{get_board, DEFAULT_BOARD_KEY};
+use crate::db_manage::code_versions::{get_code_versions, get_pinned_version};
 use crate::db_manage::codes::get_forms;
-use crate::db_manage::logs::{get_logs_from_note, Log};
-use crate::db_manage::notes::get_ancestors;
+use crate::db_manage::login::get_calendar_token;
+use crate::db_manage::logs::{get_log, get_logs_from_note, Log};
+use crate::db_manage::notes::{
+    get_all_notes, get_ancestors, get_child_notes_with_status, get_subtree_ids,
+};
+use crate::db_manage::progress::{get_children_progress, get_progress};
+use crate::db_manage::revisions::get_revisions;
+use crate::db_manage::templates::get_template_params;
+use crate::db_manage::trash::get_trash;
+use crate::db_manage::tree::get_tree;
 use crate::db_manage::Db;
+use chrono::Local;
 use rocket::get;
+use rocket::http::CookieJar;
 use rocket::request::FlashMessage;
 use rocket::response::{Flash, Redirect};
 use rocket::uri;
 use rocket_db_pools::Connection;
+use std::collections::HashMap;
 
 use crate::api::Authenticated;
-use crate::db_manage::codes::get_all_code_names;
-use crate::db_manage::{get_child_notes, get_note, get_root_notes};
+use crate::db_manage::codes::{get_all_code_names, get_note_code_names};
+use crate::db_manage::{get_note, Note, ROOT_NOTE_ID};
 
-use super::view::{MyFlash, View, ViewState};
+use super::view::{ListOptions, MyFlash, View, ViewState};
 
 #[get("/")]
 pub async fn root_notes(
     flash: Option<FlashMessage<'_>>,
     _auth: Authenticated,
+    jar: &CookieJar<'_>,
     mut db: Connection<Db>,
-) -> View {
-    let notes = get_root_notes(&mut db).await.unwrap_or_default();
-    return View {
-        state: ViewState::Root(notes),
-        flash: flash.into_iter().map(MyFlash::from).collect(),
-    };
+) -> Result<View, Flash<Redirect>> {
+    // Redirecting to "/" on failure would loop, the login page does not
+    // need any note
+    note_view(&mut db, ROOT_NOTE_ID, jar, flash)
+        .await
+        .map_err(|e| {
+            Flash::error(
+                Redirect::to("/login"),
+                format!("Failed to load the root note: {e}"),
+            )
+        })
 }
 
 #[get("/notes/<id>")]
@@ -33,38 +56,62 @@ pub async fn show_note(
     _auth: Authenticated,
     id: i64,
     flash: Option<FlashMessage<'_>>,
+    jar: &CookieJar<'_>,
     mut db: Connection<Db>,
 ) -> Result<View, Flash<Redirect>> {
-    let note = match get_note(&mut db, id).await {
+    note_view(&mut db, id, jar, flash)
+        .await
+        .map_err(|e| Flash::error(Redirect::to("/"), e))
+}
+
+async fn note_view(
+    db: &mut Connection<Db>,
+    id: i64,
+    jar: &CookieJar<'_>,
+    flash: Option<FlashMessage<'_>>,
+) -> Result<View, String> {
+    let note = match get_note(db, id).await {
         Ok(Some(note)) => note,
-        Ok(None) => {
-            return Err(Flash::error(Redirect::to("/"), "Note not found."))
-        }
-        Err(e) => {
-            return Err(Flash::error(
-                Redirect::to("/"),
-                format!("Failed to load note: {e}"),
-            ))
-        }
+        Ok(None) => return Err("Note not found.".to_string()),
+        Err(e) => return Err(format!("Failed to load note: {e}")),
     };
 
-    let child_notes = get_child_notes(&mut db, id).await.unwrap();
+    let options = ListOptions {
+        hide_done: jar.get(HIDE_DONE_COOKIE).is_some(),
+        by_priority: jar.get(PRIORITY_SORT_COOKIE).is_some(),
+    };
+    let mut children_progress = get_children_progress(db, id)
+        .await
+        .map_err(|e| format!("Failed to compute progress: {e}"))?;
+    let child_notes = get_child_notes_with_status(db, id, options.by_priority)
+        .await
+        .map_err(|e| format!("Failed to load subnotes: {e}"))?
+        .into_iter()
+        .map(|(child, done)| {
+            let progress =
+                children_progress.remove(&child.id).unwrap_or_default();
+            (child, done, progress)
+        })
+        .collect::<Vec<_>>();
+    let progress = get_progress(db, id)
+        .await
+        .map_err(|e| format!("Failed to compute progress: {e}"))?;
 
-    let attributes: Vec<(String, String)> =
-        get_attributes(&mut db, id).await.unwrap();
+    let attributes: Vec<(String, AttributeValue)> = get_attributes(db, id)
+        .await
+        .map_err(|e| format!("Failed to load attributes: {e}"))?;
 
-    let logs: Vec<Log> =
-        get_logs_from_note(&mut db, id).await.map_err(|e| {
-            Flash::error(Redirect::to("/"), format!("Failed to load logs: {e}"))
-        })?;
+    let logs: Vec<Log> = get_logs_from_note(db, id)
+        .await
+        .map_err(|e| format!("Failed to load logs: {e}"))?;
 
-    let forms = get_forms(&mut db, note.id).await.map_err(|e| {
-        Flash::error(Redirect::to("/"), format!("Failed to load forms: {e}"))
-    })?;
+    let forms = get_forms(db, note.id)
+        .await
+        .map_err(|e| format!("Failed to load forms: {e}"))?;
 
-    let ancestors = get_ancestors(&mut db, id).await.map_err(|e| {
-        Flash::error(Redirect::to("/"), format!("Failed to get ancestors: {e}"))
-    })?;
+    let ancestors = get_ancestors(db, id)
+        .await
+        .map_err(|e| format!("Failed to get ancestors: {e}"))?;
 
     Ok(View {
         state: ViewState::Note(
@@ -74,6 +121,8 @@ pub async fn show_note(
             child_notes,
             ancestors,
             logs,
+            options,
+            progress,
         ),
         flash: flash.into_iter().map(MyFlash::from).collect(),
     })
@@ -86,7 +135,7 @@ pub async fn new_note(
     mut db: Connection<Db>,
     flash: Option<FlashMessage<'_>>,
 ) -> Result<View, Flash<Redirect>> {
-    let codes = get_all_code_names(&mut db).await.map_err(|e| {
+    let codes = get_note_code_names(&mut db).await.map_err(|e| {
         Flash::error(
             Redirect::to(uri!(root_notes)),
             format!("Failed to load code list: {e}."),
@@ -117,12 +166,31 @@ pub async fn edit_note(
 
     let attributes = get_attributes(&mut db, id).await.unwrap_or_default();
 
-    let codes = get_all_code_names(&mut db).await.map_err(|e| {
+    let schema = get_attribute_schema(&mut db, id).await.map_err(|e| {
+        Flash::error(Redirect::to(uri!(show_note(id))), format!("Error: {e}"))
+    })?;
+
+    let codes = get_note_code_names(&mut db).await.map_err(|e| {
         Flash::error(Redirect::to(uri!(root_notes)), format!("Error: {e}"))
     })?;
 
+    let pinned = get_pinned_version(&mut db, id).await.map_err(|e| {
+        Flash::error(Redirect::to(uri!(show_note(id))), format!("Error: {e}"))
+    })?;
+    let versions = match &note.code_name {
+        Some(name) => get_code_versions(&mut db, name).await.map_err(|e| {
+            Flash::error(
+                Redirect::to(uri!(show_note(id))),
+                format!("Error: {e}"),
+            )
+        })?,
+        None => vec![],
+    };
+
     Ok(View {
-        state: ViewState::NoteEdit(id, note, codes, attributes),
+        state: ViewState::NoteEdit(
+            id, note, codes, attributes, schema, pinned, versions,
+        ),
         flash: flash.into_iter().map(MyFlash::from).collect(),
     })
 }
@@ -143,3 +211,260 @@ pub async fn delete_note_confirm(
         flash: flash.into_iter().map(MyFlash::from).collect(),
     })
 }
+
+#[get("/notes/<id>/history")]
+pub async fn note_history(
+    _auth: Authenticated,
+    id: i64,
+    flash: Option<FlashMessage<'_>>,
+    mut db: Connection<Db>,
+) -> Result<View, Flash<Redirect>> {
+    let note = get_note(&mut db, id)
+        .await
+        .map_err(|e| {
+            Flash::error(Redirect::to(uri!(root_notes)), format!("Error: {e}"))
+        })?
+        .ok_or_else(|| {
+            Flash::error(Redirect::to(uri!(root_notes)), "Note not found")
+        })?;
+    let revisions = get_revisions(&mut db, id).await.map_err(|e| {
+        Flash::error(
+            Redirect::to(uri!(show_note(id))),
+            format!("Failed to load history: {e}"),
+        )
+    })?;
+    Ok(View {
+        state: ViewState::NoteHistory(note, revisions),
+        flash: flash.into_iter().map(MyFlash::from).collect(),
+    })
+}
+
+/// A log with the trace of its run, if it has one.
+#[get("/logs/<id>")]
+pub async fn view_log(
+    _auth: Authenticated,
+    id: i64,
+    flash: Option<FlashMessage<'_>>,
+    mut db: Connection<Db>,
+) -> Result<View, Flash<Redirect>> {
+    let log = get_log(&mut db, id)
+        .await
+        .map_err(|e| {
+            Flash::error(Redirect::to(uri!(root_notes)), format!("Error: {e}"))
+        })?
+        .ok_or_else(|| {
+            Flash::error(Redirect::to(uri!(root_notes)), "Log not found")
+        })?;
+    Ok(View {
+        state: ViewState::Log(log),
+        flash: flash.into_iter().map(MyFlash::from).collect(),
+    })
+}
+
+#[get("/tree?<code>&<depth>")]
+pub async fn note_tree(
+    _auth: Authenticated,
+    code: Option<String>,
+    depth: Option<usize>,
+    flash: Option<FlashMessage<'_>>,
+    mut db: Connection<Db>,
+) -> Result<View, Flash<Redirect>> {
+    // Empty select option means no filter
+    let code = code.filter(|c| !c.is_empty());
+    let nodes = get_tree(&mut db, code.as_deref()).await.map_err(|e| {
+        Flash::error(Redirect::to(uri!(root_notes)), format!("Error: {e}"))
+    })?;
+    let codes = get_all_code_names(&mut db).await.map_err(|e| {
+        Flash::error(Redirect::to(uri!(root_notes)), format!("Error: {e}"))
+    })?;
+    Ok(View {
+        state: ViewState::Tree(nodes, codes, code, depth.unwrap_or(2)),
+        flash: flash.into_iter().map(MyFlash::from).collect(),
+    })
+}
+
+#[get("/agenda")]
+pub async fn agenda(
+    _auth: Authenticated,
+    flash: Option<FlashMessage<'_>>,
+    mut db: Connection<Db>,
+) -> Result<View, Flash<Redirect>> {
+    let agenda = get_agenda(&mut db, Local::now().date_naive())
+        .await
+        .map_err(|e| {
+            Flash::error(Redirect::to(uri!(root_notes)), format!("Error: {e}"))
+        })?;
+    let token = get_calendar_token(&mut db).await.map_err(|e| {
+        Flash::error(Redirect::to(uri!(root_notes)), format!("Error: {e}"))
+    })?;
+    Ok(View {
+        state: ViewState::Agenda(agenda, token),
+        flash: flash.into_iter().map(MyFlash::from).collect(),
+    })
+}
+
+#[get("/trash")]
+pub async fn trash(
+    _auth: Authenticated,
+    flash: Option<FlashMessage<'_>>,
+    mut db: Connection<Db>,
+) -> Result<View, Flash<Redirect>> {
+    let entries = get_trash(&mut db).await.map_err(|e| {
+        Flash::error(
+            Redirect::to(uri!(root_notes)),
+            format!("Failed to load trash: {e}"),
+        )
+    })?;
+    Ok(View {
+        state: ViewState::Trash(entries),
+        flash: flash.into_iter().map(MyFlash::from).collect(),
+    })
+}
+
+/// Orders notes depth first, pairing each with its depth. Notes whose
+/// parent is not in `notes` are left out.
+fn tree_order(notes: Vec<Note>) -> Vec<(Note, usize)> {
+    let mut children: HashMap<Option<i64>, Vec<Note>> = HashMap::new();
+    for note in notes {
+        children.entry(note.parent_id).or_default().push(note);
+    }
+    let mut ordered = vec![];
+    let mut stack: Vec<(Note, usize)> = children
+        .remove(&None)
+        .unwrap_or_default()
+        .into_iter()
+        .rev()
+        .map(|n| (n, 0))
+        .collect();
+    while let Some((note, depth)) = stack.pop() {
+        if let Some(kids) = children.remove(&Some(note.id)) {
+            stack.extend(kids.into_iter().rev().map(|n| (n, depth + 1)));
+        }
+        ordered.push((note, depth));
+    }
+    ordered
+}
+
+#[get("/notes/<id>/move")]
+pub async fn move_note_form(
+    _auth: Authenticated,
+    id: i64,
+    flash: Option<FlashMessage<'_>>,
+    mut db: Connection<Db>,
+) -> Result<View, Flash<Redirect>> {
+    let note = get_note(&mut db, id)
+        .await
+        .map_err(|e| {
+            Flash::error(Redirect::to(uri!(root_notes)), format!("Error: {e}"))
+        })?
+        .ok_or_else(|| {
+            Flash::error(Redirect::to(uri!(root_notes)), "Note not found")
+        })?;
+    let subtree = get_subtree_ids(&mut db, id).await.map_err(|e| {
+        Flash::error(Redirect::to(uri!(show_note(id))), format!("Error: {e}"))
+    })?;
+    let notes = get_all_notes(&mut db).await.map_err(|e| {
+        Flash::error(Redirect::to(uri!(show_note(id))), format!("Error: {e}"))
+    })?;
+    // A note cannot move below itself
+    let destinations = tree_order(
+        notes
+            .into_iter()
+            .filter(|n| !subtree.contains(&n.id))
+            .collect(),
+    )
+    .into_iter()
+    .map(|(n, depth)| (n.id, n.title, depth))
+    .collect();
+    Ok(View {
+        state: ViewState::NoteMove(note, destinations),
+        flash: flash.into_iter().map(MyFlash::from).collect(),
+    })
+}
+
+#[get("/notes/<id>/copy")]
+pub async fn copy_note_form(
+    _auth: Authenticated,
+    id: i64,
+    flash: Option<FlashMessage<'_>>,
+    mut db: Connection<Db>,
+) -> Result<View, Flash<Redirect>> {
+    let note = get_note(&mut db, id)
+        .await
+        .map_err(|e| {
+            Flash::error(Redirect::to(uri!(root_notes)), format!("Error: {e}"))
+        })?
+        .ok_or_else(|| {
+            Flash::error(Redirect::to(uri!(root_notes)), "Note not found")
+        })?;
+    let notes = get_all_notes(&mut db).await.map_err(|e| {
+        Flash::error(Redirect::to(uri!(show_note(id))), format!("Error: {e}"))
+    })?;
+    let destinations = tree_order(notes)
+        .into_iter()
+        .map(|(n, depth)| (n.id, n.title, depth))
+        .collect();
+    Ok(View {
+        state: ViewState::NoteCopy(note, destinations),
+        flash: flash.into_iter().map(MyFlash::from).collect(),
+    })
+}
+
+#[get("/notes/<id>/board?<key>")]
+pub async fn note_board(
+    _auth: Authenticated,
+    id: i64,
+    key: Option<String>,
+    flash: Option<FlashMessage<'_>>,
+    mut db: Connection<Db>,
+) -> Result<View, Flash<Redirect>> {
+    let note = get_note(&mut db, id)
+        .await
+        .map_err(|e| {
+            Flash::error(Redirect::to(uri!(root_notes)), format!("Error: {e}"))
+        })?
+        .ok_or_else(|| {
+            Flash::error(Redirect::to(uri!(root_notes)), "Note not found")
+        })?;
+    let key = key
+        .filter(|k| !k.trim().is_empty())
+        .unwrap_or(DEFAULT_BOARD_KEY.to_string());
+    let columns = get_board(&mut db, id, &key).await.map_err(|e| {
+        Flash::error(Redirect::to(uri!(show_note(id))), format!("Error: {e}"))
+    })?;
+    Ok(View {
+        state: ViewState::Board(note, key, columns),
+        flash: flash.into_iter().map(MyFlash::from).collect(),
+    })
+}
+
+#[get("/notes/<id>/template")]
+pub async fn note_template(
+    _auth: Authenticated,
+    id: i64,
+    flash: Option<FlashMessage<'_>>,
+    mut db: Connection<Db>,
+) -> Result<View, Flash<Redirect>> {
+    let note = get_note(&mut db, id)
+        .await
+        .map_err(|e| {
+            Flash::error(Redirect::to(uri!(root_notes)), format!("Error: {e}"))
+        })?
+        .ok_or_else(|| {
+            Flash::error(Redirect::to(uri!(root_notes)), "Note not found")
+        })?;
+    let params = get_template_params(&mut db, id).await.map_err(|e| {
+        Flash::error(Redirect::to(uri!(show_note(id))), format!("Error: {e}"))
+    })?;
+    let notes = get_all_notes(&mut db).await.map_err(|e| {
+        Flash::error(Redirect::to(uri!(show_note(id))), format!("Error: {e}"))
+    })?;
+    let destinations = tree_order(notes)
+        .into_iter()
+        .map(|(n, depth)| (n.id, n.title, depth))
+        .collect();
+    Ok(View {
+        state: ViewState::NoteTemplate(note, params, destinations),
+        flash: flash.into_iter().map(MyFlash::from).collect(),
+    })
+}
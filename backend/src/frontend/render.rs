@@ -2,35 +2,55 @@ use std::collections::HashMap;
 
 use crate::api::codes::{Action, FormContainer, FormType};
 use crate::api::notes::rocket_uri_macro_execute_action;
+use crate::api::notes::rocket_uri_macro_hide_done_submit;
+use crate::api::notes::rocket_uri_macro_move_card_submit;
+use crate::api::notes::rocket_uri_macro_priority_sort_submit;
+use crate::api::notes::rocket_uri_macro_revert_revision_submit;
+use crate::api::notes::rocket_uri_macro_simulate_action;
+use crate::db_manage::agenda::{Agenda, AgendaEntry};
+use crate::db_manage::attributes::{AttributeType, AttributeValue};
+use crate::db_manage::board::BoardColumn;
 use crate::db_manage::logs::Log;
-use crate::db_manage::Note;
+use crate::db_manage::progress::Progress;
+use crate::db_manage::revisions::Revision;
+use crate::db_manage::simulation::Simulation;
+use crate::db_manage::trace::Trace;
+use crate::db_manage::tree::TreeNode;
+use crate::db_manage::{Note, ROOT_NOTE_ID};
 use crate::frontend::codes::rocket_uri_macro_view_code;
 use crate::frontend::notes::rocket_uri_macro_edit_note;
 use crate::frontend::notes::rocket_uri_macro_new_note;
+use crate::frontend::notes::rocket_uri_macro_note_board;
+use crate::frontend::notes::rocket_uri_macro_note_history;
+use crate::frontend::notes::rocket_uri_macro_note_tree;
 use crate::frontend::notes::rocket_uri_macro_show_note;
-use crate::frontend::view::render_notes_grid;
+use crate::frontend::notes::rocket_uri_macro_view_log;
+use crate::frontend::view::{render_notes_grid, ListOptions};
+use crate::utils::{diff_lines, DiffLine};
 use markdown;
 use maud::{html, Markup, PreEscaped};
 use rocket::uri;
 
+#[allow(clippy::too_many_arguments)]
 pub fn render_note(
     note: &Note,
-    attributes: &Vec<(String, String)>,
+    attributes: &Vec<(String, AttributeValue)>,
     forms: &HashMap<String, FormContainer>,
-    child_notes: &Vec<Note>,
+    child_notes: &[(Note, bool, Progress)],
+    options: ListOptions,
+    progress: &Progress,
     ancestors: &Vec<(i64, String)>,
     logs: &Vec<Log>,
 ) -> Markup {
-    let rendered_children = render_notes_grid(child_notes);
+    let rendered_children = render_notes_grid(child_notes, options);
+    let done_count = child_notes.iter().filter(|(_, done, _)| *done).count();
     html! {
           main {
             nav class="breadcrumb" {
-              a href="/" { "Home" }
               @for (id, title) in ancestors {
-                span class="crumb" { " / " }
                 a href=(uri!(show_note(*id))) { (title) }
+                span class="crumb" { " / " }
               }
-              span class="crumb" { " / " }
               span { (note.title.clone()) }
             }
 
@@ -38,7 +58,7 @@ pub fn render_note(
               h2  { (note.title) }
               @if let Some(code_name) = note.code_name.clone() {
                 a href={(uri!(view_code(name=code_name.clone(),
-                                        note=Some(note.id.to_string()))))}
+                                        note=Some(note.id.to_string()), version=_)))}
                   role="button" {
                   (code_name.clone())
                 }
@@ -47,10 +67,11 @@ pub fn render_note(
             div class="attribute-container" {
               @for a in attributes {
                   p class="badge attribute" {
-                      (a.0) ": " (a.1)
+                      (a.0) ": " (render_attribute_value(&a.1))
                   }
               }
             }
+            (render_progress(progress))
             article { p { (PreEscaped(markdown::to_html(&note.description))) } }
 
             (render_forms(note.id, forms))
@@ -62,14 +83,59 @@ pub fn render_note(
               a href={(uri!(edit_note(note.id)))} role="button" {
                 "Edit Note"
               }
-              a href=(uri!(crate::frontend::notes::delete_note_confirm(note.id))) role="button" {
-                "Delete Note"
+              // The root note stays where it is
+              @if note.id != ROOT_NOTE_ID {
+                a href=(uri!(crate::frontend::notes::delete_note_confirm(note.id))) role="button" {
+                  "Delete Note"
+                }
+                a href=(uri!(crate::frontend::notes::move_note_form(note.id))) role="button" {
+                  "Move Note"
+                }
+              }
+              a href=(uri!(crate::frontend::notes::copy_note_form(note.id))) role="button" {
+                "Copy Note"
+              }
+              a href=(uri!(crate::frontend::notes::note_template(note.id))) role="button" {
+                "Template"
+              }
+              a href=(uri!(note_board(note.id, None::<String>))) role="button" {
+                "Board"
+              }
+              a href=(uri!(note_history(note.id))) role="button" {
+                "History"
+              }
+            }
+            div class="note-header" {
+              h3 {"Subnotes"}
+              div class="note-bottom-buttons" {
+                @if done_count > 0 {
+                  form method="post" action=(uri!(hide_done_submit(note.id))) {
+                    input type="hidden" name="hide" value=(!options.hide_done);
+                    button type="submit" title="Applies to every note" {
+                      @if options.hide_done { "Show " (done_count) " done" }
+                      @else { "Hide done" }
+                    }
+                  }
+                }
+                @if child_notes.len() > 1 {
+                  form method="post" action=(uri!(priority_sort_submit(note.id))) {
+                    input type="hidden" name="enabled" value=(!options.by_priority);
+                    button type="submit" title="Applies to every note" {
+                      @if options.by_priority { "Manual order" }
+                      @else { "Sort by priority" }
+                    }
+                  }
+                }
               }
             }
-            h3 {"Subnotes"}
             (rendered_children);
             @for l in logs {
-                p style="color: var(--muted-color); font-size: 0.9em;" { (format!("{:?}", l)) }
+                p style="color: var(--muted-color); font-size: 0.9em;" {
+                    (l.timestamp) " " (l.kind) ": " (l.message)
+                    @if l.data.is_some() {
+                        " " a href=(uri!(view_log(l.id))) { "trace" }
+                    }
+                }
             }
           }
     }
@@ -99,6 +165,9 @@ pub fn render_form(note_id: i64, action: &Action, prefix: String) -> Markup {
             }
 
             button type="submit" { "Execute" }
+            " "
+            button type="submit" class="secondary"
+                   formaction=(uri!(simulate_action(note_id))) { "Simulate" }
           }
         },
         FormType::Date => html! {
@@ -110,6 +179,24 @@ pub fn render_form(note_id: i64, action: &Action, prefix: String) -> Markup {
               input type="date" name=(format!("fields[{}]",action.label));
 
               button type="submit" { "Execute" }
+              " "
+              button type="submit" class="secondary"
+                     formaction=(uri!(simulate_action(note_id))) { "Simulate" }
+            }
+          }
+        },
+        FormType::Text => html! {
+          form method="post" action=(uri!(execute_action(note_id))) {
+            div class="field" {
+              input type="hidden" name="action_label" value=(prefix);
+
+              label for=(format!("fields[{}]",action.label)) { (action.title) }
+              input type="text" name=(format!("fields[{}]",action.label));
+
+              button type="submit" { "Execute" }
+              " "
+              button type="submit" class="secondary"
+                     formaction=(uri!(simulate_action(note_id))) { "Simulate" }
             }
           }
         },
@@ -118,6 +205,9 @@ pub fn render_form(note_id: i64, action: &Action, prefix: String) -> Markup {
             input type="hidden" name="action_label" value=(prefix);
 
             button type="submit" { "Execute" }
+            " "
+            button type="submit" class="secondary"
+                   formaction=(uri!(simulate_action(note_id))) { "Simulate" }
           }
         },
     };
@@ -125,3 +215,460 @@ pub fn render_form(note_id: i64, action: &Action, prefix: String) -> Markup {
         (form)
     }
 }
+
+pub fn render_attribute_value(value: &AttributeValue) -> Markup {
+    match value {
+        AttributeValue::Note(id) => html! {
+            a href=(uri!(show_note(*id))) { "#" (id) }
+        },
+        AttributeValue::Json(json) => html! { code { (json) } },
+        _ => html! { (value) },
+    }
+}
+
+/// An input suited to `value_type`, prefilled with `value` if any.
+pub fn render_attribute_input(
+    id: &str,
+    value_type: AttributeType,
+    value: Option<&AttributeValue>,
+) -> Markup {
+    let current = value.map(|v| v.to_string()).unwrap_or_default();
+    match value_type {
+        AttributeType::String => html! {
+          input type="text" id=(id) name="value" value=(current) required;
+        },
+        AttributeType::Integer => html! {
+          input type="number" step="1" id=(id) name="value" value=(current)
+            required;
+        },
+        AttributeType::Float => html! {
+          input type="number" step="any" id=(id) name="value" value=(current)
+            required;
+        },
+        AttributeType::Bool => html! {
+          select id=(id) name="value" {
+            option value="true" selected[current == "true"] { "true" }
+            option value="false" selected[current == "false"] { "false" }
+          }
+        },
+        AttributeType::Date => html! {
+          input type="date" id=(id) name="value" value=(current) required;
+        },
+        AttributeType::DateTime => html! {
+          input type="datetime-local" step="1" id=(id) name="value"
+            value=(current) required;
+        },
+        AttributeType::Json => html! {
+          textarea id=(id) name="value" required { (current) }
+        },
+        AttributeType::Note => html! {
+          input type="number" min="1" id=(id) name="value" value=(current)
+            required;
+        },
+    }
+}
+
+pub fn render_diff(before: &str, after: &str) -> Markup {
+    html! {
+        pre class="diff" {
+            @for line in diff_lines(before, after) {
+                @match line {
+                    DiffLine::Same(l) => { span { "  " (l) "\n" } },
+                    DiffLine::Removed(l) => {
+                        del class="diff-removed" { "- " (l) "\n" }
+                    },
+                    DiffLine::Added(l) => {
+                        ins class="diff-added" { "+ " (l) "\n" }
+                    },
+                }
+            }
+        }
+    }
+}
+
+pub fn render_note_history(note: &Note, revisions: &Vec<Revision>) -> Markup {
+    html! {
+        main class="container" {
+            a href=(uri!(show_note(note.id))) role="button" {
+                "Back to note"
+            }
+            h1 { "History of " (note.title) }
+            p {
+                "Reverting restores the note as it was right before the "
+                "chosen revision. The revert is itself recorded here."
+            }
+            @if revisions.is_empty() {
+                p { "No changes recorded yet." }
+            }
+            @for r in revisions {
+                article class="revision" {
+                    div class="note-header" {
+                        h5 { (r.label()) }
+                        span { (r.created_at) }
+                        form method="post"
+                             action=(uri!(revert_revision_submit(note.id, r.id))) {
+                            button type="submit" { "Revert to this revision" }
+                        }
+                    }
+                    (render_diff(
+                        r.before_text().as_deref().unwrap_or(""),
+                        r.after_text().as_deref().unwrap_or(""),
+                    ))
+                }
+            }
+        }
+    }
+}
+
+fn format_micros(micros: u64) -> String {
+    match micros {
+        0..1000 => format!("{micros} µs"),
+        _ => format!("{:.1} ms", micros as f64 / 1000.0),
+    }
+}
+
+/// A run as a timeline of the commands it issued, ending with its result
+/// or error. The controls of trace.js replay it one command at a time.
+pub fn render_trace(trace: &Trace) -> Markup {
+    html! {
+        div class="trace" {
+            p class="tree-count" {
+                code { (trace.code_name) } " v" (trace.code_version) ", "
+                code { (trace.entry_point) } " on "
+                a href=(uri!(show_note(trace.note_id))) { "#" (trace.note_id) }
+                " with " code { (trace.arguments) }
+                ", took " (format_micros(trace.duration))
+            }
+            div class="trace-controls" {
+                button type="button" class="secondary" data-replay="back" { "◀ Back" }
+                " "
+                button type="button" class="secondary" data-replay="next" { "Next ▶" }
+                " "
+                button type="button" class="secondary" data-replay="all" { "Show all" }
+            }
+            @if trace.steps.is_empty() {
+                p { "No commands issued." }
+            } @else {
+                table {
+                    thead {
+                        tr {
+                            th { "#" } th { "At" } th { "Took" }
+                            th { "Command" } th { "Response" }
+                        }
+                    }
+                    tbody {
+                        @for (i, step) in trace.steps.iter().enumerate() {
+                            tr class="trace-step" {
+                                td { (i + 1) }
+                                td { (format_micros(step.at)) }
+                                td { (format_micros(step.took)) }
+                                td { code { (step.command) } }
+                                td {
+                                    @if !step.response.is_null() {
+                                        code { (step.response) }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            div class="trace-end" {
+                @if let Some(result) = &trace.result {
+                    p class="flash-success" { "Returned " code { (result) } }
+                }
+                @if let Some(error) = &trace.error {
+                    div class="flash-error" {
+                        p { (error.message) }
+                        @if let Some(traceback) = &error.traceback {
+                            pre { (traceback) }
+                        }
+                    }
+                }
+            }
+        }
+        script src="/static/trace.js" {}
+    }
+}
+
+pub fn render_log(log: &Log) -> Markup {
+    let trace = log.data.as_deref().and_then(Trace::from_blob);
+    html! {
+        main class="container" {
+            a href=(uri!(show_note(log.note_id))) role="button" {
+                "Back to note"
+            }
+            h1 { "Log #" (log.id) }
+            p { (log.timestamp) " " (log.kind) ": " (log.message) }
+            @match &trace {
+                Some(trace) => { (render_trace(trace)) },
+                None => { p { "This log holds no trace." } },
+            }
+        }
+    }
+}
+
+pub fn render_simulation(
+    note: &Note,
+    title: &str,
+    simulation: &Simulation,
+) -> Markup {
+    html! {
+        main class="container" {
+            a href=(uri!(show_note(note.id))) role="button" {
+                "Back to note"
+            }
+            h1 { "Simulation of " (title) " on " (note.title) }
+            p {
+                "Nothing below was saved: the action ran in a transaction "
+                "that was rolled back."
+            }
+            @match &simulation.outcome {
+                Ok(message) => { p class="flash-success" { (message) } },
+                Err(e) => { p class="flash-error" { "The action failed: " (e) } },
+            }
+            h3 { "Commands" }
+            (render_trace(&simulation.trace))
+            h3 { "Attributes" }
+            @if simulation.attributes.is_empty() {
+                p { "No attribute changes." }
+            }
+            @for a in &simulation.attributes {
+                article class="revision" {
+                    h5 {
+                        a href=(uri!(show_note(a.note_id))) { "#" (a.note_id) }
+                        " " (a.key)
+                    }
+                    (render_diff(
+                        a.before.as_deref().unwrap_or(""),
+                        a.after.as_deref().unwrap_or(""),
+                    ))
+                }
+            }
+            h3 { "Created notes" }
+            @if simulation.notes.is_empty() {
+                p { "No notes created." }
+            }
+            ul {
+                @for n in &simulation.notes {
+                    li {
+                        (n.title)
+                        @if let Some(parent_id) = n.parent_id {
+                            " under "
+                            a href=(uri!(show_note(parent_id))) { "#" (parent_id) }
+                        }
+                    }
+                }
+            }
+            h3 { "Logs" }
+            @if simulation.logs.is_empty() {
+                p { "No logs written." }
+            }
+            ul {
+                @for l in &simulation.logs {
+                    li { "#" (l.note_id) " " (l.kind) ": " (l.message) }
+                }
+            }
+        }
+    }
+}
+
+/// Opening depth deeper than any real hierarchy
+const EXPAND_ALL: usize = 1000;
+
+fn render_tree_level(
+    children: &HashMap<Option<i64>, Vec<&TreeNode>>,
+    parent_id: Option<i64>,
+    open_depth: usize,
+) -> Markup {
+    html! {
+        @for node in children.get(&parent_id).into_iter().flatten() {
+            @let label = html! {
+                a href=(uri!(show_note(node.id)))
+                  class=[node.done.then_some("tree-done")] { (node.title) }
+                @if let Some(code) = &node.code_name {
+                    " " span class="badge attribute" { (code) }
+                }
+                @if node.open_children > 0 {
+                    " " span class="tree-count" title="Open subnotes" {
+                        "(" (node.open_children) ")"
+                    }
+                }
+            };
+            @if children.contains_key(&Some(node.id)) {
+                details class="tree-node"
+                        open[(node.depth as usize) < open_depth] {
+                    summary { (label) }
+                    (render_tree_level(children, Some(node.id), open_depth))
+                }
+            } @else {
+                div class="tree-node tree-leaf" { (label) }
+            }
+        }
+    }
+}
+
+pub fn render_tree(
+    nodes: &[TreeNode],
+    codes: &[String],
+    code: &Option<String>,
+    open_depth: usize,
+) -> Markup {
+    let mut children: HashMap<Option<i64>, Vec<&TreeNode>> = HashMap::new();
+    for node in nodes {
+        children.entry(node.parent_id).or_default().push(node);
+    }
+    html! {
+        main class="container" {
+            div class="note-header" {
+                h2 { "Tree" }
+                div class="note-bottom-buttons" {
+                    a href=(uri!(note_tree(code = code.clone(), depth = Some(EXPAND_ALL))))
+                      role="button" { "Expand all" }
+                    a href=(uri!(note_tree(code = code.clone(), depth = Some(1))))
+                      role="button" { "Collapse all" }
+                }
+            }
+            form method="get" action=(uri!(note_tree(code = None::<String>, depth = None::<usize>)))
+                 class="note-bottom-buttons" {
+                select name="code" {
+                    option value="" selected[code.is_none()] { "All codes" }
+                    @for c in codes {
+                        option value=(c) selected[code.as_ref() == Some(c)] { (c) }
+                    }
+                }
+                input type="hidden" name="depth" value=(open_depth);
+                button type="submit" { "Filter" }
+            }
+            @if nodes.is_empty() {
+                p { "No note runs this code." }
+            }
+            (render_tree_level(&children, None, open_depth))
+        }
+    }
+}
+
+/// Progress bar over the leaves below a note, empty for leaves themselves.
+pub fn render_progress(progress: &Progress) -> Markup {
+    html! {
+        @if let Some(percent) = progress.percent() {
+            div class="progress" {
+                progress value=(progress.done) max=(progress.total()) {
+                    (percent) "%"
+                }
+                span {
+                    (progress.done) "/" (progress.total()) " done"
+                    @if let Some(due) = progress.latest_due {
+                        ", due by " (due.format("%Y-%m-%d"))
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn render_agenda_group(title: &str, entries: &[AgendaEntry]) -> Markup {
+    html! {
+        h3 { (title) " (" (entries.len()) ")" }
+        @if entries.is_empty() {
+            p { "Nothing here." }
+        }
+        @for entry in entries {
+            article class="note-article" {
+                div {
+                    a href=(uri!(show_note(entry.id))) { (entry.title) }
+                    @if let Some(parent) = &entry.parent_title {
+                        " " span class="tree-count" { "in " (parent) }
+                    }
+                }
+                div class="attribute-container" {
+                    @if entry.priority != 0 {
+                        p class="badge attribute" { "priority " (entry.priority) }
+                    }
+                    p class="badge attribute" { (entry.due.format("%Y-%m-%d")) }
+                }
+            }
+        }
+    }
+}
+
+pub fn render_agenda(agenda: &Agenda, token: Option<&str>) -> Markup {
+    html! {
+        main class="container" {
+            h2 { "Agenda" }
+            (render_agenda_group("Overdue", &agenda.overdue))
+            (render_agenda_group("Today", &agenda.today))
+            (render_agenda_group("Upcoming", &agenda.upcoming))
+            h3 { "Calendar feed" }
+            @if let Some(token) = token {
+                p {
+                    "Subscribe to "
+                    code { "/calendar.ics?token=" (token) }
+                    ", add " code { "&note=<id>" } " to only get a subtree."
+                }
+            } @else {
+                p { "No calendar feed yet." }
+            }
+            form method="post" action="/calendar/token" {
+                button type="submit" class="secondary" {
+                    @if token.is_some() { "Reset token" } @else { "Create feed" }
+                }
+            }
+        }
+    }
+}
+
+pub fn render_board(note: &Note, key: &str, columns: &[BoardColumn]) -> Markup {
+    html! {
+        main class="container" {
+            div class="note-header" {
+                h2 {
+                    "Board of "
+                    a href=(uri!(show_note(note.id))) { (note.title) }
+                }
+            }
+            form method="get" action=(uri!(note_board(note.id, None::<String>)))
+                 class="note-bottom-buttons" {
+                input type="text" name="key" value=(key)
+                      aria-label="Group by attribute";
+                button type="submit" { "Group" }
+            }
+            datalist id="board-columns" {
+                @for column in columns {
+                    @if let Some(value) = &column.value {
+                        option value=(value) {}
+                    }
+                }
+            }
+            div class="board" {
+                @for column in columns {
+                    section class="board-column"
+                            data-value=(column.value.clone().unwrap_or_default()) {
+                        h4 {
+                            @match &column.value {
+                                Some(value) => (value),
+                                None => { "No " (key) },
+                            }
+                            " " span class="tree-count" { "(" (column.cards.len()) ")" }
+                        }
+                        @for (card, done) in &column.cards {
+                            article class=(if *done { "board-card note-done" } else { "board-card" })
+                                    draggable="true" {
+                                a href=(uri!(show_note(card.id))) { (card.title) }
+                                form method="post" action=(uri!(move_card_submit(card.id)))
+                                     class="board-move" {
+                                    input type="hidden" name="key" value=(key);
+                                    input type="text" name="value" list="board-columns"
+                                          aria-label="Column"
+                                          value=(column.value.clone().unwrap_or_default());
+                                    button type="submit" class="secondary" { "Move" }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            script src="/static/board.js" {}
+        }
+    }
+}
@@ -7,19 +7,56 @@ use rocket::Request;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+use crate::api::codes::rocket_uri_macro_delete_code_submit;
 use crate::api::codes::rocket_uri_macro_edit_code_submit;
+use crate::api::codes::rocket_uri_macro_rename_code_submit;
+use crate::api::codes::rocket_uri_macro_rollback_code_submit;
+use crate::api::codes::rocket_uri_macro_run_code_tests_submit;
+use crate::api::codes::rocket_uri_macro_save_code_tests_submit;
+use crate::api::codes::rocket_uri_macro_trace_code_submit;
+use crate::api::codes::rocket_uri_macro_upgrade_notes_submit;
 use crate::api::notes::rocket_uri_macro_delete_attribute_submit;
 use crate::api::notes::rocket_uri_macro_edit_note_submit;
+use crate::api::notes::rocket_uri_macro_pin_note_submit;
+use crate::api::notes::rocket_uri_macro_reorder_note_submit;
 use crate::api::notes::rocket_uri_macro_update_or_add_attribute_submit;
-use crate::db_manage::codes::Code;
+use crate::db_manage::agenda::Agenda;
+use crate::db_manage::attributes::{
+    AttributeSpec, AttributeType, AttributeValue,
+};
+use crate::db_manage::board::{BoardColumn, DEFAULT_BOARD_KEY};
+use crate::db_manage::code_tests::TestReport;
+use crate::db_manage::code_versions::{CodeVersion, OutdatedNote};
+use crate::db_manage::codes::{
+    Code, CodeUser, DEFAULT_ATTRIBUTE_SCHEMA, DEFAULT_COMPLETION_ATTRIBUTE,
+};
+use crate::db_manage::dependencies::DependencyNode;
 use crate::db_manage::logs::Log;
+use crate::db_manage::progress::Progress;
+use crate::db_manage::revisions::Revision;
+use crate::db_manage::simulation::Simulation;
+use crate::db_manage::trace::Trace;
+use crate::db_manage::trash::TrashEntry;
+use crate::db_manage::tree::TreeNode;
+use crate::db_manage::validation::CodeProblem;
 use crate::db_manage::Note;
+use crate::frontend::codes::rocket_uri_macro_code_tests;
+use crate::frontend::codes::rocket_uri_macro_delete_code_confirm;
 use crate::frontend::codes::rocket_uri_macro_edit_code;
 use crate::frontend::codes::rocket_uri_macro_list_codes;
+use crate::frontend::codes::rocket_uri_macro_upgrade_code;
+use crate::frontend::codes::rocket_uri_macro_view_code;
+use crate::frontend::notes::rocket_uri_macro_agenda;
+use crate::frontend::notes::rocket_uri_macro_note_tree;
 use crate::frontend::notes::rocket_uri_macro_root_notes;
 use crate::frontend::notes::rocket_uri_macro_show_note;
+use crate::frontend::notes::rocket_uri_macro_trash;
 
-use super::render::render_note;
+use super::render::{
+    render_agenda, render_attribute_input, render_board, render_diff,
+    render_log, render_note, render_note_history, render_progress,
+    render_simulation, render_trace, render_tree,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum MyFlashType {
@@ -60,25 +97,75 @@ impl<'a> From<FlashMessage<'a>> for MyFlash {
     }
 }
 
+/// How the children of a note are listed. Both options are remembered in
+/// cookies shared by all notes, toggling them on one note changes them all.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ListOptions {
+    pub hide_done: bool,
+    pub by_priority: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ViewState {
     Login,
-    Root(Vec<Note>),
     Note(
         Note,
-        Vec<(String, String)>,
+        Vec<(String, AttributeValue)>,
         HashMap<String, FormContainer>,
-        Vec<Note>,
+        Vec<(Note, bool, Progress)>,
         Vec<(i64, String)>,
         Vec<Log>,
+        ListOptions,
+        Progress,
     ),
     NoteNew(Vec<String>, Option<i64>),
-    NoteEdit(i64, Note, Vec<String>, Vec<(String, String)>),
+    NoteEdit(
+        i64,
+        Note,
+        Vec<String>,
+        Vec<(String, AttributeValue)>,
+        Vec<AttributeSpec>,
+        // Pinned version and the versions of the note's code
+        Option<i64>,
+        Vec<CodeVersion>,
+    ),
     NoteConfirmDelete(i64, String),
-    Code(Code, Option<String>),
+    NoteHistory(Note, Vec<Revision>),
+    /// A log, showing the trace of the run it records
+    Log(Log),
+    /// What an action of the note would do, with the action's title
+    Simulation(Note, String, Simulation),
+    Trash(Vec<TrashEntry>),
+    /// The agenda and the calendar feed token, if one was created
+    Agenda(Agenda, Option<String>),
+    Tree(Vec<TreeNode>, Vec<String>, Option<String>, usize),
+    /// A note with its children in columns grouped by an attribute
+    Board(Note, String, Vec<BoardColumn>),
+    NoteMove(Note, Vec<(i64, String, usize)>),
+    NoteCopy(Note, Vec<(i64, String, usize)>),
+    NoteTemplate(Note, Option<Vec<String>>, Vec<(i64, String, usize)>),
+    /// A code, its versions, the codes importing it and the version whose
+    /// changes are shown
+    Code(
+        Code,
+        Vec<CodeVersion>,
+        Vec<String>,
+        DependencyNode,
+        i64,
+        Option<String>,
+    ),
+    /// A code about to be deleted, the notes using it and its importers
+    CodeConfirmDelete(String, Vec<CodeUser>, Vec<String>),
+    /// A code and the notes pinned to older versions of it
+    CodeUpgrade(Code, Vec<OutdatedNote>),
     CodeList(Vec<String>, Option<String>),
-    CodeNew(),
-    CodeEdit(Code, Option<String>),
+    /// A run of a code started from its page, to be replayed
+    CodeTrace(String, Trace),
+    /// The test cases of a code and the reports of their last run
+    CodeTests(String, String, Option<Vec<TestReport>>),
+    /// The code form, filled again with problems after a failed save
+    CodeNew(Option<Code>, Vec<CodeProblem>),
+    CodeEdit(Code, Option<String>, Vec<CodeProblem>),
 }
 
 #[derive(Debug)]
@@ -149,6 +236,7 @@ fn render_confirm_delete(id: i64, title: &String) -> Markup {
         main class="container" {
             h1 { "Confirm Delete Note" }
             p { "Are you sure you want to delete the note: " (title) "?" }
+            p { "It will be moved to the trash together with its subnotes." }
             form method="post" action=(uri!(crate::api::notes::delete_note_submit(id))) {
                 button type="submit" { "Yes, delete" }
             }
@@ -157,15 +245,152 @@ fn render_confirm_delete(id: i64, title: &String) -> Markup {
     }
 }
 
-fn root(notes: Vec<Note>) -> Markup {
+fn render_trash(entries: &Vec<TrashEntry>) -> Markup {
     html! {
-      main {
-        section class="main" {
-            div class="note-header" {h2 { "Home" }}
-          (render_notes_grid(&notes))
-          a href="/notes/new" role="button" { "Create New Root Note" }
+        main class="container" {
+            h1 { "Trash" }
+            @if entries.is_empty() {
+                p { "The trash is empty." }
+            }
+            @for entry in entries {
+                article class="note-article" {
+                    span { (entry.title) }
+                    span {
+                        (format!("{} notes, deleted {}",
+                                 entry.subtree_size, entry.deleted_at))
+                    }
+                    div class="note-bottom-buttons" {
+                        form method="post"
+                             action=(uri!(crate::api::notes::restore_note_submit(entry.id))) {
+                            button type="submit" { "Restore" }
+                        }
+                        form method="post"
+                             action=(uri!(crate::api::notes::purge_note_submit(entry.id))) {
+                            button type="submit" { "Delete forever" }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn render_note_template(
+    note: &Note,
+    params: &Option<Vec<String>>,
+    destinations: &Vec<(i64, String, usize)>,
+) -> Markup {
+    html! {
+        main class="container" {
+            a href={(uri!(show_note(note.id)))} role="button" {
+              "Back to note"
+            }
+            h1 { "Template " (note.title) }
+            p {
+                "Titles, descriptions and attributes of a template and its "
+                "subnotes may contain placeholders like "
+                code { "{{name}}" } ", filled in when instantiating it. "
+                code { "{{today}}" } " is always available."
+            }
+            form method="post"
+                 action=(uri!(crate::api::notes::template_submit(note.id)))
+                 class="edit-note-form" {
+                label {
+                    input type="checkbox" name="is_template" value="true"
+                      checked[params.is_some()];
+                    " This note is a template"
+                }
+                label for="params" { "Parameters (comma separated)" }
+                input type="text" id="params" name="params"
+                  value=(params.as_deref().unwrap_or_default().join(", "));
+                button type="submit" { "Save Template" }
+            }
+            @if let Some(params) = params {
+                h2 { "Instantiate" }
+                form method="post"
+                     action=(uri!(crate::api::notes::instantiate_submit(note.id)))
+                     class="edit-note-form" {
+                    @for param in params {
+                        label for=(format!("values.{param}")) { (param) }
+                        input type="text" id=(format!("values.{param}"))
+                          name=(format!("values[{param}]")) required;
+                    }
+                    fieldset class="code-select" {
+                        legend { "Parent of the new note" }
+                        @for (id, title, depth) in destinations {
+                            label style=(format!("margin-left: {}rem", 2 * depth)) {
+                                input type="radio" name="parent_id" value=(id)
+                                  checked[note.parent_id == Some(*id)];
+                                " " (title)
+                            }
+                        }
+                    }
+                    button type="submit" class="contrast" { "Instantiate" }
+                }
+            }
+        }
+    }
+}
+
+fn render_copy_note(
+    note: &Note,
+    destinations: &Vec<(i64, String, usize)>,
+) -> Markup {
+    html! {
+        main class="container" {
+            a href={(uri!(show_note(note.id)))} role="button" {
+              "Back to note"
+            }
+            h1 { "Copy " (note.title) }
+            form method="post"
+                 action=(uri!(crate::api::notes::copy_note_submit(note.id)))
+                 class="edit-note-form" {
+                fieldset class="code-select" {
+                    legend { "Parent of the copy" }
+                    @for (id, title, depth) in destinations {
+                        label style=(format!("margin-left: {}rem", 2 * depth)) {
+                            input type="radio" name="parent_id" value=(id)
+                              checked[note.parent_id == Some(*id)];
+                            " " (title)
+                        }
+                    }
+                }
+                label {
+                    input type="checkbox" name="with_logs" value="true";
+                    " Copy logs too"
+                }
+                button type="submit" class="contrast" { "Copy Note" }
+            }
+        }
+    }
+}
+
+fn render_move_note(
+    note: &Note,
+    destinations: &Vec<(i64, String, usize)>,
+) -> Markup {
+    html! {
+        main class="container" {
+            a href={(uri!(show_note(note.id)))} role="button" {
+              "Back to note"
+            }
+            h1 { "Move " (note.title) }
+            form method="post"
+                 action=(uri!(crate::api::notes::move_note_submit(note.id)))
+                 class="edit-note-form" {
+                fieldset class="code-select" {
+                    legend { "New parent" }
+                    @for (id, title, depth) in destinations {
+                        label style=(format!("margin-left: {}rem", 2 * depth)) {
+                            input type="radio" name="parent_id" value=(id)
+                              checked[note.parent_id == Some(*id)];
+                            " " (title)
+                        }
+                    }
+                }
+                button type="submit" class="contrast" { "Move Note" }
+            }
         }
-      }
     }
 }
 
@@ -188,6 +413,15 @@ pub fn header() -> maud::Markup {
           a href={(uri!(root_notes()))} role="button" {
             "Notes"
           }
+          a href={(uri!(note_tree(code = None::<String>, depth = None::<usize>)))} role="button" {
+            "Tree"
+          }
+          a href={(uri!(agenda()))} role="button" {
+            "Agenda"
+          }
+          a href={(uri!(trash()))} role="button" {
+            "Trash"
+          }
           form method="post" action="/logout" {
             button type="submit" { "Logout" }
           }
@@ -222,43 +456,453 @@ pub fn render(page: Page) -> Markup {
     }
 }
 
-pub fn render_notes_grid(notes: &Vec<Note>) -> Markup {
+/// Cards for `notes`, done ones greyed out or left out with `hide_done`.
+/// Manual reordering is only offered when not sorted by priority. Arrows
+/// move a card past its visible neighbour, so positions are those of the
+/// full sibling list, hidden notes included.
+pub fn render_notes_grid(
+    notes: &[(Note, bool, Progress)],
+    options: ListOptions,
+) -> Markup {
+    let reorderable = !options.by_priority;
+    let shown: Vec<usize> = (0..notes.len())
+        .filter(|i| !(options.hide_done && notes[*i].1))
+        .collect();
     html! {
       section class="note-grid" {
-        @for note in notes {
-          article class="note-article" {
+        @for (rank, index) in shown.iter().enumerate() {
+          @let (note, done, progress) = &notes[*index];
+          article class={"note-article" @if *done { " note-done" }} {
               a href={(format!("/notes/{}", note.id))} { // TODO: use uri
                 (note.title)
               }
-              @if let Some(code) = &note.code_name { p class="badge" { (code) } }
+              @if *done { span class="badge done-badge" { "done" } }
+              (render_progress(progress))
+              div class="note-bottom-buttons" {
+                @if let Some(code) = &note.code_name { p class="badge" { (code) } }
+                @if let (true, Some(previous)) =
+                    (reorderable, rank.checked_sub(1).map(|r| shown[r])) {
+                  form method="post" action=(uri!(reorder_note_submit(note.id))) {
+                    input type="hidden" name="position" value=(previous);
+                    button type="submit" title="Move up" { "↑" }
+                  }
+                }
+                @if let (true, Some(next)) = (reorderable, shown.get(rank + 1)) {
+                  form method="post" action=(uri!(reorder_note_submit(note.id))) {
+                    input type="hidden" name="position" value=(next);
+                    button type="submit" title="Move down" { "↓" }
+                  }
+                }
+              }
           }
         }
       }
     }
 }
 
-fn render_code(code: Code, next: Option<String>) -> Markup {
+fn render_dependency(node: &DependencyNode) -> Markup {
+    html! {
+        li {
+            a href=(uri!(view_code(name = node.name.clone(), note = None::<String>, version = _))) {
+                (node.name)
+            }
+            @match (node.library, node.cycle) {
+                (_, true) => " (cycle)",
+                (Some(true), _) if node.repeated => " (imports shown above)",
+                (None, _) => " (missing)",
+                (Some(false), _) => " (not a library)",
+                (Some(true), _) => "",
+            }
+            @if !node.imports.is_empty() {
+                ul {
+                    @for import in &node.imports {
+                        (render_dependency(import))
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn render_code(
+    code: Code,
+    versions: &[CodeVersion],
+    importers: &[String],
+    dependencies: &DependencyNode,
+    selected: i64,
+    next: Option<String>,
+) -> Markup {
     html! {
         main class="container" {
             h1 { "Code Details" }
             div class="attribute-container" {
               p class="badge" { (code.name.clone()) }
+              @if code.library {
+                  p class="badge" { "Library" }
+              }
             }
             h2 { "Capabilities" }
             code { (code.capabilities.clone()) }
+            h2 { "Attribute Schema" }
+            pre { code { (code.attribute_schema.clone()) } }
+            h2 { "Completion Attribute" }
+            code { (code.completion_attribute.clone()) }
+            h2 { "Board Action" }
+            @match &code.board_action {
+                Some(label) => p {
+                    code { (label) } " when moved on a board grouping by "
+                    code { (code.board_action_key.clone().unwrap_or_default()) }
+                },
+                None => p { "None, moving a card sets the attribute." },
+            }
             h2 { "Script" }
             pre { code { (code.script.clone()) } }
             nav style="margin-top: 1rem" {
-                a href=(uri!(edit_code(name = code.name.clone(), next = next)
+                a href=(uri!(edit_code(name = code.name.clone(), next = next.clone())
                 )) role="button" {
                     "Edit Code"
                 }
+                a href=(uri!(upgrade_code(name = code.name.clone()))) role="button"
+                  class="secondary" {
+                    "Upgrade pinned notes"
+                }
+                a href=(uri!(delete_code_confirm(name = code.name.clone()))) role="button"
+                  class="secondary" {
+                    "Delete Code"
+                }
+                a href=(uri!(code_tests(name = code.name.clone()))) role="button"
+                  class="secondary" {
+                    "Tests"
+                }
+            }
+            form method="post" action=(uri!(run_code_tests_submit(name = code.name.clone()))) {
+                button type="submit" class="secondary" { "Run tests" }
+            }
+            h2 { "Replay a Run" }
+            p {
+                "Runs a coroutine of this code on a note to the end, then "
+                "replays the commands it issued one at a time. Nothing is "
+                "saved."
+            }
+            form method="post" action=(uri!(trace_code_submit(name = code.name.clone()))) {
+                div class="grid" {
+                    label {
+                        "Note"
+                        input type="number" name="note_id" required
+                              value=[next.as_deref().and_then(|n| n.rsplit('/').next())];
+                    }
+                    label {
+                        "Coroutine"
+                        input type="text" name="entry_point" value="forms" required;
+                    }
+                    label {
+                        "Input"
+                        input type="text" name="arguments" value="null";
+                    }
+                }
+                button type="submit" class="secondary" { "Run and replay" }
+            }
+            h2 { "Imports" }
+            @if dependencies.imports.is_empty() {
+                p { "This code imports no other code." }
+            } @else {
+                ul {
+                    @for import in &dependencies.imports {
+                        (render_dependency(import))
+                    }
+                }
+            }
+            h2 { "Imported By" }
+            @if importers.is_empty() {
+                p { "No other code imports this one." }
+            } @else {
+                ul {
+                    @for importer in importers {
+                        li {
+                            a href=(uri!(view_code(name = importer.clone(), note = None::<String>, version = _))) {
+                                (importer)
+                            }
+                        }
+                    }
+                }
+            }
+            h2 { "Rename" }
+            form method="post" action=(uri!(rename_code_submit(name = code.name.clone())))
+                 class="note-bottom-buttons" {
+                input type="text" name="new_name" required aria-label="New name"
+                      value=(code.name);
+                input type="text" name="author" required aria-label="Author"
+                      placeholder="Author";
+                button type="submit" { "Rename" }
+            }
+            @if !importers.is_empty() {
+                p class="tree-count" {
+                    "Notes follow the new name, and the imports in the codes "
+                    "above are rewritten as new versions."
+                }
+            }
+            (render_code_versions(&code, versions, selected, next))
+        }
+    }
+}
+
+fn render_code_versions(
+    code: &Code,
+    versions: &[CodeVersion],
+    selected: i64,
+    next: Option<String>,
+) -> Markup {
+    let shown = versions.iter().position(|v| v.version == selected);
+    html! {
+        h2 { "Versions" }
+        table {
+            thead {
+                tr {
+                    th { "Version" } th { "Date" } th { "Author" }
+                    th { "Message" } th {}
+                }
+            }
+            tbody {
+                @for v in versions {
+                    tr {
+                        td {
+                            a href=(uri!(view_code(name = code.name.clone(), note = next.clone(), version = Some(v.version)))) {
+                                "v" (v.version)
+                            }
+                            @if v.version == code.version { " (current)" }
+                        }
+                        td { (v.created_at) }
+                        td { (v.author) }
+                        td { (v.message) }
+                        td {
+                            @if v.version != code.version {
+                                form method="post" style="margin: 0"
+                                     action=(uri!(rollback_code_submit(name = code.name.clone()))) {
+                                    input type="hidden" name="version" value=(v.version);
+                                    input type="text" name="author" required
+                                          aria-label="Author" placeholder="Author";
+                                    button type="submit" class="secondary" { "Roll back" }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        @if let Some(index) = shown {
+            @let after = &versions[index];
+            @let before = versions.get(index + 1);
+            h3 { "Changes in v" (after.version) }
+            @if let Some(before) = before {
+                @if before.capabilities != after.capabilities {
+                    h4 { "Capabilities" }
+                    (render_diff(&before.capabilities, &after.capabilities))
+                }
+                @if before.attribute_schema != after.attribute_schema {
+                    h4 { "Attribute Schema" }
+                    (render_diff(&before.attribute_schema, &after.attribute_schema))
+                }
             }
+            h4 { "Script" }
+            (render_diff(before.map(|b| b.script.as_str()).unwrap_or(""), &after.script))
         }
     }
 }
 
-pub fn render_new_code() -> Markup {
+fn render_code_trace(name: &str, trace: &Trace) -> Markup {
+    html! {
+        main class="container" {
+            a href=(uri!(view_code(name = name, note = None::<String>, version = _)))
+              role="button" { "Back to code" }
+            h1 { "Run of " (name) }
+            (render_trace(trace))
+        }
+    }
+}
+
+fn render_code_tests(
+    name: &str,
+    tests: &str,
+    reports: Option<&[TestReport]>,
+) -> Markup {
+    html! {
+        main class="container" {
+            a href=(uri!(view_code(name = name, note = None::<String>, version = _)))
+              role="button" { "Back to code" }
+            h1 { "Tests of " (name) }
+            @if let Some(reports) = reports {
+                h2 { "Results" }
+                @for report in reports {
+                    @if report.passed() {
+                        p class="flash-success" { "✓ " (report.name) }
+                    } @else {
+                        div class="flash-error" {
+                            p { "✗ " (report.name) }
+                            ul {
+                                @for failure in &report.failures {
+                                    li { (failure) }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            form method="post" action=(uri!(run_code_tests_submit(name = name))) {
+                button type="submit" { "Run tests" }
+            }
+            h2 { "Test cases" }
+            p {
+                "A JSON list of cases. Each one creates a note using the code "
+                "with the given " code { "attributes" } " on an empty "
+                "database, runs " code { "action" } " with " code { "input" }
+                " and compares the " code { "result" } " and "
+                code { "expected_attributes" } ", where " code { "null" }
+                " means absent."
+            }
+            pre {
+                code {
+                    r#"[{ "name": "marks as done", "action": "done","#
+                    "\n"
+                    r#"   "input": { "Date": "2025-01-01" }, "result": "Done","#
+                    "\n"
+                    r#"   "expected_attributes": { "done": { "Date": "2025-01-01" } } }]"#
+                }
+            }
+            form method="post" action=(uri!(save_code_tests_submit(name = name))) {
+                textarea name="tests" rows="16" { (tests) }
+                button type="submit" { "Save tests" }
+            }
+        }
+    }
+}
+
+fn render_confirm_delete_code(
+    name: &str,
+    users: &[CodeUser],
+    importers: &[String],
+) -> Markup {
+    html! {
+        main class="container" {
+            h1 { "Confirm Delete Code" }
+            p { "Are you sure you want to delete the code " code { (name) } " and all its versions?" }
+            @if users.is_empty() {
+                p { "No note uses it." }
+            } @else {
+                p { "These notes will be left without code:" }
+                ul {
+                    @for user in users {
+                        li {
+                            @if user.in_trash {
+                                (user.title) " (in trash)"
+                            } @else {
+                                a href=(uri!(show_note(user.id))) { (user.title) }
+                            }
+                        }
+                    }
+                }
+            }
+            @if importers.is_empty() {
+                form method="post" action=(uri!(delete_code_submit(name = name))) {
+                    button type="submit" { "Yes, delete" }
+                }
+            } @else {
+                p { "These codes import it, remove their imports before deleting it:" }
+                ul {
+                    @for importer in importers {
+                        li { (importer) }
+                    }
+                }
+            }
+            a href=(uri!(view_code(name = name, note = None::<String>, version = _))) { "Cancel" }
+        }
+    }
+}
+
+fn render_code_upgrade(code: &Code, notes: &[OutdatedNote]) -> Markup {
+    let note: Option<String> = None;
+    html! {
+        main class="container" {
+            a href=(uri!(view_code(name = code.name.clone(), note = note, version = _)))
+              role="button" { "Back to code" }
+            h1 { "Upgrade notes to v" (code.version) " of " (code.name) }
+            @if notes.is_empty() {
+                p { "No note is pinned to an older version." }
+            } @else {
+                p {
+                    "The selected notes will run the current version. If its "
+                    "script defines " code { "migrate" } ", it runs on each "
+                    "note with the versions it moves between."
+                }
+                form method="post" action=(uri!(upgrade_notes_submit(name = code.name.clone()))) {
+                    @for n in notes {
+                        label {
+                            input type="checkbox" name="notes" value=(n.id) checked;
+                            " "
+                            a href=(uri!(show_note(n.id))) { (n.title) }
+                            " " span class="tree-count" { "(v" (n.code_version) ")" }
+                        }
+                    }
+                    button type="submit" class="contrast" { "Upgrade" }
+                }
+            }
+        }
+    }
+}
+
+/// Problems found in one field of the code form, each with the line it
+/// points at when known.
+fn render_code_problems(
+    problems: &[CodeProblem],
+    field: &str,
+    text: &str,
+) -> Markup {
+    html! {
+        @for problem in problems.iter().filter(|p| p.field == field) {
+            div class="code-problem" {
+                p {
+                    @if let Some(line) = problem.line {
+                        "Line " (line)
+                        @if let Some(column) = problem.column { ", column " (column) }
+                        ": "
+                    }
+                    (problem.message)
+                }
+                @if let Some(line) = problem.line
+                    && let Some(source) = text.lines().nth(line.saturating_sub(1))
+                {
+                    pre {
+                        (source) "\n"
+                        @if let Some(column) = problem.column {
+                            (" ".repeat(column.saturating_sub(1))) "^"
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn render_new_code(
+    draft: Option<&Code>,
+    problems: &[CodeProblem],
+) -> Markup {
+    let name = draft.map(|c| c.name.as_str()).unwrap_or_default();
+    let capabilities =
+        draft.map(|c| c.capabilities.as_str()).unwrap_or_default();
+    let schema = draft
+        .map(|c| c.attribute_schema.as_str())
+        .unwrap_or(DEFAULT_ATTRIBUTE_SCHEMA);
+    let completion = draft
+        .map(|c| c.completion_attribute.as_str())
+        .unwrap_or(DEFAULT_COMPLETION_ATTRIBUTE);
+    let board_action = draft
+        .and_then(|c| c.board_action.as_deref())
+        .unwrap_or_default();
+    let board_action_key = draft
+        .and_then(|c| c.board_action_key.as_deref())
+        .unwrap_or(DEFAULT_BOARD_KEY);
+    let script = draft.map(|c| c.script.as_str()).unwrap_or_default();
+    let library = draft.is_some_and(|c| c.library);
     html! {
       main class="container" {
         h1 { "Create New Code" }
@@ -266,16 +910,52 @@ pub fn render_new_code() -> Markup {
         form method="post" action="/codes/new"
              class="edit-code-form" {
           label for="name" { "Label (example: mark_done)" }
-          input type="name" id="name" name="name" required;
+          input type="name" id="name" name="name" required value=(name);
 
           label for="capabilities" {
               r#"Capabilities (example: ["SysLog", { "GetAttribute": "Own" } ]"#
           }
           input type="capabilities" id="capabilities"
-                name="capabilities" required;
+                name="capabilities" required value=(capabilities);
+          (render_code_problems(problems, "capabilities", capabilities))
+
+          label for="attribute_schema" {
+              r#"Attribute schema (example: [{ "name": "done", "value_type": "Bool", "required": true, "default": "false" }])"#
+          }
+          textarea id="attribute_schema" name="attribute_schema" rows="5" {
+              (schema)
+          }
+          (render_code_problems(problems, "attribute_schema", schema))
+
+          label for="completion_attribute" {
+              "Completion attribute (the note is done once it is set)"
+          }
+          input type="text" id="completion_attribute"
+                name="completion_attribute" required value=(completion);
+
+          label for="board_action" {
+              "Board action (form run when a card is moved on a board, optional)"
+          }
+          input type="text" id="board_action" name="board_action"
+                value=(board_action);
+
+          label for="board_action_key" {
+              "Board action key (attribute the board groups by)"
+          }
+          input type="text" id="board_action_key" name="board_action_key"
+                value=(board_action_key);
+
+          label {
+              input type="checkbox" name="library" checked[library];
+              "Library (imported by other codes, notes cannot use it)"
+          }
+
+          label for="author" { "Author" }
+          input type="text" id="author" name="author" required value="admin";
 
           label for="script" { "Script" }
-          textarea id="script" name="script" rows="30" {};
+          (render_code_problems(problems, "script", script))
+          textarea id="script" name="script" rows="30" { (script) };
 
           button type="submit" class="contrast" { "Create Code" }
         }
@@ -283,7 +963,11 @@ pub fn render_new_code() -> Markup {
     }
 }
 
-pub fn render_edit_code(code: &Code, next_url: Option<String>) -> Markup {
+pub fn render_edit_code(
+    code: &Code,
+    next_url: Option<String>,
+    problems: &[CodeProblem],
+) -> Markup {
     html! {
       main class="container" {
         h1 { "Edit Code" }
@@ -295,7 +979,41 @@ pub fn render_edit_code(code: &Code, next_url: Option<String>) -> Markup {
           }
           input type="capabilities" id="capabilities"
                 name="capabilities" required value=(code.capabilities);
+          (render_code_problems(problems, "capabilities", &code.capabilities))
+          label for="attribute_schema" {
+              r#"Attribute schema (example: [{ "name": "done", "value_type": "Bool", "required": true, "default": "false" }])"#
+          }
+          textarea id="attribute_schema" name="attribute_schema" rows="5" {
+              (code.attribute_schema)
+          }
+          (render_code_problems(problems, "attribute_schema", &code.attribute_schema))
+          label for="completion_attribute" {
+              "Completion attribute (the note is done once it is set)"
+          }
+          input type="text" id="completion_attribute"
+                name="completion_attribute" required
+                value=(code.completion_attribute);
+          label for="board_action" {
+              "Board action (form run when a card is moved on a board, optional)"
+          }
+          input type="text" id="board_action" name="board_action"
+                value=(code.board_action.clone().unwrap_or_default());
+          label for="board_action_key" {
+              "Board action key (attribute the board groups by)"
+          }
+          input type="text" id="board_action_key" name="board_action_key"
+                value=(code.board_action_key.as_deref()
+                    .unwrap_or(DEFAULT_BOARD_KEY));
+          label {
+              input type="checkbox" name="library" checked[code.library];
+              "Library (imported by other codes, notes cannot use it)"
+          }
+          label for="author" { "Author" }
+          input type="text" id="author" name="author" required value="admin";
+          label for="message" { "What changed (recorded with the new version)" }
+          input type="text" id="message" name="message";
           label for="script" { "Script" }
+          (render_code_problems(problems, "script", &code.script))
           textarea id="script" name="script" rows="50" {
               (code.script)
           }
@@ -309,8 +1027,15 @@ pub fn render_edit_note(
     id: i64,
     note: &Note,
     all_codes: &Vec<String>,
-    attributes: &Vec<(String, String)>,
+    attributes: &Vec<(String, AttributeValue)>,
+    schema: &[AttributeSpec],
+    pinned: Option<i64>,
+    versions: &[CodeVersion],
 ) -> Markup {
+    let missing: Vec<&AttributeSpec> = schema
+        .iter()
+        .filter(|spec| attributes.iter().all(|(key, _)| key != &spec.name))
+        .collect();
     html! {
         main class="container" {
           a href={(uri!(show_note(id)))} role="button" {
@@ -348,15 +1073,66 @@ pub fn render_edit_note(
           button type="submit" class="contrast" { "Save Changes" }
         }
 
+        @if !versions.is_empty() {
+          h3 { "Code Version" }
+          form method="post" action=(uri!(pin_note_submit(id)))
+               class="note-bottom-buttons" {
+            select name="version" aria-label="Code version" {
+              option value="" selected[pinned.is_none()] { "Always the current version" }
+              @for v in versions {
+                option value=(v.version) selected[pinned == Some(v.version)] {
+                  "v" (v.version) ": " (v.message)
+                }
+              }
+            }
+            button type="submit" { "Pin" }
+          }
+        }
+
 
         h3 { "Attributes" }
 
         div class="edit-note-form"{
           @for (key, value) in attributes {
-            form method="post" action=(uri!(delete_attribute_submit(id, key)))
+            @let spec = schema.iter().find(|spec| &spec.name == key);
+            div class="attribute-remover" {
+              form method="post"
+                   action=(uri!(update_or_add_attribute_submit(id)))
+                   class="attribute-remover" {
+                label class="badge" for=(format!("attr_{key}")) {
+                  (format!("{} ({})", key, value.value_type()))
+                  @if spec.is_some_and(|s| s.required) { " *" }
+                }
+                input type="hidden" name="key" value=(key);
+                input type="hidden" name="value_type"
+                  value=(value.value_type().to_string());
+                (render_attribute_input(&format!("attr_{key}"),
+                                        value.value_type(), Some(value)))
+                button type="submit" { "Save" }
+              }
+              @if !spec.is_some_and(|s| s.required) {
+                form method="post" action=(uri!(delete_attribute_submit(id, key)))
+                     class="attribute-remover" {
+                  button type="submit" name="remove_attribute" value=(key) { "Remove" }
+                }
+              }
+            }
+          }
+          // Declared by the code but not set on this note
+          @for spec in missing {
+            form method="post"
+                 action=(uri!(update_or_add_attribute_submit(id)))
                  class="attribute-remover" {
-              label class="badge" { (format!("{}: {}", key, value)) }
-              button type="submit" name="remove_attribute" value=(key) { "Remove" }
+              label class="badge" for=(format!("attr_{}", spec.name)) {
+                (format!("{} ({}, unset)", spec.name, spec.value_type))
+              }
+              input type="hidden" name="key" value=(spec.name);
+              input type="hidden" name="value_type"
+                value=(spec.value_type.to_string());
+              (render_attribute_input(&format!("attr_{}", spec.name),
+                                      spec.value_type,
+                                      spec.default_value().as_ref()))
+              button type="submit" { "Set" }
             }
           }
         }
@@ -366,7 +1142,16 @@ pub fn render_edit_note(
           label for="new_attr_key" { "New Attribute Key" }
           input type="text" id="new_attr_key" name="key" required;
 
-          label for="new_attr_value" { "New Attribute Value" }
+          label for="new_attr_type" { "New Attribute Type" }
+          select id="new_attr_type" name="value_type" {
+            @for t in AttributeType::ALL {
+              option value=(t.to_string()) { (t.to_string()) }
+            }
+          }
+
+          label for="new_attr_value" {
+            "New Attribute Value (dates as 2025-07-01, notes by id, bools as true/false)"
+          }
           input type="text" id="new_attr_value" name="value" required;
 
           button type="submit" { "Add Attribute" }
@@ -385,7 +1170,7 @@ pub fn render_list_codes(
             ul {
                 @for name in codes {
                     li {
-                        a href=(uri!(crate::frontend::codes::view_code(name=&name.clone(), note=no_note.clone()))) {
+                        a href=(uri!(crate::frontend::codes::view_code(name=&name.clone(), note=no_note.clone(), version=_))) {
                             (name)
                         }
                     }
@@ -449,7 +1234,6 @@ impl<'r> Responder<'r, 'static> for View {
         }
         let main = match self.state {
             ViewState::Login => login(),
-            ViewState::Root(notes) => root(notes),
             ViewState::Note(
                 note,
                 attributes,
@@ -457,29 +1241,103 @@ impl<'r> Responder<'r, 'static> for View {
                 child_notes,
                 ancestors,
                 logs,
+                options,
+                progress,
             ) => render_note(
                 &note,
                 &attributes,
                 &forms,
                 &child_notes,
+                options,
+                &progress,
                 &ancestors,
                 &logs,
             ),
             ViewState::NoteNew(codes, parent_id) => {
                 render_new_note(codes, parent_id)
             }
-            ViewState::NoteEdit(id, note, all_codes, attributes) => {
-                render_edit_note(id, &note, &all_codes, &attributes)
+            ViewState::NoteEdit(
+                id,
+                note,
+                all_codes,
+                attributes,
+                schema,
+                pinned,
+                versions,
+            ) => render_edit_note(
+                id,
+                &note,
+                &all_codes,
+                &attributes,
+                &schema,
+                pinned,
+                &versions,
+            ),
+            ViewState::CodeUpgrade(code, notes) => {
+                render_code_upgrade(&code, &notes)
             }
             ViewState::NoteConfirmDelete(id, title) => {
                 render_confirm_delete(id, &title)
             }
-            ViewState::Code(code, next) => render_code(code, next),
+            ViewState::NoteHistory(note, revisions) => {
+                render_note_history(&note, &revisions)
+            }
+            ViewState::Log(log) => render_log(&log),
+            ViewState::Simulation(note, title, simulation) => {
+                render_simulation(&note, &title, &simulation)
+            }
+            ViewState::Trash(entries) => render_trash(&entries),
+            ViewState::Agenda(agenda, token) => {
+                render_agenda(&agenda, token.as_deref())
+            }
+            ViewState::Tree(nodes, codes, code, depth) => {
+                render_tree(&nodes, &codes, &code, depth)
+            }
+            ViewState::Board(note, key, columns) => {
+                render_board(&note, &key, &columns)
+            }
+            ViewState::NoteTemplate(note, params, destinations) => {
+                render_note_template(&note, &params, &destinations)
+            }
+            ViewState::NoteCopy(note, destinations) => {
+                render_copy_note(&note, &destinations)
+            }
+            ViewState::NoteMove(note, destinations) => {
+                render_move_note(&note, &destinations)
+            }
+            ViewState::Code(
+                code,
+                versions,
+                importers,
+                dependencies,
+                selected,
+                next,
+            ) => render_code(
+                code,
+                &versions,
+                &importers,
+                &dependencies,
+                selected,
+                next,
+            ),
+            ViewState::CodeConfirmDelete(name, users, importers) => {
+                render_confirm_delete_code(&name, &users, &importers)
+            }
+            ViewState::CodeTrace(name, trace) => {
+                render_code_trace(&name, &trace)
+            }
+            ViewState::CodeTests(name, tests, reports) => {
+                render_code_tests(&name, &tests, reports.as_deref())
+            }
             ViewState::CodeList(codes, no_note) => {
                 render_list_codes(&codes, &no_note)
             }
-            ViewState::CodeNew() => render_new_code(),
-            ViewState::CodeEdit(code, next) => render_edit_code(&code, next),
+            ViewState::CodeNew(draft, problems) => {
+                render_new_code(draft.as_ref(), &problems)
+            }
+            ViewState::CodeEdit(code, next, problems) => {
+                render_edit_code(&code, next, &problems)
+            }
         };
         let rendered_flash = render_flashes(self.flash);
         let page = Page {
@@ -42,6 +42,7 @@ pub fn prepare_rocket(figment: Figment) -> Rocket<Build> {
                 api::logout_submit,
                 api::create_note_submit,
                 api::notes::execute_action,
+                api::notes::simulate_action,
                 frontend::login::login,
                 frontend::notes::show_note,
                 frontend::notes::new_note,
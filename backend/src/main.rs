@@ -1,14 +1,10 @@
-mod api;
-mod db_manage;
-mod frontend;
-mod utils;
-
 #[macro_use]
 extern crate rocket;
 
-use backend::unauthorized;
-use db_manage::Db;
-use utils::RateLimiter;
+use backend::db_manage::code_tests::{get_tested_codes, run_code_tests};
+use backend::db_manage::{self, Db};
+use backend::utils::{self, RateLimiter};
+use backend::{api, frontend, unauthorized};
 
 use bcrypt::{hash, DEFAULT_COST};
 use rocket::figment::Figment;
@@ -26,6 +22,44 @@ enum Error {
     RocketError { source: rocket::Error },
 }
 
+/// Runs the tests of the given codes, or of every code having some, and
+/// tells whether they all passed.
+async fn test_codes(
+    conn: &mut sqlx::SqliteConnection,
+    names: &[String],
+) -> bool {
+    let names = match names {
+        [] => get_tested_codes(conn).await.expect("Failed to list codes"),
+        names => names.to_vec(),
+    };
+    let (mut passed, mut failed) = (0, 0);
+    for name in names {
+        println!("{name}");
+        let reports = match run_code_tests(conn, &name).await {
+            Ok(reports) => reports,
+            Err(e) => {
+                println!("  could not run tests: {e}");
+                failed += 1;
+                continue;
+            }
+        };
+        for report in reports {
+            if report.passed() {
+                passed += 1;
+                println!("  ok    {}", report.name);
+            } else {
+                failed += 1;
+                println!("  FAIL  {}", report.name);
+                for failure in report.failures {
+                    println!("        {failure}");
+                }
+            }
+        }
+    }
+    println!("{passed} passed, {failed} failed");
+    failed == 0
+}
+
 fn rocket_config() -> Figment {
     // initialize secret key if not yet done
     let secret_key = utils::load_or_generate_secret();
@@ -33,12 +67,14 @@ fn rocket_config() -> Figment {
 }
 
 #[rocket::main]
+#[allow(clippy::result_large_err)]
 async fn main() -> Result<(), Error> {
     // setup rocket and db
     let config = rocket_config();
     let rocket = rocket::custom(config)
         .manage(RateLimiter::new())
         .attach(Db::init())
+        .attach(db_manage::trash_purge_fairing())
         .mount("/static", FileServer::from("static"))
         .mount(
             "/",
@@ -47,18 +83,52 @@ async fn main() -> Result<(), Error> {
                 api::logout_submit,
                 api::create_note_submit,
                 api::notes::execute_action,
+                api::notes::simulate_action,
                 api::codes::create_code_submit,
                 api::codes::edit_code_submit,
+                api::codes::rollback_code_submit,
+                api::codes::upgrade_notes_submit,
+                api::codes::rename_code_submit,
+                api::codes::delete_code_submit,
+                api::codes::save_code_tests_submit,
+                api::codes::run_code_tests_submit,
+                frontend::codes::code_tests,
+                api::codes::trace_code_submit,
+                frontend::notes::view_log,
+                frontend::codes::delete_code_confirm,
+                api::notes::pin_note_submit,
+                frontend::codes::upgrade_code,
                 api::notes::edit_note_submit,
                 api::notes::delete_attribute_submit,
                 api::notes::delete_note_submit,
                 api::notes::update_or_add_attribute_submit,
+                api::notes::revert_revision_submit,
+                api::notes::restore_note_submit,
+                api::notes::purge_note_submit,
+                api::notes::move_note_submit,
+                api::notes::copy_note_submit,
+                api::notes::template_submit,
+                api::notes::instantiate_submit,
+                api::notes::reorder_note_submit,
+                api::notes::hide_done_submit,
+                api::notes::priority_sort_submit,
                 frontend::login::login,
                 frontend::notes::show_note,
                 frontend::notes::new_note,
                 frontend::notes::root_notes,
                 frontend::notes::edit_note,
                 frontend::notes::delete_note_confirm,
+                frontend::notes::note_history,
+                frontend::notes::trash,
+                frontend::notes::move_note_form,
+                frontend::notes::copy_note_form,
+                frontend::notes::note_template,
+                frontend::notes::note_tree,
+                frontend::notes::agenda,
+                frontend::notes::note_board,
+                api::notes::move_card_submit,
+                frontend::calendar::calendar_feed,
+                api::login::reset_calendar_token_submit,
                 frontend::codes::new_code,
                 frontend::codes::edit_code,
                 frontend::codes::view_code,
@@ -70,11 +140,16 @@ async fn main() -> Result<(), Error> {
         .await
         .context(RocketSnafu)?;
     let db = Db::fetch(&rocket).expect("Database not initialized");
-    db_manage::migrate(&db)
-        .await
-        .expect("Failed to run DB setup");
+    db_manage::migrate(db).await.context(DbSnafu)?;
 
     let mut conn = db.acquire().await.context(DbSnafu)?;
+
+    // `backend test-codes [NAME...]` runs code tests instead of serving
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("test-codes") {
+        let passed = test_codes(&mut conn, &args[1..]).await;
+        std::process::exit(if passed { 0 } else { 1 });
+    }
     let is_initialized =
         db_manage::get_password(&mut conn).await.clone().is_some();
 
@@ -97,7 +172,7 @@ async fn main() -> Result<(), Error> {
         let hash =
             hash(password, DEFAULT_COST).expect("Failed to hash password");
 
-        db_manage::set_password(&db, hash).await.context(DbSnafu)?;
+        db_manage::set_password(db, hash).await.context(DbSnafu)?;
     }
 
     rocket.launch().await.context(RocketSnafu)?;
@@ -39,6 +39,12 @@ impl RateLimiter {
     }
 }
 
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub fn is_password_valid(pw: &str) -> bool {
     !pw.is_empty() && pw.chars().all(|c| c.is_ascii_alphanumeric())
 }
@@ -58,3 +64,45 @@ pub fn load_or_generate_secret() -> String {
         }
     }
 }
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiffLine {
+    Same(String),
+    Removed(String),
+    Added(String),
+}
+
+/// Line based diff from the longest common subsequence of both texts.
+pub fn diff_lines(before: &str, after: &str) -> Vec<DiffLine> {
+    let a: Vec<&str> = before.lines().collect();
+    let b: Vec<&str> = after.lines().collect();
+    // lcs[i][j] is the common length of a[i..] and b[j..]
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut result = vec![];
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            result.push(DiffLine::Same(a[i].to_string()));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            result.push(DiffLine::Removed(a[i].to_string()));
+            i += 1;
+        } else {
+            result.push(DiffLine::Added(b[j].to_string()));
+            j += 1;
+        }
+    }
+    result.extend(a[i..].iter().map(|l| DiffLine::Removed(l.to_string())));
+    result.extend(b[j..].iter().map(|l| DiffLine::Added(l.to_string())));
+    result
+}
@@ -0,0 +1,29 @@
+// Drag and drop for the board view: dropping a card on a column submits
+// the card's move form with the column value.
+let dragged = null;
+
+document.querySelectorAll(".board-card").forEach((card) => {
+  card.addEventListener("dragstart", () => {
+    dragged = card;
+  });
+});
+
+document.querySelectorAll(".board-column").forEach((column) => {
+  column.addEventListener("dragover", (event) => {
+    event.preventDefault();
+    column.classList.add("board-over");
+  });
+  column.addEventListener("dragleave", () => {
+    column.classList.remove("board-over");
+  });
+  column.addEventListener("drop", (event) => {
+    event.preventDefault();
+    column.classList.remove("board-over");
+    if (dragged === null || dragged.closest(".board-column") === column) {
+      return;
+    }
+    const form = dragged.querySelector("form");
+    form.querySelector("input[name=value]").value = column.dataset.value;
+    form.submit();
+  });
+});
@@ -0,0 +1,32 @@
+// Replay of a recorded trace: shows the commands of a finished run one at
+// a time, the result or error only once every command is shown.
+document.querySelectorAll(".trace").forEach((trace) => {
+  const steps = trace.querySelectorAll(".trace-step");
+  const end = trace.querySelector(".trace-end");
+  let shown = steps.length;
+
+  const update = () => {
+    steps.forEach((step, i) => {
+      step.hidden = i >= shown;
+      step.classList.toggle("trace-current", i === shown - 1);
+    });
+    end.hidden = shown < steps.length;
+  };
+
+  trace.querySelectorAll("[data-replay]").forEach((button) => {
+    button.addEventListener("click", () => {
+      switch (button.dataset.replay) {
+        case "back":
+          shown = Math.max(shown - 1, 0);
+          break;
+        case "next":
+          // Going on from the whole run starts over
+          shown = shown >= steps.length ? Math.min(1, steps.length) : shown + 1;
+          break;
+        default:
+          shown = steps.length;
+      }
+      update();
+    });
+  });
+});
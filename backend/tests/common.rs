@@ -1,3 +1,5 @@
+#![allow(dead_code)]
+
 //use crate::integration;
 
 use std::net::SocketAddrV4;
@@ -11,9 +13,11 @@ use sqlx::{sqlite::SqlitePoolOptions, Executor};
 pub const LOCALHOST: SocketAddrV4 =
     std::net::SocketAddrV4::new(std::net::Ipv4Addr::LOCALHOST, 8000);
 
+use backend::api::codes::{Action, FormContainer, FormType};
 use backend::db_manage::Db;
 use backend::utils::RateLimiter;
 use backend::{internal_error, unauthorized};
+use chrono::NaiveDate;
 use rocket::{catchers, routes};
 use rocket::{Build, Rocket};
 use rocket_db_pools::Database;
@@ -37,6 +41,7 @@ pub async fn spawn_test_rocket() -> Rocket<Build> {
                 backend::frontend::notes::show_note,
                 backend::frontend::notes::new_note,
                 backend::frontend::notes::root_notes,
+                backend::frontend::calendar::calendar_feed,
             ],
         )
         .register("/", catchers![unauthorized])
@@ -51,10 +56,27 @@ pub async fn prepare_test_db() -> SqlitePool {
         .unwrap();
 
     let meta = include_str!(".././tests/meta.sql");
-    let migration = include_str!("../migrations/001-init.sql");
+    let migrations = [
+        include_str!("../migrations/001-init.sql"),
+        include_str!("../migrations/002-typed-attributes.sql"),
+        include_str!("../migrations/003-attribute-schemas.sql"),
+        include_str!("../migrations/004-revisions.sql"),
+        include_str!("../migrations/005-trash.sql"),
+        include_str!("../migrations/006-note-positions.sql"),
+        include_str!("../migrations/007-templates.sql"),
+        include_str!("../migrations/008-root-note.sql"),
+        include_str!("../migrations/009-completion-attribute.sql"),
+        include_str!("../migrations/010-board-action.sql"),
+        include_str!("../migrations/011-code-versions.sql"),
+        include_str!("../migrations/012-pinned-versions.sql"),
+        include_str!("../migrations/013-code-tests.sql"),
+        include_str!("../migrations/014-code-libraries.sql"),
+    ];
     let dump = include_str!(".././tests/test_dump.sql");
     pool.execute(meta).await.unwrap();
-    pool.execute(migration).await.unwrap();
+    for migration in migrations {
+        pool.execute(migration).await.unwrap();
+    }
     pool.execute(dump).await.unwrap();
     pool
 }
@@ -77,3 +99,21 @@ pub async fn login_as_test_user() -> Client {
     }
     client
 }
+
+/// A day of a fixed month, for tests that only compare dates.
+pub fn date(day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(2025, 5, day).unwrap()
+}
+
+/// A form without fields running the action `label`.
+pub fn form(label: &str) -> FormContainer {
+    FormContainer {
+        title: label.into(),
+        label: label.into(),
+        action: Action {
+            label: label.into(),
+            title: label.into(),
+            form_type: FormType::Empty,
+        },
+    }
+}
@@ -0,0 +1,66 @@
+mod common;
+
+use backend::db_manage::agenda::get_agenda;
+use backend::db_manage::attributes::{set_attribute, AttributeValue};
+use backend::db_manage::create_note;
+use backend::db_manage::errors::DbError;
+use backend::db_manage::notes::get_child_notes_with_status;
+use rocket::tokio;
+
+#[tokio::test]
+async fn test_reserved_attribute_types() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    let text = AttributeValue::String("soon".to_string());
+    let result = set_attribute(&mut conn, 1, "due", &text).await;
+    assert!(matches!(result, Err(DbError::InvalidAttribute { .. })));
+    let result = set_attribute(&mut conn, 1, "priority", &text).await;
+    assert!(matches!(result, Err(DbError::InvalidAttribute { .. })));
+}
+
+#[tokio::test]
+async fn test_agenda_groups() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    let mut ids = vec![];
+    for (title, day, priority) in [
+        ("late", 1, 0),
+        ("now", 10, 1),
+        ("urgent", 10, 5),
+        ("later", 20, 0),
+    ] {
+        let id = create_note(&mut conn, 1, title.into(), "".into(), None)
+            .await
+            .unwrap();
+        let due = AttributeValue::Date(common::date(day));
+        set_attribute(&mut conn, id, "due", &due).await.unwrap();
+        let priority = AttributeValue::Integer(priority);
+        set_attribute(&mut conn, id, "priority", &priority)
+            .await
+            .unwrap();
+        ids.push(id);
+    }
+    // Done notes are left out
+    let done = AttributeValue::Date(common::date(2));
+    set_attribute(&mut conn, ids[3], "done", &done)
+        .await
+        .unwrap();
+
+    let agenda = get_agenda(&mut conn, common::date(10)).await.unwrap();
+    let titles = |entries: &[backend::db_manage::agenda::AgendaEntry]| {
+        entries.iter().map(|e| e.title.clone()).collect::<Vec<_>>()
+    };
+    assert_eq!(titles(&agenda.overdue), ["late"]);
+    assert_eq!(titles(&agenda.today), ["urgent", "now"]);
+    assert!(agenda.upcoming.is_empty());
+
+    let children = get_child_notes_with_status(&mut conn, 1, true)
+        .await
+        .unwrap();
+    let titles: Vec<String> =
+        children.into_iter().map(|(n, _)| n.title).collect();
+    assert_eq!(
+        titles,
+        ["urgent", "now", "Sub note of one", "late", "later"]
+    );
+}
@@ -0,0 +1,82 @@
+mod common;
+
+use backend::db_manage::attributes::{
+    get_attribute, get_attribute_type, set_attribute, AttributeType,
+    AttributeValue,
+};
+use backend::db_manage::errors::DbError;
+use backend::db_manage::{create_note, ROOT_NOTE_ID};
+use chrono::NaiveDate;
+use rocket::tokio;
+
+#[tokio::test]
+async fn test_typed_round_trip() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    let values = [
+        AttributeValue::Integer(-3),
+        AttributeValue::Float(1.5),
+        AttributeValue::Bool(true),
+        AttributeValue::Date(NaiveDate::from_ymd_opt(2025, 7, 1).unwrap()),
+        AttributeValue::Json(serde_json::json!({ "a": [1, 2] })),
+        AttributeValue::Note(2),
+    ];
+    for value in values {
+        set_attribute(&mut conn, 1, "typed", &value).await.unwrap();
+        let stored = get_attribute(&mut conn, 1, "typed").await.unwrap();
+        assert_eq!(stored, Some(value));
+    }
+}
+
+#[tokio::test]
+async fn test_invalid_values_rejected() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    let dangling = AttributeValue::Note(999);
+    let result = set_attribute(&mut conn, 1, "link", &dangling).await;
+    assert!(matches!(result, Err(DbError::InvalidAttribute { .. })));
+    assert!(AttributeValue::parse(AttributeType::Date, "tomorrow").is_err());
+    assert!(AttributeValue::parse(AttributeType::Integer, "1.5").is_err());
+}
+
+#[tokio::test]
+async fn test_schema_defaults_and_types() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    sqlx::query(
+        r#"INSERT INTO codes (name, capabilities, script, attribute_schema)
+           VALUES ('counter', '[]', '',
+                   '[{ "name": "count", "value_type": "Integer",
+                       "required": true, "default": "0" },
+                     { "name": "limit", "value_type": "Integer" }]')"#,
+    )
+    .execute(&mut *conn)
+    .await
+    .unwrap();
+    let id = create_note(
+        &mut conn,
+        ROOT_NOTE_ID,
+        "Counted".to_string(),
+        String::new(),
+        Some("counter".to_string()),
+    )
+    .await
+    .unwrap();
+    let count = get_attribute(&mut conn, id, "count").await.unwrap();
+    assert_eq!(count, Some(AttributeValue::Integer(0)));
+    let wrong = AttributeValue::String("zero".to_string());
+    let result = set_attribute(&mut conn, id, "count", &wrong).await;
+    assert!(matches!(result, Err(DbError::InvalidAttribute { .. })));
+
+    // Declared types come first, then the stored value, then strings
+    let flag = AttributeValue::Bool(false);
+    set_attribute(&mut conn, id, "flag", &flag).await.unwrap();
+    for (key, expected) in [
+        ("limit", AttributeType::Integer),
+        ("flag", AttributeType::Bool),
+        ("other", AttributeType::String),
+    ] {
+        let value_type = get_attribute_type(&mut conn, id, key).await;
+        assert_eq!(value_type.unwrap(), expected);
+    }
+}
@@ -0,0 +1,108 @@
+mod common;
+
+use backend::db_manage::attributes::{
+    get_attribute, set_attribute, AttributeValue,
+};
+use backend::db_manage::board::{get_board, move_card};
+use backend::db_manage::create_note;
+use rocket::tokio;
+
+#[tokio::test]
+async fn test_board_columns_and_moves() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    let mut ids = vec![];
+    for (title, status) in
+        [("a", Some("doing")), ("b", None), ("c", Some("todo"))]
+    {
+        let id = create_note(&mut conn, 1, title.into(), "".into(), None)
+            .await
+            .unwrap();
+        if let Some(status) = status {
+            let value = AttributeValue::String(status.into());
+            set_attribute(&mut conn, id, "status", &value)
+                .await
+                .unwrap();
+        }
+        ids.push(id);
+    }
+    set_attribute(&mut conn, ids[1], "points", &AttributeValue::Integer(1))
+        .await
+        .unwrap();
+
+    let columns = get_board(&mut conn, 1, "status").await.unwrap();
+    let values: Vec<_> = columns.iter().map(|c| c.value.clone()).collect();
+    assert_eq!(values, [None, Some("doing".into()), Some("todo".into())]);
+    // The existing sub note and "b" have no status
+    assert_eq!(columns[0].cards.len(), 2);
+
+    move_card(&mut conn, ids[0], "status", Some("todo"))
+        .await
+        .unwrap();
+    move_card(&mut conn, ids[2], "status", None).await.unwrap();
+    let columns = get_board(&mut conn, 1, "status").await.unwrap();
+    let values: Vec<_> = columns.iter().map(|c| c.value.clone()).collect();
+    assert_eq!(values, [None, Some("todo".into())]);
+
+    // Moves keep the type of the current value
+    move_card(&mut conn, ids[1], "points", Some("3"))
+        .await
+        .unwrap();
+    let points = get_attribute(&mut conn, ids[1], "points").await.unwrap();
+    assert_eq!(points, Some(AttributeValue::Integer(3)));
+    assert!(move_card(&mut conn, ids[1], "points", Some("x"))
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_board_action() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    sqlx::query(
+        r#"INSERT INTO codes
+             (name, capabilities, script, board_action, board_action_key)
+           VALUES ('stage', '[{ "SetAttribute": "Own" }]', ?, 'advance',
+                   'status')"#,
+    )
+    .bind(
+        r#"
+        forms = coroutine.create(function()
+          return { Result = { advance = { title = "Advance", label = "advance",
+            action = { label = "advance", title = "Stage", form_type = "Text" } } } }
+        end)
+        advance = coroutine.create(function(value)
+          local id = coroutine.yield("GetId")
+          coroutine.yield({ SetAttribute = { id = id, key = "status",
+            value = { String = "checked " .. value.Text } } })
+          return { Result = "advanced" }
+        end)
+        "#,
+    )
+    .execute(&mut *conn)
+    .await
+    .unwrap();
+    let id = create_note(
+        &mut conn,
+        1,
+        "staged".into(),
+        "".into(),
+        Some("stage".into()),
+    )
+    .await
+    .unwrap();
+    let message = move_card(&mut conn, id, "status", Some("done"))
+        .await
+        .unwrap();
+    assert_eq!(message, "advanced");
+    let status = get_attribute(&mut conn, id, "status").await.unwrap();
+    assert_eq!(status, Some(AttributeValue::String("checked done".into())));
+
+    // Boards grouping by another key set the attribute themselves
+    let message = move_card(&mut conn, id, "owner", Some("bob"))
+        .await
+        .unwrap();
+    assert_eq!(message, format!("Set owner of note {id} to bob."));
+    let owner = get_attribute(&mut conn, id, "owner").await.unwrap();
+    assert_eq!(owner, Some(AttributeValue::String("bob".into())));
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use backend::db_manage::agenda::get_calendar_entries;
+use backend::db_manage::attributes::{set_attribute, AttributeValue};
+use backend::db_manage::create_note;
+use backend::db_manage::login::{get_calendar_token, reset_calendar_token};
+use backend::frontend::calendar::render_ics;
+use chrono::NaiveDate;
+use rocket::{http::Status, tokio};
+
+#[tokio::test]
+async fn test_calendar_feed() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    let day = NaiveDate::from_ymd_opt(2025, 5, 10).unwrap();
+    let inside =
+        create_note(&mut conn, 1, "Call, then write".into(), "".into(), None)
+            .await
+            .unwrap();
+    let outside =
+        create_note(&mut conn, 0, "Elsewhere".into(), "".into(), None)
+            .await
+            .unwrap();
+    for id in [inside, outside] {
+        set_attribute(&mut conn, id, "due", &AttributeValue::Date(day))
+            .await
+            .unwrap();
+    }
+    let alarm = AttributeValue::DateTime(day.and_hms_opt(9, 0, 0).unwrap());
+    set_attribute(&mut conn, inside, "alarm", &alarm)
+        .await
+        .unwrap();
+    set_attribute(&mut conn, outside, "done", &AttributeValue::Date(day))
+        .await
+        .unwrap();
+
+    let entries = get_calendar_entries(&mut conn, 1).await.unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].id, inside);
+    assert!(entries[0].alarm.is_some());
+
+    let entries = get_calendar_entries(&mut conn, 0).await.unwrap();
+    assert_eq!(entries.len(), 2);
+    let ics = render_ics(&entries, day.and_hms_opt(0, 0, 0).unwrap());
+    assert!(ics.starts_with("BEGIN:VCALENDAR\r\n"));
+    assert!(ics.contains("SUMMARY:Call\\, then write\r\n"));
+    assert!(ics.contains("DUE;VALUE=DATE:20250510\r\n"));
+    assert!(ics.contains("DTEND;VALUE=DATE:20250511\r\n"));
+    assert!(ics.contains("STATUS:COMPLETED\r\n"));
+    assert!(ics.contains("STATUS:NEEDS-ACTION\r\n"));
+    assert_eq!(ics.matches("BEGIN:VALARM").count(), 1);
+    let event = &ics[ics.find("BEGIN:VEVENT").unwrap()..];
+    assert!(event.contains("BEGIN:VALARM\r\n"));
+    assert_eq!(ics.matches("BEGIN:VTODO").count(), 2);
+    assert_eq!(ics.matches("BEGIN:VEVENT").count(), 2);
+
+    assert!(get_calendar_token(&mut conn).await.unwrap().is_none());
+    let token = reset_calendar_token(&mut conn).await.unwrap();
+    assert_eq!(get_calendar_token(&mut conn).await.unwrap(), Some(token));
+}
+
+#[tokio::test]
+async fn test_calendar_feed_needs_token() {
+    let rocket = common::spawn_test_rocket().await;
+    let client = rocket::local::asynchronous::Client::untracked(rocket)
+        .await
+        .expect("valid rocket instance");
+    for uri in [
+        "/calendar.ics",
+        "/calendar.ics?token=",
+        "/calendar.ics?token=wrong",
+    ] {
+        let response = client.get(uri).dispatch().await;
+        assert_eq!(response.status(), Status::Forbidden, "{uri}");
+    }
+}
@@ -0,0 +1,115 @@
+mod common;
+
+use backend::db_manage::code_versions::{get_code_versions, pin_note};
+use backend::db_manage::codes::{
+    create_code, delete_code, get_code_by_name, get_code_users, rename_code,
+};
+use backend::db_manage::dependencies::{
+    get_importers, imports, rename_imports,
+};
+use backend::db_manage::errors::DbError;
+use backend::db_manage::{create_note, get_note};
+use rocket::tokio;
+
+const LIBRARY: &str = r#"
+    return {}
+"#;
+
+const IMPORTER: &str = r#"
+    -- kept as is: { Import = "lib" }
+    local hint = '{ Import = "lib" }'
+    forms = coroutine.create(function() return { Result = {} } end)
+    run = coroutine.create(function()
+      coroutine.yield({ Import = "lib" })
+      coroutine.yield({Import='lib'})
+      coroutine.yield({ Import = "other" })
+      return { Result = "ok" }
+    end)
+"#;
+
+#[test]
+fn test_import_scan() {
+    assert_eq!(imports(IMPORTER), ["lib", "other"]);
+    assert!(imports("-- Import nothing, Import = 3").is_empty());
+    assert!(imports("--[==[ { Import = 'a' } ]==]").is_empty());
+    assert!(imports("x = [[ { Import = 'a' } ]]").is_empty());
+    assert!(imports("x = { MyImport = 'a' }").is_empty());
+    assert_eq!(imports("x = {[[a]], Import = [[b]]}"), ["b"]);
+}
+
+#[test]
+fn test_import_scan_non_ascii() {
+    assert!(imports("x = 1\u{a0}-- é").is_empty());
+    assert_eq!(imports("é = { Import = 'lib' }ü"), ["lib"]);
+    assert_eq!(
+        rename_imports("{ Import = 'lib' }\u{a0}'ß'", "lib", "new"),
+        "{ Import = 'new' }\u{a0}'ß'"
+    );
+}
+
+#[tokio::test]
+async fn test_rename_and_delete() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    for (name, script, library) in
+        [("lib", LIBRARY, true), ("app", IMPORTER, false)]
+    {
+        create_code(
+            &mut conn,
+            name.into(),
+            r#"["SysLog"]"#.into(),
+            script.into(),
+            "[]".into(),
+            "done".into(),
+            None,
+            library,
+            "alice",
+        )
+        .await
+        .unwrap();
+    }
+    let note =
+        create_note(&mut conn, 0, "n".into(), "".into(), Some("app".into()))
+            .await
+            .unwrap();
+    assert_eq!(get_importers(&mut conn, "lib").await.unwrap(), ["app"]);
+
+    assert!(rename_code(&mut conn, "lib", "app", "bob").await.is_err());
+    pin_note(&mut conn, note, Some(1)).await.unwrap();
+    let error = rename_code(&mut conn, "lib", "core", "bob")
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(&error, DbError::LibraryPinned { pinned, .. } if pinned == &["app@1"]),
+        "{error}"
+    );
+    pin_note(&mut conn, note, None).await.unwrap();
+    let rewritten = rename_code(&mut conn, "lib", "core", "bob").await.unwrap();
+    assert_eq!(rewritten, ["app"]);
+    assert!(get_code_by_name(&mut conn, "lib").await.unwrap().is_none());
+    assert_eq!(get_code_versions(&mut conn, "core").await.unwrap().len(), 1);
+    let app = get_code_by_name(&mut conn, "app").await.unwrap().unwrap();
+    assert_eq!(imports(&app.script), ["core", "other"]);
+    assert!(app.script.contains(r#"-- kept as is: { Import = "lib" }"#));
+    assert!(app.script.contains(r#"'{ Import = "lib" }'"#));
+    assert_eq!(app.version, 2);
+
+    rename_code(&mut conn, "app", "main", "bob").await.unwrap();
+    let renamed = get_note(&mut conn, note).await.unwrap().unwrap();
+    assert_eq!(renamed.code_name.as_deref(), Some("main"));
+    assert_eq!(get_code_users(&mut conn, "main").await.unwrap().len(), 1);
+    let error = delete_code(&mut conn, "core").await.unwrap_err();
+    assert!(
+        matches!(&error, DbError::CodeImported { importers, .. } if importers == &["main"]),
+        "{error}"
+    );
+    assert_eq!(delete_code(&mut conn, "main").await.unwrap(), 1);
+    let unbound = get_note(&mut conn, note).await.unwrap().unwrap();
+    assert!(unbound.code_name.is_none());
+    assert!(get_code_versions(&mut conn, "main")
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(delete_code(&mut conn, "main").await.is_err());
+    assert_eq!(delete_code(&mut conn, "core").await.unwrap(), 0);
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use backend::db_manage::code_tests::{
+    get_tested_codes, run_code_tests, set_code_tests,
+};
+use backend::db_manage::codes::create_code;
+use backend::db_manage::errors::DbError;
+use rocket::tokio;
+
+const SCRIPT: &str = r#"
+forms = coroutine.create(function() return { Result = {} } end)
+
+bump = coroutine.create(function(value)
+  local id = coroutine.yield("GetId")
+  local count = coroutine.yield({ GetAttribute = { id = id, key = "count" } })
+  local next = (count and count.Integer or 0) + value.UInt
+  coroutine.yield({ SetAttribute = { id = id, key = "count",
+                                     value = { Integer = next } } })
+  return { Result = "bumped" }
+end)
+
+spin = coroutine.create(function()
+  while true do end
+end)
+"#;
+
+const TESTS: &str = r#"[
+  { "name": "from nothing", "action": "bump", "input": { "UInt": 2 },
+    "result": "bumped",
+    "expected_attributes": { "count": { "Integer": 2 }, "other": null } },
+  { "name": "wrong count", "action": "bump", "input": { "UInt": 1 },
+    "attributes": { "count": { "Integer": 5 } },
+    "expected_attributes": { "count": { "Integer": 5 } } },
+  { "name": "missing action", "action": "nope" },
+  { "name": "runaway", "action": "spin" }
+]"#;
+
+#[tokio::test]
+async fn test_run_code_tests() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    create_code(
+        &mut conn,
+        "counter".into(),
+        r#"[{"GetAttribute": "Own"}, {"SetAttribute": "Own"}]"#.into(),
+        SCRIPT.into(),
+        "[]".into(),
+        "done".into(),
+        None,
+        false,
+        "alice",
+    )
+    .await
+    .unwrap();
+    assert!(get_tested_codes(&mut conn).await.unwrap().is_empty());
+    assert!(set_code_tests(&mut conn, "counter", "[{").await.is_err());
+    let error = set_code_tests(&mut conn, "nope", "[]").await.unwrap_err();
+    assert!(matches!(error, DbError::CodeNotFound { .. }), "{error}");
+    set_code_tests(&mut conn, "counter", TESTS).await.unwrap();
+    assert_eq!(get_tested_codes(&mut conn).await.unwrap(), ["counter"]);
+
+    let reports = run_code_tests(&mut conn, "counter").await.unwrap();
+    let passed: Vec<_> = reports.iter().map(|r| r.passed()).collect();
+    assert_eq!(passed, [true, false, false, false]);
+    assert_eq!(reports[1].failures.len(), 1);
+    assert!(reports[1].failures[0].starts_with("attribute count"));
+    assert!(reports[3].failures[0].contains("ran for more than"));
+
+    // Each case has its own database, nothing reaches this one
+    let count: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM attributes WHERE key = 'count'",
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .unwrap();
+    assert_eq!(count, 0);
+}
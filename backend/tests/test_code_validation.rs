@@ -0,0 +1,129 @@
+mod common;
+
+use backend::db_manage::codes::{create_code, gated_commands, Capabilities};
+use backend::db_manage::dependencies::check_capabilities;
+use backend::db_manage::errors::DbError;
+use backend::db_manage::validation::{
+    capability_report, check_code, used_commands,
+};
+use rocket::tokio;
+
+const FORMS: &str = "forms = coroutine.create(function() end)";
+
+#[test]
+fn test_gated_commands_follow_capabilities() {
+    for (capability, command) in Capabilities::ALL.iter().zip(gated_commands())
+    {
+        // Capabilities are granted by the name of their command
+        let json = serde_json::to_value(capability).unwrap();
+        let name = match &json {
+            serde_json::Value::String(name) => name.as_str(),
+            serde_json::Value::Object(map) => map.keys().next().unwrap(),
+            _ => panic!("unexpected capability {json}"),
+        };
+        assert_eq!(name, command);
+        assert_eq!(used_commands(&format!("{{ {command} = 1 }}")), [command]);
+    }
+}
+
+#[test]
+fn test_check_code() {
+    assert!(check_code(r#"["SysLog"]"#, FORMS, "[]", false).is_empty());
+
+    let problems = check_code("[\n  \"Nope\"\n]", FORMS, "[]", false);
+    assert_eq!(problems.len(), 1);
+    assert_eq!(problems[0].field, "capabilities");
+    assert_eq!((problems[0].line, problems[0].column), (Some(2), Some(8)));
+
+    let script = format!("{FORMS}\nlocal x = = 1");
+    let problems = check_code("[]", &script, "[]", false);
+    assert_eq!(problems.len(), 1);
+    assert_eq!(problems[0].field, "script");
+    assert_eq!(problems[0].line, Some(2));
+
+    let problems = check_code("[]", "-- nothing", "[{}]", false);
+    let fields: Vec<_> = problems.iter().map(|p| p.field.as_str()).collect();
+    assert_eq!(fields, ["attribute_schema", "script"]);
+
+    // Runaway top levels are stopped
+    let problems = check_code("[]", "while true do end", "[]", false);
+    assert!(problems[0].message.contains("ran for more than"));
+    let script = "local t = {} while true do table.insert(t, t) end";
+    assert_eq!(check_code("[]", script, "[]", false).len(), 1);
+}
+
+#[tokio::test]
+async fn test_invalid_code_is_not_saved() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    let result = create_code(
+        &mut conn,
+        "broken".into(),
+        "[".into(),
+        "function(".into(),
+        "[]".into(),
+        "done".into(),
+        None,
+        false,
+        "alice",
+    )
+    .await;
+    let Err(DbError::InvalidCode { problems }) = result else {
+        panic!("expected problems, got {result:?}");
+    };
+    assert_eq!(problems.len(), 2);
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM codes")
+        .fetch_one(&mut *conn)
+        .await
+        .unwrap();
+    assert_eq!(count, 1);
+}
+
+#[test]
+fn test_used_commands() {
+    let script = r#"
+        coroutine.yield({ SysLog = "hi" })
+        coroutine.yield({SetAttribute={ id = id, key = "k", value = v }})
+        if GetProgress == nil then end
+        local MyCopyNote = 1
+    "#;
+    assert_eq!(used_commands(script), ["SysLog", "SetAttribute"]);
+}
+
+#[test]
+fn test_capability_report() {
+    let script = "coroutine.yield({ GetAttribute = { id = 1, key = 'k' } })";
+    let report = capability_report(r#"["SysLog"]"#, &[script]);
+    assert_eq!(report.missing, ["GetAttribute"]);
+    assert_eq!(report.unused, ["SysLog"]);
+    assert_eq!(
+        report.to_string(),
+        "Capabilities used but not granted: GetAttribute; \
+         granted but unused: SysLog."
+    );
+    let report = capability_report(r#"[{"GetAttribute": "Own"}]"#, &[script]);
+    assert!(report.is_empty());
+}
+
+#[tokio::test]
+async fn test_check_capabilities_follows_imports() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    create_code(
+        &mut conn,
+        "logger".into(),
+        r#"["SysLog"]"#.into(),
+        "function log(s) coroutine.yield({ SysLog = s }) end".into(),
+        "[]".into(),
+        "done".into(),
+        None,
+        true,
+        "alice",
+    )
+    .await
+    .unwrap();
+    let script = format!("{FORMS}\ncoroutine.yield({{ Import = 'logger' }})");
+    let report = check_capabilities(&mut conn, "[]", &script).await.unwrap();
+    assert_eq!(report.missing, ["SysLog"]);
+    assert!(report.unused.is_empty());
+}
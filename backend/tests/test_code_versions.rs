@@ -0,0 +1,199 @@
+mod common;
+
+use backend::api::codes::{Action, FormContainer, FormType, Value};
+use backend::db_manage::attributes::{
+    get_attribute, set_attribute, AttributeValue,
+};
+use backend::db_manage::code_versions::{
+    get_code_versions, get_outdated_notes, get_pinned_version, pin_note,
+    rollback_code, upgrade_note,
+};
+use backend::db_manage::codes::{
+    create_code, edit_code, execute, get_code, get_code_by_name,
+};
+use backend::db_manage::create_note;
+use backend::db_manage::errors::DbError;
+use rocket::tokio;
+
+fn script(result: &str) -> String {
+    format!(
+        r#"
+        forms = coroutine.create(function() return {{ Result = {{}} }} end)
+        act = coroutine.create(function()
+          return {{ Result = "{result}" }}
+        end)
+        "#
+    )
+}
+
+#[tokio::test]
+async fn test_versions_and_rollback() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    let name = create_code(
+        &mut conn,
+        "versioned".into(),
+        r#"["SysLog"]"#.into(),
+        script("one"),
+        "[]".into(),
+        "done".into(),
+        None,
+        false,
+        "alice",
+    )
+    .await
+    .unwrap();
+    let version = edit_code(
+        &mut conn,
+        &name,
+        "[]",
+        &script("two"),
+        "[]",
+        "done",
+        None,
+        false,
+        "bob",
+        "Say two",
+    )
+    .await
+    .unwrap();
+    assert_eq!(version, 2);
+
+    let versions = get_code_versions(&mut conn, &name).await.unwrap();
+    let summary: Vec<_> = versions
+        .iter()
+        .map(|v| (v.version, v.author.as_str(), v.message.as_str()))
+        .collect();
+    assert_eq!(summary, [(2, "bob", "Say two"), (1, "alice", "Created")]);
+
+    // Versions cannot be rewritten
+    let rewrite = sqlx::query("UPDATE code_versions SET script = ''")
+        .execute(&mut *conn)
+        .await;
+    assert!(rewrite.is_err());
+
+    assert_eq!(
+        rollback_code(&mut conn, &name, 1, "carol").await.unwrap(),
+        3
+    );
+    let code = get_code_by_name(&mut conn, &name).await.unwrap().unwrap();
+    assert_eq!(code.version, 3);
+    assert_eq!(code.script, script("one"));
+    let error = rollback_code(&mut conn, &name, 7, "carol")
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(error, DbError::CodeVersionNotFound { version: 7, .. }),
+        "{error}"
+    );
+    let error = edit_code(
+        &mut conn, "nope", "[]", "", "[]", "done", None, true, "carol", "",
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(error, DbError::CodeNotFound { .. }), "{error}");
+
+    // Execution logs record the version that ran
+    let note = create_note(&mut conn, 0, "n".into(), "".into(), Some(name))
+        .await
+        .unwrap();
+    let form = FormContainer {
+        title: "Act".into(),
+        label: "act".into(),
+        action: Action {
+            label: "act".into(),
+            title: "Act".into(),
+            form_type: FormType::Empty,
+        },
+    };
+    let message = execute(&mut conn, note, &form, &Value::Empty)
+        .await
+        .unwrap();
+    assert_eq!(message, "one");
+    let logged: Option<i64> = sqlx::query_scalar(
+        "SELECT code_version FROM logs WHERE note_id = ? ORDER BY id DESC",
+    )
+    .bind(note)
+    .fetch_one(&mut *conn)
+    .await
+    .unwrap();
+    assert_eq!(logged, Some(3));
+}
+
+#[tokio::test]
+async fn test_pin_and_upgrade() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    let caps = r#"[{ "GetAttribute": "Own" }, { "SetAttribute": "Own" }]"#;
+    let name = create_code(
+        &mut conn,
+        "sized".into(),
+        caps.into(),
+        script("one"),
+        r#"[{ "name": "size", "value_type": "String" }]"#.into(),
+        "done".into(),
+        None,
+        false,
+        "alice",
+    )
+    .await
+    .unwrap();
+    let note =
+        create_note(&mut conn, 0, "n".into(), "".into(), Some(name.clone()))
+            .await
+            .unwrap();
+    let size = AttributeValue::String("3".into());
+    set_attribute(&mut conn, note, "size", &size).await.unwrap();
+    pin_note(&mut conn, note, Some(1)).await.unwrap();
+    let error = pin_note(&mut conn, note, Some(5)).await.unwrap_err();
+    assert!(
+        matches!(error, DbError::CodeVersionNotFound { .. }),
+        "{error}"
+    );
+    let error = pin_note(&mut conn, 999, None).await.unwrap_err();
+    assert!(
+        matches!(error, DbError::NoteNotFound { id: 999 }),
+        "{error}"
+    );
+
+    // Version 2 stores sizes as integers and migrates older notes
+    let migrate = r#"
+        migrate = coroutine.create(function(args)
+          local id = coroutine.yield("GetId")
+          local size = coroutine.yield({ GetAttribute = { id = id, key = "size" } })
+          coroutine.yield({ SetAttribute = { id = id, key = "size",
+            value = { Integer = tonumber(size.String) } } })
+          return { Result = args.from .. "->" .. args.to }
+        end)
+    "#;
+    edit_code(
+        &mut conn,
+        &name,
+        caps,
+        &(script("two") + migrate),
+        r#"[{ "name": "size", "value_type": "Integer" }]"#,
+        "done",
+        None,
+        false,
+        "alice",
+        "Integer sizes",
+    )
+    .await
+    .unwrap();
+
+    // The pinned note keeps running the first version
+    let code = get_code(&mut conn, note).await.unwrap().unwrap();
+    assert_eq!((code.version, code.script), (1, script("one")));
+    let outdated = get_outdated_notes(&mut conn, &name).await.unwrap();
+    assert_eq!(outdated.len(), 1);
+
+    assert_eq!(upgrade_note(&mut conn, note).await.unwrap(), 2);
+    let size = get_attribute(&mut conn, note, "size").await.unwrap();
+    assert_eq!(size, Some(AttributeValue::Integer(3)));
+    assert_eq!(get_pinned_version(&mut conn, note).await.unwrap(), Some(2));
+    assert!(get_outdated_notes(&mut conn, &name)
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(upgrade_note(&mut conn, note).await.is_err());
+}
@@ -1,10 +1,16 @@
 -- Sample codes
 INSERT INTO codes (name, capabilities, script) VALUES
   ('simple_done', '["SysLog", { "GetAttribute": "Own" } , { "SetAttribute": "Own" } ]', '-- placeholder');
+INSERT INTO code_versions
+  (code_name, version, author, message, capabilities, script,
+   attribute_schema, completion_attribute)
+SELECT name, 1, 'admin', 'Created', capabilities, script, attribute_schema,
+       completion_attribute
+FROM codes;
 
--- Root notes
-INSERT INTO notes (title, description, code_name) VALUES
-  ('First Note', 'First note description', 'simple_done');
+-- Children of the root note
+INSERT INTO notes (parent_id, title, description, code_name) VALUES
+  (0, 'First Note', 'First note description', 'simple_done');
 
 -- Sub-notes
 INSERT INTO notes (parent_id, title, description, code_name) VALUES
@@ -0,0 +1,243 @@
+mod common;
+
+use backend::db_manage::code_versions::rollback_code;
+use backend::db_manage::codes::{
+    create_code, edit_code, get_code_by_name, run,
+};
+use backend::db_manage::dependencies::{get_dependency_tree, DependencyNode};
+use backend::db_manage::errors::DbError;
+use backend::db_manage::validation::check_code;
+use backend::db_manage::{create_note, migrate_connection};
+use rocket::tokio;
+use serde_json::Value as JsonValue;
+use sqlx::{Connection, SqliteConnection};
+
+const BASE: &str = r#"
+loads = (loads or 0) + 1
+function suffix(s) return s .. "!" end
+"#;
+
+const STRINGS: &str = r#"
+local base = coroutine.yield({ Import = "base" })
+local M = {}
+function M.shout(s) return base.suffix(string.upper(s)) end
+return M
+"#;
+
+const MAIN: &str = r#"
+forms = coroutine.create(function() return {} end)
+
+run = coroutine.create(function()
+  local strings = coroutine.yield({ Import = "strings" })
+  local again = coroutine.yield({ Import = "strings" })
+  local base = coroutine.yield({ Import = "base" })
+  assert(rawequal(strings, again), "imported twice")
+  assert(shout == nil, "library leaked globals")
+  assert(suffix ~= nil, "library returning nothing shares its globals")
+  return strings.shout("hi") .. " " .. base.loads
+end)
+
+plain = coroutine.create(function()
+  return coroutine.yield({ Import = "other" })
+end)
+"#;
+
+async fn save(
+    conn: &mut SqliteConnection,
+    name: &str,
+    script: &str,
+    library: bool,
+) -> Result<String, DbError> {
+    create_code(
+        conn,
+        name.into(),
+        "[]".into(),
+        script.into(),
+        "[]".into(),
+        "done".into(),
+        None,
+        library,
+        "alice",
+    )
+    .await
+}
+
+async fn run_main(
+    conn: &mut SqliteConnection,
+    entry_point: &str,
+) -> Result<String, DbError> {
+    let code = get_code_by_name(conn, "main").await.unwrap().unwrap();
+    run(conn, code, entry_point, 1, JsonValue::Null).await
+}
+
+#[tokio::test]
+async fn test_modules() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    // Libraries need no forms coroutine
+    assert!(check_code("[]", STRINGS, "[]", true).is_empty());
+    assert!(!check_code("[]", STRINGS, "[]", false).is_empty());
+    save(&mut conn, "base", BASE, true).await.unwrap();
+    save(&mut conn, "strings", STRINGS, true).await.unwrap();
+    save(&mut conn, "main", MAIN, false).await.unwrap();
+    save(
+        &mut conn,
+        "other",
+        "forms = coroutine.create(function() end)",
+        false,
+    )
+    .await
+    .unwrap();
+
+    // Exports come back to the importer, each library loads once
+    assert_eq!(run_main(&mut conn, "run").await.unwrap(), "HI! 1");
+    let error = run_main(&mut conn, "plain").await.unwrap_err();
+    assert!(matches!(error, DbError::NotALibrary { .. }), "{error}");
+
+    let tree = get_dependency_tree(&mut conn, "main").await.unwrap();
+    assert_eq!(tree.imports.len(), 3);
+    assert_eq!(tree.imports[2].name, "strings");
+    assert_eq!(tree.imports[2].imports[0].name, "base");
+    assert_eq!(tree.imports[1].library, Some(false));
+
+    // Notes cannot use libraries
+    let error =
+        create_note(&mut conn, 1, "n".into(), "".into(), Some("base".into()))
+            .await
+            .unwrap_err();
+    assert!(
+        matches!(error, DbError::LibraryUsedByNote { .. }),
+        "{error}"
+    );
+    create_note(&mut conn, 1, "n".into(), "".into(), Some("main".into()))
+        .await
+        .unwrap();
+    let error = edit_code(
+        &mut conn, "main", "[]", MAIN, "[]", "done", None, true, "bob", "",
+    )
+    .await
+    .unwrap_err();
+    assert!(
+        matches!(error, DbError::LibraryUsedByNote { .. }),
+        "{error}"
+    );
+
+    // Imported codes stay libraries
+    let with_forms =
+        format!("{BASE}\nforms = coroutine.create(function() end)");
+    let error = edit_code(
+        &mut conn,
+        "base",
+        "[]",
+        &with_forms,
+        "[]",
+        "done",
+        None,
+        false,
+        "bob",
+        "",
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(error, DbError::LibraryImported { .. }), "{error}");
+
+    // Cycles are refused when saving, and stopped when running
+    let cyclic = format!("{BASE}\ncoroutine.yield({{ Import = 'strings' }})");
+    let error = edit_code(
+        &mut conn, "base", "[]", &cyclic, "[]", "done", None, true, "bob", "",
+    )
+    .await
+    .unwrap_err();
+    let DbError::ImportCycle { cycle } = error else {
+        panic!("{error}");
+    };
+    assert_eq!(cycle, ["base", "strings", "base"]);
+    sqlx::query("UPDATE codes SET script = ? WHERE name = 'base'")
+        .bind(&cyclic)
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+    let error = run_main(&mut conn, "run").await.unwrap_err();
+    let DbError::ImportCycle { cycle } = error else {
+        panic!("{error}");
+    };
+    assert_eq!(cycle, ["strings", "base", "strings"]);
+    let tree = get_dependency_tree(&mut conn, "strings").await.unwrap();
+    assert!(tree.imports[0].imports[0].cycle);
+}
+
+#[tokio::test]
+async fn test_imported_codes_become_libraries() {
+    let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+    migrate_connection(&mut conn, false).await.unwrap();
+    // Back to the schema before libraries
+    sqlx::raw_sql(
+        r#"
+        ALTER TABLE codes DROP COLUMN library;
+        UPDATE meta SET value = '13' WHERE key = 'schema_version';
+        INSERT INTO codes (name, capabilities, script, attribute_schema)
+        VALUES ('logger', '[]', 'function log(s) end', '[]'),
+               ('app', '[]', 'coroutine.yield({ Import = "logger" })', '[]');
+        "#,
+    )
+    .execute(&mut conn)
+    .await
+    .unwrap();
+    migrate_connection(&mut conn, false).await.unwrap();
+    for (name, library) in [("logger", true), ("app", false)] {
+        let code = get_code_by_name(&mut conn, name).await.unwrap().unwrap();
+        assert_eq!(code.library, library, "{name}");
+    }
+}
+
+#[tokio::test]
+async fn test_rollback_checks_cycles() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    let importing =
+        |name: &str| format!("coroutine.yield({{ Import = '{name}' }})");
+    save(&mut conn, "b", "return {}", true).await.unwrap();
+    save(&mut conn, "a", &importing("b"), true).await.unwrap();
+    for (name, script) in
+        [("a", "return {}".to_string()), ("b", importing("a"))]
+    {
+        edit_code(
+            &mut conn, name, "[]", &script, "[]", "done", None, true, "bob", "",
+        )
+        .await
+        .unwrap();
+    }
+    // Version 1 of a imports b, which now imports a
+    let error = rollback_code(&mut conn, "a", 1, "bob").await.unwrap_err();
+    assert!(matches!(error, DbError::ImportCycle { .. }), "{error}");
+}
+
+#[tokio::test]
+async fn test_dependency_tree_lists_imports_once() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    // Each level imports both libraries of the level below
+    save(&mut conn, "l0a", "return {}", true).await.unwrap();
+    save(&mut conn, "l0b", "return {}", true).await.unwrap();
+    for level in 1..12 {
+        let script = format!(
+            "coroutine.yield({{ Import = 'l{0}a' }})\n\
+             coroutine.yield({{ Import = 'l{0}b' }})",
+            level - 1
+        );
+        for side in ["a", "b"] {
+            save(&mut conn, &format!("l{level}{side}"), &script, true)
+                .await
+                .unwrap();
+        }
+    }
+    fn count(node: &DependencyNode) -> usize {
+        1 + node.imports.iter().map(count).sum::<usize>()
+    }
+    let tree = get_dependency_tree(&mut conn, "l11a").await.unwrap();
+    // Below the root, each level has its two libraries, and the second
+    // one lists the two below it without their imports
+    assert_eq!(count(&tree), 1 + 4 * 10 + 2);
+    assert!(!tree.imports[1].repeated);
+    assert!(tree.imports[1].imports[0].repeated);
+}
@@ -0,0 +1,114 @@
+mod common;
+
+use backend::db_manage::attributes::{get_attribute, AttributeValue};
+use backend::db_manage::notes::{
+    copy_note, delete_note, move_note, reorder_note,
+};
+use backend::db_manage::progress::Progress;
+use backend::db_manage::{
+    create_note, get_child_notes, get_note, Note, ROOT_NOTE_ID,
+};
+use backend::frontend::view::{render_notes_grid, ListOptions};
+use rocket::tokio;
+
+#[tokio::test]
+async fn test_move_refuses_cycles() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    let grandchild = create_note(&mut conn, 2, "Deep".into(), "".into(), None)
+        .await
+        .unwrap();
+    assert!(move_note(&mut conn, 1, grandchild).await.is_err());
+    assert!(move_note(&mut conn, 1, 1).await.is_err());
+
+    move_note(&mut conn, grandchild, ROOT_NOTE_ID)
+        .await
+        .unwrap();
+    move_note(&mut conn, 1, grandchild).await.unwrap();
+    let note = get_note(&mut conn, 1).await.unwrap().unwrap();
+    assert_eq!(note.parent_id, Some(grandchild));
+}
+
+#[tokio::test]
+async fn test_reorder_siblings() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    for title in ["b", "c"] {
+        create_note(&mut conn, 1, title.into(), "".into(), None)
+            .await
+            .unwrap();
+    }
+    let titles = |notes: Vec<backend::db_manage::Note>| -> Vec<String> {
+        notes.into_iter().map(|n| n.title).collect()
+    };
+    let children = get_child_notes(&mut conn, 1).await.unwrap();
+    let last = children.last().unwrap().id;
+    assert_eq!(titles(children), ["Sub note of one", "b", "c"]);
+
+    reorder_note(&mut conn, last, 0).await.unwrap();
+    reorder_note(&mut conn, 2, 99).await.unwrap();
+    let children = get_child_notes(&mut conn, 1).await.unwrap();
+    assert_eq!(titles(children), ["c", "b", "Sub note of one"]);
+}
+
+#[tokio::test]
+async fn test_copy_subtree_under_itself() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    let copy = copy_note(&mut conn, 1, 2, false).await.unwrap();
+
+    let note = get_note(&mut conn, copy).await.unwrap().unwrap();
+    assert_eq!(note.parent_id, Some(2));
+    assert_eq!(note.title, "First Note");
+    assert_eq!(note.code_name.as_deref(), Some("simple_done"));
+    let tag = get_attribute(&mut conn, copy, "tag1").await.unwrap();
+    assert_eq!(tag, Some(AttributeValue::String("Value of tag 1".into())));
+
+    // The copy holds a copy of the subnote, but not of itself
+    let children = get_child_notes(&mut conn, copy).await.unwrap();
+    assert_eq!(children.len(), 1);
+    assert_eq!(children[0].title, "Sub note of one");
+    assert!(get_child_notes(&mut conn, children[0].id)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_root_note_is_permanent() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    let root = get_note(&mut conn, ROOT_NOTE_ID).await.unwrap().unwrap();
+    assert!(root.parent_id.is_none());
+    let first = get_note(&mut conn, 1).await.unwrap().unwrap();
+    assert_eq!(first.parent_id, Some(ROOT_NOTE_ID));
+
+    assert!(delete_note(&mut conn, ROOT_NOTE_ID).await.is_err());
+    assert!(move_note(&mut conn, ROOT_NOTE_ID, 1).await.is_err());
+    assert!(get_note(&mut conn, ROOT_NOTE_ID).await.unwrap().is_some());
+}
+
+#[test]
+fn test_reorder_skips_hidden_notes() {
+    let card = |id: i64, done: bool| {
+        let note = Note {
+            id,
+            parent_id: Some(1),
+            title: format!("note {id}"),
+            description: "".into(),
+            code_name: None,
+        };
+        (note, done, Progress::default())
+    };
+    let notes = [card(10, false), card(11, true), card(12, false)];
+    let options = ListOptions {
+        hide_done: true,
+        by_priority: false,
+    };
+    let html = render_notes_grid(&notes, options).into_string();
+    assert!(!html.contains("note 11"));
+    // The arrows swap the two visible notes, past the hidden one
+    assert!(html.contains(r#"name="position" value="2""#));
+    assert!(html.contains(r#"name="position" value="0""#));
+    assert!(!html.contains(r#"name="position" value="1""#));
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use backend::db_manage::attributes::{set_attribute, AttributeValue};
+use backend::db_manage::codes::{run, Code};
+use backend::db_manage::create_note;
+use backend::db_manage::progress::{
+    get_children_progress, get_progress, Progress,
+};
+use rocket::tokio;
+
+#[tokio::test]
+async fn test_progress_counts_leaves() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    let task = create_note(&mut conn, 2, "Task".into(), "".into(), None)
+        .await
+        .unwrap();
+    create_note(&mut conn, 1, "Other".into(), "".into(), None)
+        .await
+        .unwrap();
+    let done = AttributeValue::Date(common::date(1));
+    set_attribute(&mut conn, task, "done", &done).await.unwrap();
+    let due = AttributeValue::Date(common::date(20));
+    set_attribute(&mut conn, task, "due", &due).await.unwrap();
+    let due = AttributeValue::Date(common::date(9));
+    set_attribute(&mut conn, 1, "due", &due).await.unwrap();
+
+    // Note 2 is not a leaf anymore, so only Task and Other count
+    let progress = get_progress(&mut conn, 1).await.unwrap();
+    assert_eq!(
+        progress,
+        Progress {
+            open: 1,
+            done: 1,
+            latest_due: Some(common::date(20)),
+        }
+    );
+    assert_eq!(progress.percent(), Some(50));
+    let leaf = get_progress(&mut conn, task).await.unwrap();
+    assert_eq!(leaf.percent(), None);
+
+    let children = get_children_progress(&mut conn, 1).await.unwrap();
+    assert!(!children.is_empty());
+    for (id, progress) in children {
+        assert_eq!(progress, get_progress(&mut conn, id).await.unwrap());
+    }
+}
+
+#[tokio::test]
+async fn test_progress_from_lua() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    let code = Code {
+        name: "progress".to_string(),
+        capabilities: r#"[{ "GetProgress": "Own" }]"#.to_string(),
+        script: r#"
+            summary = coroutine.create(function()
+              local id = coroutine.yield("GetId")
+              local p = coroutine.yield({ GetProgress = { id = id } })
+              return { Result = p.done .. "/" .. (p.open + p.done) }
+            end)
+        "#
+        .to_string(),
+        attribute_schema: "[]".to_string(),
+        completion_attribute: "done".to_string(),
+        board_action: None,
+        board_action_key: None,
+        version: 1,
+        library: false,
+    };
+    let summary: String =
+        run(&mut conn, code, "summary", 1, serde_json::Value::Null)
+            .await
+            .unwrap();
+    assert_eq!(summary, "0/1");
+}
@@ -0,0 +1,44 @@
+mod common;
+
+use backend::db_manage::attributes::{
+    delete_attribute, get_attribute, set_attribute, AttributeValue,
+};
+use backend::db_manage::get_note;
+use backend::db_manage::notes::update_note;
+use backend::db_manage::revisions::{get_revisions, revert_to_revision};
+use rocket::tokio;
+
+#[tokio::test]
+async fn test_revert_restores_fields_and_attributes() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    let one = AttributeValue::Integer(1);
+    let two = AttributeValue::Integer(2);
+    set_attribute(&mut conn, 1, "count", &one).await.unwrap();
+    set_attribute(&mut conn, 1, "count", &two).await.unwrap();
+    update_note(
+        &mut conn,
+        1,
+        "Renamed".to_string(),
+        "First note description".to_string(),
+        Some("simple_done".to_string()),
+    )
+    .await
+    .unwrap();
+    delete_attribute(&mut conn, 1, "tag1").await.unwrap();
+
+    // newest first: tag1 removal, title, count 1 -> 2, count added
+    let revisions = get_revisions(&mut conn, 1).await.unwrap();
+    assert_eq!(revisions.len(), 4);
+    assert_eq!(revisions[1].before_text().as_deref(), Some("First Note"));
+
+    revert_to_revision(&mut conn, 1, revisions[2].id)
+        .await
+        .unwrap();
+    let note = get_note(&mut conn, 1).await.unwrap().unwrap();
+    assert_eq!(note.title, "First Note");
+    let count = get_attribute(&mut conn, 1, "count").await.unwrap();
+    assert_eq!(count, Some(one));
+    let tag = get_attribute(&mut conn, 1, "tag1").await.unwrap();
+    assert_eq!(tag, Some(AttributeValue::String("Value of tag 1".into())));
+}
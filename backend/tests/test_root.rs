@@ -1,6 +1,7 @@
 mod common;
+use backend::db_manage::ROOT_NOTE_ID;
 use backend::frontend::view::ViewState;
-use rocket::{http::ContentType, http::Status, tokio};
+use rocket::{http::ContentType, tokio};
 
 use common::LOCALHOST;
 
@@ -20,15 +21,18 @@ async fn test_root() {
         .expect("Could not get contents");
     let view: ViewState = serde_json::from_str(contents.as_str())
         .expect("Could not parse contents");
-    let root = match view {
-        ViewState::Root(r) => r,
+    let (root, children) = match view {
+        ViewState::Note(note, _, _, children, ..) => (note, children),
         _ => {
-            panic!("View was not of type root");
+            panic!("View was not of type note");
         }
     };
+    assert_eq!(root.id, ROOT_NOTE_ID);
+    assert!(root.parent_id.is_none());
     assert!(
-        root.into_iter()
-            .any(|note| note.title == "Main Project".to_string()),
+        children
+            .into_iter()
+            .any(|(note, _, _)| note.title == "Main Project"),
         "No note named Main Project"
     );
 }
@@ -0,0 +1,238 @@
+mod common;
+
+use backend::db_manage::codes::{create_code, get_code_by_name, run};
+use backend::db_manage::errors::DbError;
+use backend::db_manage::{create_note, get_note, ROOT_NOTE_ID};
+use rocket::tokio;
+use serde_json::Value as JsonValue;
+
+const SCRIPT: &str = r#"
+forms = coroutine.create(function() return {} end)
+
+plain = coroutine.create(function()
+  local id = coroutine.yield("GetId")
+  return "plain " .. id
+end)
+
+wrapped = coroutine.create(function() return { Result = "wrapped" } end)
+
+yielded = coroutine.create(function()
+  coroutine.yield({ Result = "yielded" })
+  return "unreachable"
+end)
+
+nothing = coroutine.create(function() end)
+
+failing = coroutine.create(function()
+  error("broken")
+end)
+
+nonsense = coroutine.create(function()
+  coroutine.yield({ Fly = "away" })
+end)
+
+logging = coroutine.create(function()
+  coroutine.yield({ SysLog = "hello" })
+  return "logged"
+end)
+
+not_a_coroutine = 3
+"#;
+
+#[tokio::test]
+async fn test_run_outcomes() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    create_code(
+        &mut conn,
+        "runner".into(),
+        "[]".into(),
+        SCRIPT.into(),
+        "[]".into(),
+        "done".into(),
+        None,
+        false,
+        "alice",
+    )
+    .await
+    .unwrap();
+    let id =
+        create_note(&mut conn, 1, "n".into(), "".into(), Some("runner".into()))
+            .await
+            .unwrap();
+    let code = get_code_by_name(&mut conn, "runner")
+        .await
+        .unwrap()
+        .unwrap();
+    let empty: JsonValue = "Empty".into();
+
+    // Getting the id and returning need no capability
+    let result: String =
+        run(&mut conn, code.clone(), "plain", id, empty.clone())
+            .await
+            .unwrap();
+    assert_eq!(result, format!("plain {id}"));
+    let result: String =
+        run(&mut conn, code.clone(), "wrapped", id, empty.clone())
+            .await
+            .unwrap();
+    assert_eq!(result, "wrapped");
+    let result: String =
+        run(&mut conn, code.clone(), "yielded", id, empty.clone())
+            .await
+            .unwrap();
+    assert_eq!(result, "yielded");
+
+    let error =
+        run::<String>(&mut conn, code.clone(), "nothing", id, empty.clone())
+            .await
+            .unwrap_err();
+    assert!(matches!(error, DbError::InvalidResult { .. }), "{error}");
+    let result: Option<String> =
+        run(&mut conn, code.clone(), "nothing", id, empty.clone())
+            .await
+            .unwrap();
+    assert_eq!(result, None);
+
+    let error =
+        run::<String>(&mut conn, code.clone(), "failing", id, empty.clone())
+            .await
+            .unwrap_err();
+    match &error {
+        DbError::ScriptError {
+            chunk,
+            line,
+            message,
+            traceback,
+        } => {
+            assert_eq!(chunk.as_deref(), Some("runner"));
+            assert_eq!(*line, Some(19));
+            assert_eq!(message, "broken");
+            assert!(traceback.is_some());
+        }
+        _ => panic!("{error}"),
+    }
+    assert!(error.to_string().contains("runner:19: broken"));
+
+    for name in ["missing", "not_a_coroutine"] {
+        let error =
+            run::<String>(&mut conn, code.clone(), name, id, empty.clone())
+                .await
+                .unwrap_err();
+        assert!(matches!(error, DbError::NoEntryPoint { .. }), "{error}");
+    }
+
+    let error =
+        run::<String>(&mut conn, code.clone(), "nonsense", id, empty.clone())
+            .await
+            .unwrap_err();
+    assert!(matches!(error, DbError::InvalidCommand { .. }), "{error}");
+
+    let error =
+        run::<String>(&mut conn, code.clone(), "logging", id, empty.clone())
+            .await
+            .unwrap_err();
+    assert!(matches!(error, DbError::Unauthorized { .. }), "{error}");
+}
+
+const COPIER: &str = r#"
+forms = coroutine.create(function() return {} end)
+
+copy = coroutine.create(function(source)
+  local id = coroutine.yield("GetId")
+  local target = type(source) == "number" and source or id
+  return coroutine.yield({ CopyNote = { id = target, parent_id = id } })
+end)
+"#;
+
+#[tokio::test]
+async fn test_copy_range() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    create_code(
+        &mut conn,
+        "copier".into(),
+        r#"[{"CopyNote": "Own"}]"#.into(),
+        COPIER.into(),
+        "[]".into(),
+        "done".into(),
+        None,
+        false,
+        "alice",
+    )
+    .await
+    .unwrap();
+    let id =
+        create_note(&mut conn, 1, "n".into(), "".into(), Some("copier".into()))
+            .await
+            .unwrap();
+    let secret = create_note(&mut conn, 1, "secret".into(), "".into(), None)
+        .await
+        .unwrap();
+    let code = get_code_by_name(&mut conn, "copier")
+        .await
+        .unwrap()
+        .unwrap();
+
+    // Copying the note itself stays in range
+    let copy: i64 = run(&mut conn, code.clone(), "copy", id, JsonValue::Null)
+        .await
+        .unwrap();
+    assert!(copy > secret);
+
+    // A note outside the range cannot be copied into it
+    let error = run::<i64>(&mut conn, code, "copy", id, secret.into())
+        .await
+        .unwrap_err();
+    assert!(matches!(error, DbError::Unauthorized { .. }), "{error}");
+}
+
+const CREATOR: &str = r#"
+forms = coroutine.create(function() return {} end)
+
+make = coroutine.create(function()
+  return coroutine.yield({ CreateChild = { parent_id = nil, title = "top",
+    description = "" } })
+end)
+"#;
+
+#[tokio::test]
+async fn test_nil_parent_is_root() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    create_code(
+        &mut conn,
+        "creator".into(),
+        r#"[{"CreateChild": "Own"}]"#.into(),
+        CREATOR.into(),
+        "[]".into(),
+        "done".into(),
+        None,
+        false,
+        "alice",
+    )
+    .await
+    .unwrap();
+    let code = get_code_by_name(&mut conn, "creator")
+        .await
+        .unwrap()
+        .unwrap();
+
+    let child: i64 = run(
+        &mut conn,
+        code.clone(),
+        "make",
+        ROOT_NOTE_ID,
+        JsonValue::Null,
+    )
+    .await
+    .unwrap();
+    let child = get_note(&mut conn, child).await.unwrap().unwrap();
+    assert_eq!(child.parent_id, Some(ROOT_NOTE_ID));
+
+    // Other notes only reach their own children
+    let error = run::<i64>(&mut conn, code, "make", 1, JsonValue::Null)
+        .await
+        .unwrap_err();
+    assert!(matches!(error, DbError::Unauthorized { .. }), "{error}");
+}
@@ -0,0 +1,93 @@
+mod common;
+
+use backend::api::codes::Value;
+use backend::db_manage::attributes::get_attribute;
+use backend::db_manage::codes::create_code;
+use backend::db_manage::create_note;
+use backend::db_manage::simulation::simulate;
+use rocket::tokio;
+
+const SCRIPT: &str = r#"
+forms = coroutine.create(function() return { Result = {} } end)
+
+go = coroutine.create(function()
+  local id = coroutine.yield("GetId")
+  coroutine.yield({ SetAttribute = { id = id, key = "k",
+                                     value = { String = "new" } } })
+  coroutine.yield({ CreateChild = { parent_id = id, title = "child",
+                                    description = "" } })
+  return { Result = "went" }
+end)
+
+fail = coroutine.create(function()
+  local id = coroutine.yield("GetId")
+  coroutine.yield({ SetAttribute = { id = id, key = "k",
+                                     value = { String = "new" } } })
+  coroutine.yield({ SysLog = "not granted" })
+  return { Result = "unreachable" }
+end)
+"#;
+
+async fn count(conn: &mut sqlx::SqliteConnection, table: &str) -> i64 {
+    sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {table}"))
+        .fetch_one(conn)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_simulation_is_rolled_back() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    create_code(
+        &mut conn,
+        "sim".into(),
+        r#"[{"SetAttribute": "Own"}, {"CreateChild": "Own"}]"#.into(),
+        SCRIPT.into(),
+        "[]".into(),
+        "done".into(),
+        None,
+        false,
+        "alice",
+    )
+    .await
+    .unwrap();
+    let id =
+        create_note(&mut conn, 1, "n".into(), "".into(), Some("sim".into()))
+            .await
+            .unwrap();
+    let (notes, logs) = (
+        count(&mut conn, "notes").await,
+        count(&mut conn, "logs").await,
+    );
+
+    let simulation =
+        simulate(&mut conn, id, &common::form("go"), &Value::Empty)
+            .await
+            .unwrap();
+    assert_eq!(simulation.outcome, Ok("went".to_string()));
+    assert_eq!(simulation.trace.steps.len(), 3);
+    assert_eq!(simulation.trace.steps[0].response, id);
+    assert_eq!(simulation.attributes.len(), 1);
+    assert_eq!(simulation.attributes[0].key, "k");
+    assert_eq!(simulation.attributes[0].before, None);
+    assert_eq!(simulation.attributes[0].after.as_deref(), Some("new"));
+    assert_eq!(simulation.notes.len(), 1);
+    assert_eq!(simulation.notes[0].parent_id, Some(id));
+    // The child's creation and the execution itself
+    assert_eq!(simulation.logs.len(), 2);
+
+    assert!(get_attribute(&mut conn, id, "k").await.unwrap().is_none());
+    assert_eq!(count(&mut conn, "notes").await, notes);
+    assert_eq!(count(&mut conn, "logs").await, logs);
+
+    // A failing action shows what it did before failing
+    let simulation =
+        simulate(&mut conn, id, &common::form("fail"), &Value::Empty)
+            .await
+            .unwrap();
+    assert!(simulation.outcome.is_err());
+    assert_eq!(simulation.trace.steps.len(), 2);
+    assert_eq!(simulation.attributes.len(), 1);
+    assert!(get_attribute(&mut conn, id, "k").await.unwrap().is_none());
+}
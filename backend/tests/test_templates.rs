@@ -0,0 +1,66 @@
+mod common;
+
+use std::collections::HashMap;
+
+use backend::db_manage::attributes::{
+    get_attribute, set_attribute, AttributeValue,
+};
+use backend::db_manage::errors::DbError;
+use backend::db_manage::notes::update_note;
+use backend::db_manage::templates::{
+    fill_placeholders, instantiate_template, set_template_params,
+};
+use backend::db_manage::{get_child_notes, get_note, ROOT_NOTE_ID};
+use chrono::Local;
+use rocket::tokio;
+
+#[test]
+fn test_fill_placeholders() {
+    let values = HashMap::from([("name".to_string(), "Ada".to_string())]);
+    let filled = fill_placeholders("Hi {{name}}, {{ name }}!", &values);
+    assert_eq!(filled, "Hi Ada, Ada!");
+    assert_eq!(fill_placeholders("{{other}}", &values), "{{other}}");
+    assert_eq!(fill_placeholders("{{name", &values), "{{name");
+    let code = fill_placeholders("if {{x}} {{{name}}}}", &values);
+    assert_eq!(code, "if {{x}} {Ada}}");
+}
+
+#[tokio::test]
+async fn test_instantiate_template() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    update_note(
+        &mut conn,
+        1,
+        "Report for {{journal}}".to_string(),
+        "Started {{today}}".to_string(),
+        Some("simple_done".to_string()),
+    )
+    .await
+    .unwrap();
+    let tag = AttributeValue::String("{{journal}}".to_string());
+    set_attribute(&mut conn, 1, "tag1", &tag).await.unwrap();
+
+    let values = HashMap::from([("journal".to_string(), "JFP".to_string())]);
+    let result =
+        instantiate_template(&mut conn, 1, ROOT_NOTE_ID, values.clone()).await;
+    assert!(matches!(result, Err(DbError::InvalidTemplate { .. })));
+
+    set_template_params(&mut conn, 1, Some(vec!["journal".to_string()]))
+        .await
+        .unwrap();
+    let id = instantiate_template(&mut conn, 1, ROOT_NOTE_ID, values)
+        .await
+        .unwrap();
+    let note = get_note(&mut conn, id).await.unwrap().unwrap();
+    let today = Local::now().date_naive().format("%Y-%m-%d").to_string();
+    assert_eq!(note.title, "Report for JFP");
+    assert_eq!(note.description, format!("Started {today}"));
+    let tag = get_attribute(&mut conn, id, "tag1").await.unwrap();
+    assert_eq!(tag, Some(AttributeValue::String("JFP".to_string())));
+    assert_eq!(get_child_notes(&mut conn, id).await.unwrap().len(), 1);
+
+    let result =
+        instantiate_template(&mut conn, 1, ROOT_NOTE_ID, HashMap::new()).await;
+    assert!(matches!(result, Err(DbError::InvalidTemplate { .. })));
+}
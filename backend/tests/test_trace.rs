@@ -0,0 +1,119 @@
+mod common;
+
+use backend::api::codes::Value;
+use backend::db_manage::codes::{create_code, execute_traced};
+use backend::db_manage::create_note;
+use backend::db_manage::logs::{create_failure_log, get_logs_from_note};
+use backend::db_manage::simulation::trace_code;
+use backend::db_manage::trace::Trace;
+use mlua::Lua;
+use rocket::tokio;
+
+const SCRIPT: &str = r#"
+forms = coroutine.create(function() return { Result = {} } end)
+
+local function check(x)
+  if x > 1 then error("too big") end
+end
+
+go = coroutine.create(function()
+  local id = coroutine.yield("GetId")
+  coroutine.yield({ SysLog = "going" })
+  return { Result = "gone" }
+end)
+
+crash = coroutine.create(function()
+  coroutine.yield({ SysLog = "crashing" })
+  check(2)
+  return { Result = "unreachable" }
+end)
+
+spin = coroutine.create(function()
+  while true do end
+end)
+"#;
+
+#[tokio::test]
+async fn test_traces() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    create_code(
+        &mut conn,
+        "traced".into(),
+        r#"["SysLog"]"#.into(),
+        SCRIPT.into(),
+        "[]".into(),
+        "done".into(),
+        None,
+        false,
+        "alice",
+    )
+    .await
+    .unwrap();
+    let id =
+        create_note(&mut conn, 1, "n".into(), "".into(), Some("traced".into()))
+            .await
+            .unwrap();
+
+    // A successful run leaves its trace in the execution log
+    let mut trace = Trace::default();
+    execute_traced(
+        &mut conn,
+        &Lua::new(),
+        id,
+        &common::form("go"),
+        &Value::Empty,
+        &mut trace,
+    )
+    .await
+    .unwrap();
+    assert_eq!(trace.steps.len(), 2);
+    assert_eq!(trace.result, Some("gone".into()));
+    assert!(trace.error.is_none());
+    let logs = get_logs_from_note(&mut conn, id).await.unwrap();
+    let stored =
+        Trace::from_blob(logs.last().unwrap().data.as_ref().unwrap()).unwrap();
+    assert_eq!(stored.entry_point, "go");
+    assert_eq!(stored.steps.len(), 2);
+
+    // Lua errors keep their traceback, located in the named code
+    let mut trace = Trace::default();
+    let result = execute_traced(
+        &mut conn,
+        &Lua::new(),
+        id,
+        &common::form("crash"),
+        &Value::Empty,
+        &mut trace,
+    )
+    .await;
+    assert!(result.is_err());
+    assert_eq!(trace.steps.len(), 1);
+    let error = trace.error.clone().unwrap();
+    assert!(error.message.contains("traced:5: too big"), "{error:?}");
+    assert!(error.traceback.unwrap().contains("check"));
+    create_failure_log(&mut conn, &trace).await.unwrap();
+    let logs = get_logs_from_note(&mut conn, id).await.unwrap();
+    assert_eq!(logs.last().unwrap().kind, "error");
+
+    // Runs from the code page are not saved
+    let count = logs.len();
+    let trace = trace_code(&mut conn, "traced", id, "go", "Empty".into())
+        .await
+        .unwrap();
+    assert_eq!(trace.steps.len(), 2);
+    let trace = trace_code(&mut conn, "traced", id, "nope", "Empty".into())
+        .await
+        .unwrap();
+    assert!(trace.error.is_some());
+    // Runaway scripts are stopped
+    let trace = trace_code(&mut conn, "traced", id, "spin", "Empty".into())
+        .await
+        .unwrap();
+    let error = trace.error.unwrap();
+    assert!(error.message.contains("ran for more than"), "{error:?}");
+    assert_eq!(
+        get_logs_from_note(&mut conn, id).await.unwrap().len(),
+        count
+    );
+}
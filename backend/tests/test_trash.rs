@@ -0,0 +1,76 @@
+mod common;
+
+use backend::api::codes::{Action, Date, FormContainer, FormType, Value};
+use backend::db_manage::board::move_card;
+use backend::db_manage::codes::execute;
+use backend::db_manage::errors::DbError;
+use backend::db_manage::get_note;
+use backend::db_manage::notes::delete_note;
+use backend::db_manage::trash::{
+    get_trash, purge_expired_trash, purge_note, restore_note,
+};
+use chrono::NaiveDate;
+use rocket::tokio;
+
+#[tokio::test]
+async fn test_trash_and_restore_subtree() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    delete_note(&mut conn, 1).await.unwrap();
+    assert!(get_note(&mut conn, 2).await.unwrap().is_none());
+    let trash = get_trash(&mut conn).await.unwrap();
+    assert_eq!(trash.len(), 1);
+    assert_eq!(trash[0].subtree_size, 2);
+
+    // Only the trashed root can be restored
+    assert!(restore_note(&mut conn, 2).await.is_err());
+    restore_note(&mut conn, 1).await.unwrap();
+    assert!(get_note(&mut conn, 2).await.unwrap().is_some());
+    assert!(get_trash(&mut conn).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_purge() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    // Only trashed notes can be purged
+    assert!(purge_note(&mut conn, 1).await.is_err());
+    delete_note(&mut conn, 1).await.unwrap();
+    assert_eq!(purge_expired_trash(&mut conn, 30).await.unwrap(), 0);
+    purge_note(&mut conn, 1).await.unwrap();
+    assert!(purge_note(&mut conn, 1).await.is_err());
+    let remaining: i64 = sqlx::query_scalar(
+        "SELECT (SELECT COUNT(*) FROM notes) + (SELECT COUNT(*) FROM attributes)",
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .unwrap();
+    // Only the root note is left
+    assert_eq!(remaining, 1);
+}
+
+#[tokio::test]
+async fn test_trashed_notes_do_not_run() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    delete_note(&mut conn, 1).await.unwrap();
+    let error = delete_note(&mut conn, 1).await.unwrap_err();
+    assert!(matches!(error, DbError::NoteNotFound { id: 1 }), "{error}");
+
+    let done = FormContainer {
+        title: "Done".into(),
+        label: "done".into(),
+        action: Action {
+            label: "done".into(),
+            title: "Done".into(),
+            form_type: FormType::Date,
+        },
+    };
+    let today = Value::Date(Date(NaiveDate::from_ymd_opt(2025, 1, 1).unwrap()));
+    let error = execute(&mut conn, 2, &done, &today).await.unwrap_err();
+    assert!(matches!(error, DbError::NoteInTrash { id: 2 }), "{error}");
+    let error = move_card(&mut conn, 2, "status", Some("doing"))
+        .await
+        .unwrap_err();
+    assert!(matches!(error, DbError::NoteInTrash { id: 2 }), "{error}");
+}
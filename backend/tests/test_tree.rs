@@ -0,0 +1,75 @@
+mod common;
+
+use backend::db_manage::attributes::{set_attribute, AttributeValue};
+use backend::db_manage::create_note;
+use backend::db_manage::notes::get_child_notes_with_status;
+use backend::db_manage::tree::get_tree;
+use chrono::NaiveDate;
+use rocket::tokio;
+
+#[tokio::test]
+async fn test_tree_order_and_counts() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    let extra = create_note(&mut conn, 1, "Extra".into(), "".into(), None)
+        .await
+        .unwrap();
+    let date =
+        AttributeValue::Date(NaiveDate::from_ymd_opt(2025, 1, 1).unwrap());
+    set_attribute(&mut conn, extra, "done", &date)
+        .await
+        .unwrap();
+
+    let tree = get_tree(&mut conn, None).await.unwrap();
+    let summary: Vec<(i64, i64)> =
+        tree.iter().map(|n| (n.id, n.depth)).collect();
+    assert_eq!(summary, [(0, 0), (1, 1), (2, 2), (extra, 2)]);
+    assert_eq!(tree[1].open_children, 1);
+    assert!(tree[3].done);
+
+    // Filtering keeps the ancestors of matching notes
+    let tree = get_tree(&mut conn, Some("simple_done")).await.unwrap();
+    let ids: Vec<i64> = tree.iter().map(|n| n.id).collect();
+    assert_eq!(ids, [0, 1]);
+}
+
+#[tokio::test]
+async fn test_completion_attribute() {
+    let pool = common::prepare_test_db().await;
+    let mut conn = pool.acquire().await.unwrap();
+    sqlx::query(
+        r#"INSERT INTO codes (name, capabilities, script, completion_attribute)
+           VALUES ('reviewed', '[]', '', 'accepted')"#,
+    )
+    .execute(&mut *conn)
+    .await
+    .unwrap();
+    let id = create_note(
+        &mut conn,
+        1,
+        "Review".into(),
+        "".into(),
+        Some("reviewed".into()),
+    )
+    .await
+    .unwrap();
+    let done_of = async |conn: &mut sqlx::SqliteConnection| {
+        let children =
+            get_child_notes_with_status(conn, 1, false).await.unwrap();
+        children.into_iter().find(|(n, _)| n.id == id).unwrap().1
+    };
+
+    let no = AttributeValue::Bool(false);
+    set_attribute(&mut conn, id, "accepted", &no).await.unwrap();
+    assert!(!done_of(&mut conn).await);
+    // The built-in attribute does not count for this code
+    let date =
+        AttributeValue::Date(NaiveDate::from_ymd_opt(2025, 1, 1).unwrap());
+    set_attribute(&mut conn, id, "done", &date).await.unwrap();
+    assert!(!done_of(&mut conn).await);
+    let yes = AttributeValue::Bool(true);
+    set_attribute(&mut conn, id, "accepted", &yes)
+        .await
+        .unwrap();
+    assert!(done_of(&mut conn).await);
+}